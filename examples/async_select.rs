@@ -0,0 +1,49 @@
+//!
+//! Rust Firebird Client
+//!
+//! Example of select with the async connection
+//!
+//! You need create a database with this table:
+//! create table test (col_a int generated by default as identity, col_b float, col_c varchar(10));
+//!
+//! You can use the insert example to populate
+//! the database ;)
+//!
+
+use futures_util::StreamExt;
+use rsfbclient::{prelude::*, FbError};
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), FbError> {
+    let mut conn = rsfbclient::builder_pure_rust()
+        .host("localhost")
+        .db_name("examples.fdb")
+        .user("SYSDBA")
+        .pass("masterkey")
+        .connect_async()
+        .await?;
+
+    {
+        // `query_stream` for large quantities of rows, will fetch the rows as they are consumed
+        let mut rows = conn
+            .query_stream("select col_a, col_b, col_c from test", ())
+            .await?;
+
+        println!("| col_a | col_b | col_c   |");
+        println!("| ----- | ----- | ------- |");
+        while let Some(row) = rows.next().await {
+            let (col_a, col_b, col_c): (i32, f32, String) = row?;
+
+            println!("| {:^5} | {:^5} | {:7} |", col_a, col_b, col_c);
+        }
+    }
+
+    // `query` for small quantities of rows, will allocate a vector with all rows
+    let rows: Vec<(i32, f32, String)> = conn
+        .query("select col_a, col_b, col_c from test", ())
+        .await?;
+
+    println!("{:?}", rows);
+
+    conn.close().await
+}
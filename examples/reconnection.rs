@@ -1,41 +1,58 @@
 //!
 //! Rust Firebird Client
 //!
-//! Example of simple reconnection
+//! Example of automatic reconnection
 //!
 //! You need create a database named test.fdb:
 //!
 
 #![allow(unused_variables, unused_mut)]
 
-use rsfbclient::prelude::*;
+use rsfbclient::{prelude::*, ReconnectPolicy};
 use std::time::Duration;
 
 fn main() {
-    let builder = {
-        #[cfg(feature = "linking")]
-        let mut builder = rsfbclient::builder_native().with_dyn_link().with_remote();
+    let policy = ReconnectPolicy::default()
+        .max_attempts(5)
+        .initial_backoff(Duration::from_millis(500))
+        .max_backoff(Duration::from_secs(10));
 
-        #[cfg(feature = "dynamic_loading")]
-        let mut builder = rsfbclient::builder_native()
-            .with_dyn_load("./fbclient.lib")
-            .with_remote();
+    #[cfg(feature = "linking")]
+    let mut conn = rsfbclient::builder_native()
+        .with_dyn_link()
+        .with_remote()
+        .host("localhost")
+        .db_name("test.fdb")
+        .user("SYSDBA")
+        .pass("masterkey")
+        .reconnect(policy)
+        .connect()
+        .unwrap();
 
-        #[cfg(feature = "pure_rust")]
-        let mut builder = rsfbclient::builder_pure_rust();
+    #[cfg(feature = "dynamic_loading")]
+    let mut conn = rsfbclient::builder_native()
+        .with_dyn_load("./fbclient.lib")
+        .with_remote()
+        .host("localhost")
+        .db_name("test.fdb")
+        .user("SYSDBA")
+        .pass("masterkey")
+        .reconnect(policy)
+        .connect()
+        .unwrap();
 
-        builder
-            .host("localhost")
-            .db_name("test.fdb")
-            .user("SYSDBA")
-            .pass("masterkey");
-
-        builder
-    };
-
-    let mut conn = builder.connect().unwrap();
+    #[cfg(feature = "pure_rust")]
+    let mut conn = rsfbclient::builder_pure_rust()
+        .host("localhost")
+        .db_name("test.fdb")
+        .user("SYSDBA")
+        .pass("masterkey")
+        .reconnect(policy)
+        .connect()
+        .unwrap();
 
     loop {
+        // If the connection was lost, attaches again before running the query
         match conn.query_first("SELECT rand() FROM RDB$DATABASE", ()) {
             Ok(Some((resp,))) => {
                 let resp: f64 = resp;
@@ -43,14 +60,7 @@ fn main() {
                 println!("Resp: {}", resp);
             }
 
-            Err(e) => {
-                eprintln!("Error: {}", e);
-
-                match builder.connect() {
-                    Ok(new_conn) => conn = new_conn,
-                    Err(e) => eprintln!("Error on reconnect: {}", e),
-                }
-            }
+            Err(e) => eprintln!("Error: {}", e),
 
             _ => panic!("Select returned nothing"),
         }
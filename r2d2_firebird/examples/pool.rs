@@ -28,7 +28,10 @@ fn main() {
     //FirebirdConnectionManager makes use of FirebirdClientFactory, which is implemented
     //by builders
 
-    let manager = FirebirdConnectionManager::new(builder);
+    //The session of the connections can be customized, e.g. with `with_init_sql` or,
+    //on Firebird 4+, `with_session_reset` to avoid leaking the session state between the users
+    let manager =
+        FirebirdConnectionManager::new(builder).with_validation_query("SELECT 1 FROM RDB$DATABASE");
     let pool = Arc::new(r2d2::Pool::builder().max_size(4).build(manager).unwrap());
 
     let mut tasks = vec![];
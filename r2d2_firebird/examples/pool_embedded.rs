@@ -0,0 +1,61 @@
+//!
+//! Rust Firebird Client
+//!
+//! Example of the r2d2 connection pool with an embedded database
+//!
+//! You need the firebird embedded libraries and a database file
+//! at /tmp/examples.fdb. Run with `--features linking`
+//!
+
+use r2d2_firebird::FirebirdConnectionManager;
+use rsfbclient::prelude::*;
+use std::{sync::Arc, thread};
+
+fn main() {
+    let builder = {
+        let mut builder = rsfbclient::builder_native().with_dyn_link().with_embedded();
+
+        builder.db_name("/tmp/examples.fdb").user("SYSDBA");
+
+        builder
+    };
+
+    let manager = FirebirdConnectionManager::new(builder);
+    assert!(manager.is_embedded());
+
+    // All the connections share the embedded engine of this process. Idle
+    // connections are closed, releasing the database file when the pool is not used
+    let pool = Arc::new(manager.pool_builder().max_size(4).build(manager).unwrap());
+
+    let mut tasks = vec![];
+
+    for n in 0..3 {
+        let pool = pool.clone();
+
+        let th = thread::spawn(move || {
+            for _ in 0..5 {
+                match pool.get() {
+                    Ok(mut conn) => {
+                        match conn.query_first("SELECT current_connection FROM RDB$DATABASE", ()) {
+                            Ok(Some(row)) => {
+                                let (attachment,): (i64,) = row;
+                                println!("Thread {}: attachment {}", n, attachment)
+                            }
+
+                            Err(e) => println!("execute query error: {}", e),
+
+                            _ => panic!("Select returned nothing!"),
+                        }
+                    }
+                    // `FbError::DatabaseInUse` if another process locked the file
+                    Err(e) => println!("get connection from pool error: {}", e),
+                }
+            }
+        });
+        tasks.push(th);
+    }
+
+    for th in tasks {
+        let _ = th.join();
+    }
+}
@@ -4,15 +4,37 @@
 //! R2D2 Connection Pool
 //!
 
-use rsfbclient::{Connection, FbError, FirebirdClientFactory, Transaction};
+use rsfbclient::{Connection, FbError, FirebirdClientFactory};
 use rsfbclient_core::FirebirdClientDbOps;
+use std::time::Duration;
+
+mod session;
+use session::{embedded_in_use, SessionOptions};
+
+mod simple;
+pub use simple::SimpleConnectionManager;
+
+/// Time an idle connection to an embedded database is kept open by the `pool_builder`
+pub const EMBEDDED_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
 
 /// A manager for connection pools. Requires the `pool` feature.
+///
+/// Works with remote and embedded connections. The embedded engine opens the
+/// database file directly: in the SuperServer mode the file is locked exclusively
+/// for the process while any connection is open, so other processes (including a
+/// Firebird server) can't access it. In the SuperClassic and Classic modes the file
+/// is shared, as long as all the processes use one of these modes. The mode is set by
+/// the `ServerMode` entry of the `firebird.conf` used by the embedded engine.
+///
+/// The session of the pooled connections can be customized with `with_init_sql`,
+/// `with_session_reset` and `with_validation_query`
 pub struct FirebirdConnectionManager<F>
 where
     F: FirebirdClientFactory,
 {
     client_factory: F,
+
+    session: SessionOptions,
 }
 
 impl<F> FirebirdConnectionManager<F>
@@ -20,30 +42,102 @@ where
     F: FirebirdClientFactory,
 {
     pub fn new(client_factory: F) -> Self {
-        Self { client_factory }
+        Self {
+            client_factory,
+            session: Default::default(),
+        }
+    }
+
+    /// Add a statement to be executed in the new connections, like
+    /// `SET TIME ZONE 'UTC'`, `SET ROLE ...` or a `rdb$set_context` call.
+    ///
+    /// The statements run in the order they were added, and again after each session reset
+    pub fn with_init_sql<S: Into<String>>(mut self, sql: S) -> Self {
+        self.session.init_sql.push(sql.into());
+        self
+    }
+
+    /// Reset the session with `ALTER SESSION RESET` when a connection is checked
+    /// out of the pool, so the context variables, the global temporary tables and the
+    /// session settings don't leak between the users. Requires Firebird 4+.
+    ///
+    /// Any transaction left open is rolled back before the reset, with or without
+    /// this option. Both are done in the validation of the checkout, so they require
+    /// the `test_on_check_out` of the pool, enabled by default. A connection which
+    /// fails the rollback or the reset is discarded by the pool
+    pub fn with_session_reset(mut self) -> Self {
+        self.session.session_reset = true;
+        self
+    }
+
+    /// Query executed to validate the connections checked out of the pool,
+    /// instead of the default ping. It must return at least one row
+    pub fn with_validation_query<S: Into<String>>(mut self, sql: S) -> Self {
+        self.session.validation_query = Some(sql.into());
+        self
+    }
+
+    /// `true` if the connections are made to an embedded database
+    pub fn is_embedded(&self) -> bool {
+        self.client_factory.is_embedded()
+    }
+}
+
+impl<F: FirebirdClientFactory + 'static> FirebirdConnectionManager<F>
+where
+    F: Send + Sync,
+    <F::C as FirebirdClientDbOps>::AttachmentConfig: Send + Sync + Clone,
+{
+    /// Builder of a pool with defaults suited to the connections of this manager.
+    ///
+    /// For embedded databases, no idle connection is kept open after the `EMBEDDED_IDLE_TIMEOUT`,
+    /// so the file lock of the SuperServer mode is released while the pool is not used.
+    /// For remote databases, the `r2d2` defaults are used
+    pub fn pool_builder(&self) -> r2d2::Builder<Self> {
+        let builder = r2d2::Pool::builder();
+
+        if self.is_embedded() {
+            builder
+                .min_idle(Some(0))
+                .idle_timeout(Some(EMBEDDED_IDLE_TIMEOUT))
+        } else {
+            builder
+        }
     }
 }
 
 impl<F: FirebirdClientFactory + 'static> r2d2::ManageConnection for FirebirdConnectionManager<F>
 where
     F: Send + Sync,
-    <F::C as FirebirdClientDbOps>::AttachmentConfig: Send + Sync + Clone, // TODO: Allow embedded database
+    <F::C as FirebirdClientDbOps>::AttachmentConfig: Send + Sync + Clone,
 {
     type Connection = Connection<F::C>;
     type Error = FbError;
 
     fn connect(&self) -> Result<Self::Connection, Self::Error> {
         let cli = self.client_factory.new_instance()?;
-        Connection::open(cli, self.client_factory.get_conn_conf())
+
+        let mut conn = Connection::open(cli, self.client_factory.get_conn_conf()).map_err(|e| {
+            if self.is_embedded() {
+                embedded_in_use(e)
+            } else {
+                e
+            }
+        })?;
+
+        self.session.init(&mut conn)?;
+
+        Ok(conn)
     }
 
     fn is_valid(&self, conn: &mut Self::Connection) -> Result<(), Self::Error> {
-        // If it can start a transaction, we are ok
-        Transaction::new(conn)?;
-        Ok(())
+        // Called when the connection is checked out of the pool
+        self.session.check_out(conn)
     }
 
-    fn has_broken(&self, _conn: &mut Self::Connection) -> bool {
-        false
+    fn has_broken(&self, conn: &mut Self::Connection) -> bool {
+        // Called when the connection is returned to the pool, so no round trip
+        // is made here. The session is cleaned up by the `is_valid`
+        conn.is_broken()
     }
 }
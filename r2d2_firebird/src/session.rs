@@ -0,0 +1,137 @@
+//!
+//! Rust Firebird Client
+//!
+//! Session customization of the pooled connections
+//!
+
+use rsfbclient::{prelude::*, Connection, FbError, Row, SimpleConnection};
+use rsfbclient_core::FirebirdClient;
+
+/// Session options shared by the connection managers
+#[derive(Default)]
+pub(crate) struct SessionOptions {
+    /// Statements executed to set up the session of each connection
+    pub(crate) init_sql: Vec<String>,
+
+    /// Run `ALTER SESSION RESET` when a connection is checked out of the pool
+    pub(crate) session_reset: bool,
+
+    /// Query used by `is_valid`, or `None` to ping the server
+    pub(crate) validation_query: Option<String>,
+}
+
+impl SessionOptions {
+    /// Execute the `init_sql` statements in the connection
+    pub(crate) fn init<C: SessionConnection>(&self, conn: &mut C) -> Result<(), FbError> {
+        if self.init_sql.is_empty() {
+            return Ok(());
+        }
+
+        conn.execute_immediate_all(&self.init_sql)
+    }
+
+    /// Rollback the transactions left open and reset the session, if enabled,
+    /// of a connection checked out of the pool, then validate it.
+    ///
+    /// Done on the checkout, so returning a connection to the pool doesn't wait
+    /// for any round trip
+    pub(crate) fn check_out<C: SessionConnection>(&self, conn: &mut C) -> Result<(), FbError> {
+        conn.rollback_open_transactions()?;
+
+        if self.session_reset {
+            conn.reset_session()?;
+            self.init(conn)?;
+        }
+
+        self.validate(conn)
+    }
+
+    /// Validate the connection
+    fn validate<C: SessionConnection>(&self, conn: &mut C) -> Result<(), FbError> {
+        match &self.validation_query {
+            Some(sql) => {
+                let row: Option<Row> = conn.query_first(sql, ())?;
+
+                match row {
+                    Some(_) => Ok(()),
+                    None => Err("Validation query returned no rows".into()),
+                }
+            }
+            None => conn.ping(),
+        }
+    }
+}
+
+/// Operations needed by the `SessionOptions`, implemented
+/// by the connection types of the managers
+pub(crate) trait SessionConnection: Queryable {
+    /// Execute the statements in a transaction
+    fn execute_immediate_all(&mut self, sqls: &[String]) -> Result<(), FbError>;
+
+    fn reset_session(&mut self) -> Result<(), FbError>;
+
+    fn rollback_open_transactions(&mut self) -> Result<(), FbError>;
+
+    fn ping(&mut self) -> Result<(), FbError>;
+}
+
+impl<C: FirebirdClient> SessionConnection for Connection<C> {
+    fn execute_immediate_all(&mut self, sqls: &[String]) -> Result<(), FbError> {
+        self.with_transaction(|tr| {
+            for sql in sqls {
+                tr.execute_immediate(sql)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn reset_session(&mut self) -> Result<(), FbError> {
+        Connection::reset_session(self)
+    }
+
+    fn rollback_open_transactions(&mut self) -> Result<(), FbError> {
+        Connection::rollback_open_transactions(self)
+    }
+
+    fn ping(&mut self) -> Result<(), FbError> {
+        Connection::ping(self)
+    }
+}
+
+impl SessionConnection for SimpleConnection {
+    fn execute_immediate_all(&mut self, sqls: &[String]) -> Result<(), FbError> {
+        self.with_transaction(|tr| {
+            for sql in sqls {
+                tr.execute_immediate(sql)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn reset_session(&mut self) -> Result<(), FbError> {
+        SimpleConnection::reset_session(self)
+    }
+
+    fn rollback_open_transactions(&mut self) -> Result<(), FbError> {
+        SimpleConnection::rollback_open_transactions(self)
+    }
+
+    fn ping(&mut self) -> Result<(), FbError> {
+        SimpleConnection::ping(self)
+    }
+}
+
+/// Add a hint to the error of an embedded database locked by another process
+pub(crate) fn embedded_in_use(e: FbError) -> FbError {
+    match e {
+        FbError::DatabaseInUse(msg) => FbError::DatabaseInUse(format!(
+            "{}\nThe database file is locked by another process. The embedded engine in \
+             SuperServer mode locks the file exclusively, use `ServerMode = SuperClassic` \
+             in the firebird.conf of all the processes to share it",
+            msg
+        )),
+        e => e,
+    }
+}
@@ -2,6 +2,7 @@
 
 use num_enum::TryFromPrimitive;
 use std::str::FromStr;
+use std::time::Duration;
 
 use crate::*;
 
@@ -38,11 +39,41 @@ pub trait FirebirdClientDbOps: Send {
         config: &Self::AttachmentConfig,
     ) -> Result<Self::DbHandle, FbError>;
 
+    /// Create a new database with the provided configuration, attaching to it.
+    /// Uses the server default page size if `page_size` is `None`
+    fn create_database(
+        &mut self,
+        config: &Self::AttachmentConfig,
+        page_size: Option<u32>,
+        dialect: Dialect,
+    ) -> Result<Self::DbHandle, FbError>;
+
     /// Disconnect from the database
     fn detach_database(&mut self, db_handle: &mut Self::DbHandle) -> Result<(), FbError>;
 
     /// Drop the database
     fn drop_database(&mut self, db_handle: &mut Self::DbHandle) -> Result<(), FbError>;
+
+    /// Create a handle to cancel the operations of the attachment from other threads
+    fn cancel_handle(
+        &mut self,
+        db_handle: &mut Self::DbHandle,
+    ) -> Result<Box<dyn FirebirdClientCancel>, FbError>;
+
+    /// Check if the server is still reachable with a cheap roundtrip
+    fn ping(&mut self, db_handle: &mut Self::DbHandle) -> Result<(), FbError>;
+
+    /// `true` if an I/O or network error was found, meaning the
+    /// attachment is unusable and must be discarded
+    fn is_broken(&self) -> bool;
+}
+
+/// Cancels the operations of an attachment. Can be used from any thread,
+/// while the connection is executing an operation
+pub trait FirebirdClientCancel: Send + Sync {
+    /// Send the cancel request. With `CancelKind::Raise` the operation in
+    /// progress will fail with `FbError::Cancelled`
+    fn cancel_operation(&self, kind: CancelKind) -> Result<(), FbError>;
 }
 
 ///Responsible for actual transaction and statement execution
@@ -50,7 +81,7 @@ pub trait FirebirdClientSqlOps {
     /// A database handle
     type DbHandle: Send;
     /// A transaction handle
-    type TrHandle: Send;
+    type TrHandle: Send + Clone + PartialEq;
     /// A statement handle
     type StmtHandle: Send;
 
@@ -94,6 +125,36 @@ pub trait FirebirdClientSqlOps {
         op: FreeStmtOp,
     ) -> Result<(), FbError>;
 
+    /// Set the name of the statement cursor, allowing it to be referenced
+    /// by `UPDATE / DELETE ... WHERE CURRENT OF <cursor_name>` statements
+    fn set_cursor_name(
+        &mut self,
+        stmt_handle: &mut Self::StmtHandle,
+        cursor_name: &str,
+    ) -> Result<(), FbError>;
+
+    /// Set the number of rows requested from the server in each fetch round trip,
+    /// `0` for the size of the connection configuration.
+    ///
+    /// The default implementation, for the clients that manage the prefetch
+    /// by themselves, ignores it
+    fn set_fetch_size(&mut self, _stmt_handle: &mut Self::StmtHandle, _fetch_size: u32) {}
+
+    /// `true` if the timeout of the `set_statement_timeout` is sent to
+    /// the server with the executions of the statement (Firebird 4+)
+    fn supports_statement_timeout(&self) -> bool {
+        false
+    }
+
+    /// Set the timeout of the next executions of the statement, `None` for the
+    /// timeout of the session. Ignored if not `supports_statement_timeout`
+    fn set_statement_timeout(
+        &mut self,
+        _stmt_handle: &mut Self::StmtHandle,
+        _timeout: Option<Duration>,
+    ) {
+    }
+
     /// Execute the prepared statement with parameters
     fn execute(
         &mut self,
@@ -124,6 +185,40 @@ pub trait FirebirdClientSqlOps {
         tr_handle: &mut Self::TrHandle,
         stmt_handle: &mut Self::StmtHandle,
     ) -> Result<Option<Vec<Column>>, FbError>;
+
+    /// Number of rows inserted, updated or deleted by the
+    /// last execution of the statement
+    fn affected_rows(&mut self, stmt_handle: &mut Self::StmtHandle) -> Result<u64, FbError>;
+
+    /// `true` if the client and the server support the Firebird 4 batch api,
+    /// required by the `execute_batch`
+    fn supports_batch(&self) -> bool {
+        false
+    }
+
+    /// Execute the prepared statement once for each of the parameter sets, in bulk
+    /// with the Firebird 4 batch api, returning the completion state of each one,
+    /// in the same order.
+    ///
+    /// Fails if the batch api is not supported, see `supports_batch`. The default
+    /// implementation, for the clients without the batch api, always fails
+    fn execute_batch(
+        &mut self,
+        _db_handle: &mut Self::DbHandle,
+        _tr_handle: &mut Self::TrHandle,
+        _stmt_handle: &mut Self::StmtHandle,
+        _params: Vec<Vec<SqlType>>,
+    ) -> Result<Vec<BatchRowState>, FbError> {
+        err_batch_unsupported()
+    }
+}
+
+/// Error of the `execute_batch` when the batch api is not supported
+pub fn err_batch_unsupported<T>() -> Result<T, FbError> {
+    Err(FbError::from(
+        "The batch api is not supported by the client or the server, it requires the pure \
+         rust client and Firebird 4+. Use the `execute_many` to execute the parameter sets one by one",
+    ))
 }
 
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
@@ -151,6 +246,90 @@ impl FromStr for Dialect {
     }
 }
 
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[repr(u16)]
+/// Kind of a cancel request (`fb_cancel_operation` option)
+pub enum CancelKind {
+    /// Disable the cancellation of the attachment operations, until enabled again
+    Disable = ibase::fb_cancel_disable as u16,
+    /// Enable the cancellation, if disabled
+    Enable = ibase::fb_cancel_enable as u16,
+    /// Cancel the operation in progress
+    Raise = ibase::fb_cancel_raise as u16,
+    /// Cancel the operation in progress and close the attachment
+    Abort = ibase::fb_cancel_abort as u16,
+}
+
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone)]
+#[repr(u32)]
+/// Wire encryption level requested by the client
+pub enum WireCrypt {
+    /// Don't encrypt the network traffic
+    Disabled = 0,
+    /// Encrypt the network traffic if supported by the server
+    #[default]
+    Enabled = 1,
+    /// Refuse the connection if the network traffic can't be encrypted
+    Required = 2,
+}
+
+impl FromStr for WireCrypt {
+    type Err = FbError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "disabled" => Ok(WireCrypt::Disabled),
+            "enabled" => Ok(WireCrypt::Enabled),
+            "required" => Ok(WireCrypt::Required),
+            _ => Err(FbError::from(format!(
+                "'{}' doesn't represent any wire crypt level",
+                s
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+/// Wire encryption plugin
+pub enum WireCryptPlugin {
+    /// ChaCha20 with a 64 bit counter (Firebird 4.0.1+)
+    ChaCha64,
+    /// ChaCha20 with a 32 bit counter (Firebird 4+)
+    ChaCha,
+    /// Alleged RC4 (Firebird 3+)
+    Arc4,
+}
+
+impl WireCryptPlugin {
+    /// Plugins in the default order of preference
+    pub const DEFAULT: [WireCryptPlugin; 3] = [
+        WireCryptPlugin::ChaCha64,
+        WireCryptPlugin::ChaCha,
+        WireCryptPlugin::Arc4,
+    ];
+
+    /// Plugin name
+    pub fn name(&self) -> &'static str {
+        match self {
+            WireCryptPlugin::ChaCha64 => "ChaCha64",
+            WireCryptPlugin::ChaCha => "ChaCha",
+            WireCryptPlugin::Arc4 => "Arc4",
+        }
+    }
+}
+
+impl FromStr for WireCryptPlugin {
+    type Err = FbError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        WireCryptPlugin::DEFAULT
+            .iter()
+            .find(|p| p.name().eq_ignore_ascii_case(s.trim()))
+            .copied()
+            .ok_or_else(|| FbError::from(format!("'{}' isn't a known wire crypt plugin", s)))
+    }
+}
+
 #[repr(u8)]
 /// Transaction isolation level
 pub enum TrIsolationLevel {
@@ -185,6 +364,22 @@ pub enum FreeStmtOp {
     Drop = ibase::DSQL_drop as u8,
 }
 
+#[derive(Debug)]
+/// Completion state of a parameter set executed in a batch
+pub enum BatchRowState {
+    /// Executed successfully, with the number of affected rows if it was returned by the server
+    Success(Option<u32>),
+    /// Execution failed, with the error if it was returned by the server
+    Failed(Option<FbError>),
+}
+
+impl BatchRowState {
+    /// Returns `true` if the parameter set was executed successfully
+    pub fn is_success(&self) -> bool {
+        matches!(self, BatchRowState::Success(_))
+    }
+}
+
 #[repr(u8)]
 #[derive(Debug, Eq, PartialEq, Copy, Clone, TryFromPrimitive)]
 /// Statement type
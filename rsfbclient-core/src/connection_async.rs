@@ -0,0 +1,153 @@
+//! Async versions of the traits to abstract over firebird client implementations
+
+use async_trait::async_trait;
+
+use crate::*;
+
+///A wrapper trait compatible with the async api provided by the main rsfbclient crate
+pub trait FirebirdClientAsync
+where
+    Self: FirebirdClientDbOpsAsync,
+    Self: FirebirdClientSqlOpsAsync<DbHandle = <Self as FirebirdClientDbOpsAsync>::DbHandle>,
+{
+}
+
+impl<
+        Hdl,
+        A: FirebirdClientDbOpsAsync<DbHandle = Hdl> + FirebirdClientSqlOpsAsync<DbHandle = Hdl>,
+    > FirebirdClientAsync for A
+where
+    Hdl: Send,
+{
+}
+
+///Responsible for database administration and attachment/detachment, without blocking
+#[async_trait]
+pub trait FirebirdClientDbOpsAsync: Send {
+    /// A database handle
+    type DbHandle: Send;
+
+    /// Configuration details for attaching to the database
+    type AttachmentConfig: Send + Sync + Clone;
+
+    /// Create a new attachment to a database with the provided configuration
+    /// Returns a database handle on success
+    async fn attach_database(
+        &mut self,
+        config: &Self::AttachmentConfig,
+    ) -> Result<Self::DbHandle, FbError>;
+
+    /// Disconnect from the database
+    async fn detach_database(&mut self, db_handle: &mut Self::DbHandle) -> Result<(), FbError>;
+
+    /// Drop the database
+    async fn drop_database(&mut self, db_handle: &mut Self::DbHandle) -> Result<(), FbError>;
+
+    /// Check if the server is still reachable with a cheap roundtrip
+    async fn ping(&mut self, db_handle: &mut Self::DbHandle) -> Result<(), FbError>;
+
+    /// `true` if an I/O or network error was found, meaning the
+    /// attachment is unusable and must be discarded
+    fn is_broken(&self) -> bool;
+}
+
+///Responsible for actual transaction and statement execution, without blocking
+#[async_trait]
+pub trait FirebirdClientSqlOpsAsync: Send {
+    /// A database handle
+    type DbHandle: Send;
+    /// A transaction handle
+    type TrHandle: Send;
+    /// A statement handle
+    type StmtHandle: Send;
+
+    /// Start a new transaction, with the specified transaction parameter buffer
+    async fn begin_transaction(
+        &mut self,
+        db_handle: &mut Self::DbHandle,
+        isolation_level: TrIsolationLevel,
+    ) -> Result<Self::TrHandle, FbError>;
+
+    /// Commit / Rollback a transaction
+    async fn transaction_operation(
+        &mut self,
+        tr_handle: &mut Self::TrHandle,
+        op: TrOp,
+    ) -> Result<(), FbError>;
+
+    /// Execute a sql immediately, without returning rows
+    async fn exec_immediate(
+        &mut self,
+        db_handle: &mut Self::DbHandle,
+        tr_handle: &mut Self::TrHandle,
+        dialect: Dialect,
+        sql: &str,
+    ) -> Result<(), FbError>;
+
+    /// Allocate and prepare a statement
+    /// Returns the statement type and handle
+    async fn prepare_statement(
+        &mut self,
+        db_handle: &mut Self::DbHandle,
+        tr_handle: &mut Self::TrHandle,
+        dialect: Dialect,
+        sql: &str,
+    ) -> Result<(StmtType, Self::StmtHandle), FbError>;
+
+    /// Closes or drops a statement
+    async fn free_statement(
+        &mut self,
+        stmt_handle: &mut Self::StmtHandle,
+        op: FreeStmtOp,
+    ) -> Result<(), FbError>;
+
+    /// Set the name of the statement cursor, allowing it to be referenced
+    /// by `UPDATE / DELETE ... WHERE CURRENT OF <cursor_name>` statements
+    async fn set_cursor_name(
+        &mut self,
+        stmt_handle: &mut Self::StmtHandle,
+        cursor_name: &str,
+    ) -> Result<(), FbError>;
+
+    /// Set the number of rows requested from the server in each fetch round trip,
+    /// `0` for the size of the connection configuration.
+    ///
+    /// The default implementation, for the clients that manage the prefetch
+    /// by themselves, ignores it
+    fn set_fetch_size(&mut self, _stmt_handle: &mut Self::StmtHandle, _fetch_size: u32) {}
+
+    /// Execute the prepared statement with parameters
+    async fn execute(
+        &mut self,
+        db_handle: &mut Self::DbHandle,
+        tr_handle: &mut Self::TrHandle,
+        stmt_handle: &mut Self::StmtHandle,
+        params: Vec<SqlType>,
+    ) -> Result<(), FbError>;
+
+    /// Execute the prepared statement
+    /// with input and output parameters.
+    ///
+    /// The output parameters will be returned
+    /// as in the Result
+    async fn execute2(
+        &mut self,
+        db_handle: &mut Self::DbHandle,
+        tr_handle: &mut Self::TrHandle,
+        stmt_handle: &mut Self::StmtHandle,
+        params: Vec<SqlType>,
+    ) -> Result<Vec<Column>, FbError>;
+
+    /// Fetch rows from the executed statement, coercing the types
+    /// according to the provided blr
+    async fn fetch(
+        &mut self,
+        db_handle: &mut Self::DbHandle,
+        tr_handle: &mut Self::TrHandle,
+        stmt_handle: &mut Self::StmtHandle,
+    ) -> Result<Option<Vec<Column>>, FbError>;
+
+    /// Number of rows inserted, updated or deleted by the
+    /// last execution of the statement
+    async fn affected_rows(&mut self, stmt_handle: &mut Self::StmtHandle) -> Result<u64, FbError>;
+}
@@ -0,0 +1,249 @@
+//! Database parameter buffer
+
+use crate::{ibase, FbError};
+use std::str::FromStr;
+
+/// Optional items of the database parameter buffer (DPB), sent
+/// by the clients when attaching to or creating a database.
+///
+/// The user, password, charset and the client specific items
+/// (timeouts, authentication) are added by the clients.
+///
+/// # Example
+///
+/// ```
+/// use rsfbclient_core::Dpb;
+///
+/// let mut dpb = Dpb::default();
+/// dpb.role("RDB$ADMIN")
+///     .num_buffers(2048)
+///     .process_name("my_app");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Dpb {
+    role: Option<String>,
+    num_buffers: Option<u32>,
+    no_garbage_collect: bool,
+    process_name: Option<String>,
+    process_id: Option<u32>,
+    client_version: Option<String>,
+    session_time_zone: Option<String>,
+    set_bind: Option<SetBind>,
+    utf8_filename: bool,
+    custom: Vec<(u8, Vec<u8>)>,
+}
+
+impl Dpb {
+    /// SQL role of the attachment (`isc_dpb_sql_role_name`)
+    pub fn role<S: Into<String>>(&mut self, role: S) -> &mut Self {
+        self.role = Some(role.into());
+        self
+    }
+
+    /// Number of pages of the database cache, for the first attachment
+    /// of a database in the SuperServer mode (`isc_dpb_num_buffers`)
+    pub fn num_buffers(&mut self, pages: u32) -> &mut Self {
+        self.num_buffers = Some(pages);
+        self
+    }
+
+    /// Disable the garbage collection by the attachment (`isc_dpb_no_garbage_collect`)
+    pub fn no_garbage_collect(&mut self) -> &mut Self {
+        self.no_garbage_collect = true;
+        self
+    }
+
+    /// Name of the process, shown in the `MON$ATTACHMENTS` (`isc_dpb_process_name`)
+    pub fn process_name<S: Into<String>>(&mut self, name: S) -> &mut Self {
+        self.process_name = Some(name.into());
+        self
+    }
+
+    /// Id of the process, shown in the `MON$ATTACHMENTS` (`isc_dpb_process_id`)
+    pub fn process_id(&mut self, id: u32) -> &mut Self {
+        self.process_id = Some(id);
+        self
+    }
+
+    /// Version of the client, shown in the `MON$ATTACHMENTS` (`isc_dpb_client_version`)
+    pub fn client_version<S: Into<String>>(&mut self, version: S) -> &mut Self {
+        self.client_version = Some(version.into());
+        self
+    }
+
+    /// Time zone of the session, like `UTC` or `America/Sao_Paulo`.
+    /// Requires Firebird 4+ (`isc_dpb_session_time_zone`)
+    pub fn session_time_zone<S: Into<String>>(&mut self, time_zone: S) -> &mut Self {
+        self.session_time_zone = Some(time_zone.into());
+        self
+    }
+
+    /// Coercion of the data types by the server, like the `SET BIND` statement.
+    /// Requires Firebird 4+ (`isc_dpb_set_bind`)
+    pub fn set_bind(&mut self, bind: SetBind) -> &mut Self {
+        self.set_bind = Some(bind);
+        self
+    }
+
+    /// The database name is encoded in utf-8 (`isc_dpb_utf8_filename`)
+    pub fn utf8_filename(&mut self) -> &mut Self {
+        self.utf8_filename = true;
+        self
+    }
+
+    /// Add an item not supported by the other methods, with the tag
+    /// (one of the `ibase::isc_dpb_*` constants) and the value
+    pub fn custom(&mut self, tag: u32, value: &[u8]) -> &mut Self {
+        self.custom.push((tag as u8, value.to_vec()));
+        self
+    }
+
+    /// Add an item with a 4 bytes integer value
+    pub fn custom_int(&mut self, tag: u32, value: u32) -> &mut Self {
+        self.custom(tag, &value.to_le_bytes())
+    }
+
+    /// Add the items of other buffer. The items set in both are
+    /// taken from `other`, and its custom items are appended
+    pub fn merge(&mut self, other: Dpb) -> &mut Self {
+        let Dpb {
+            role,
+            num_buffers,
+            no_garbage_collect,
+            process_name,
+            process_id,
+            client_version,
+            session_time_zone,
+            set_bind,
+            utf8_filename,
+            custom,
+        } = other;
+
+        self.role = role.or(self.role.take());
+        self.num_buffers = num_buffers.or(self.num_buffers);
+        self.no_garbage_collect |= no_garbage_collect;
+        self.process_name = process_name.or(self.process_name.take());
+        self.process_id = process_id.or(self.process_id);
+        self.client_version = client_version.or(self.client_version.take());
+        self.session_time_zone = session_time_zone.or(self.session_time_zone.take());
+        self.set_bind = set_bind.or(self.set_bind.take());
+        self.utf8_filename |= utf8_filename;
+        self.custom.extend(custom);
+
+        self
+    }
+
+    /// Encode the items, to be appended to a buffer of the version 1
+    pub fn to_bytes(&self) -> Result<Vec<u8>, FbError> {
+        let mut dpb = Vec::new();
+
+        let set_bind = self.set_bind.as_ref().map(|bind| bind.rules().to_string());
+
+        let str_params = [
+            (ibase::isc_dpb_sql_role_name, &self.role),
+            (ibase::isc_dpb_process_name, &self.process_name),
+            (ibase::isc_dpb_client_version, &self.client_version),
+            (ibase::isc_dpb_session_time_zone, &self.session_time_zone),
+            (ibase::isc_dpb_set_bind, &set_bind),
+        ];
+
+        for (tag, value) in str_params.iter() {
+            if let Some(value) = value {
+                put_item(&mut dpb, *tag as u8, value.as_bytes())?;
+            }
+        }
+
+        let int_params = [
+            (ibase::isc_dpb_num_buffers, self.num_buffers),
+            (ibase::isc_dpb_process_id, self.process_id),
+        ];
+
+        for (tag, value) in int_params.iter() {
+            if let Some(value) = value {
+                put_item(&mut dpb, *tag as u8, &value.to_le_bytes())?;
+            }
+        }
+
+        let flags = [
+            (ibase::isc_dpb_no_garbage_collect, self.no_garbage_collect),
+            (ibase::isc_dpb_utf8_filename, self.utf8_filename),
+        ];
+
+        for (tag, enabled) in flags.iter() {
+            if *enabled {
+                put_item(&mut dpb, *tag as u8, &[])?;
+            }
+        }
+
+        for (tag, value) in self.custom.iter() {
+            put_item(&mut dpb, *tag, value)?;
+        }
+
+        Ok(dpb)
+    }
+}
+
+/// Coercion of the data types by the server (`SET BIND`), to use
+/// the Firebird 4+ types with the clients that don't support them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SetBind {
+    /// The `INT128`, `DECFLOAT` and time zone types are coerced to
+    /// the `BIGINT`, `DOUBLE PRECISION` and types without time zone
+    Legacy,
+
+    /// The types are sent unchanged. The server default
+    Native,
+
+    /// Rules in the syntax of the `SET BIND` statement, without the `SET BIND OF`
+    /// and separated by `;`, like `int128 to varchar; decfloat to double precision`
+    Custom(String),
+}
+
+impl SetBind {
+    /// Rules sent to the server
+    pub fn rules(&self) -> &str {
+        match self {
+            SetBind::Legacy => "int128 to legacy; decfloat to legacy; time zone to legacy",
+            SetBind::Native => "int128 to native; decfloat to native; time zone to native",
+            SetBind::Custom(rules) => rules,
+        }
+    }
+}
+
+impl FromStr for SetBind {
+    type Err = FbError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Accept the rules copied from the `SET BIND` statements
+        let rules = s.trim().trim_end_matches(';').trim_end();
+
+        match rules.to_lowercase().as_str() {
+            "legacy" => Ok(SetBind::Legacy),
+            "native" => Ok(SetBind::Native),
+            lower if lower.split(';').all(|rule| rule.contains(" to ")) => {
+                Ok(SetBind::Custom(rules.to_string()))
+            }
+            _ => Err(FbError::from(format!(
+                "'{}' is not a valid set bind, use legacy, native or rules like 'int128 to varchar'",
+                s
+            ))),
+        }
+    }
+}
+
+/// Append the tag, the length and the value
+fn put_item(dpb: &mut Vec<u8>, tag: u8, value: &[u8]) -> Result<(), FbError> {
+    if value.len() > u8::MAX as usize {
+        return Err(FbError::from(format!(
+            "The value of the dpb item {} is too long ({} bytes), the max is 255",
+            tag,
+            value.len()
+        )));
+    }
+
+    dpb.push(tag);
+    dpb.push(value.len() as u8);
+    dpb.extend(value);
+
+    Ok(())
+}
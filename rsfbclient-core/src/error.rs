@@ -4,7 +4,7 @@ use std::str::Utf8Error;
 use std::string::FromUtf8Error;
 use thiserror::Error;
 
-use crate::SqlType;
+use crate::{ibase, SqlType};
 
 #[derive(Debug, Error)]
 pub enum FbError {
@@ -12,12 +12,50 @@ pub enum FbError {
     Sql { msg: String, code: i32 },
 
     #[error("io error: {0}")]
-    Io(#[from] std::io::Error),
+    Io(#[source] std::io::Error),
+
+    /// Connect or network operation timed out. The connection may be
+    /// in an inconsistent state and should be discarded
+    #[error("timeout: {0}")]
+    Timeout(String),
+
+    /// The operation was cancelled by a cancel request (`isc_cancelled`)
+    #[error("cancelled: {0}")]
+    Cancelled(String),
+
+    /// The statement execution exceeded the statement timeout (Firebird 4+)
+    #[error("statement timeout: {0}")]
+    StatementTimeout(String),
+
+    /// The server closed the session after exceeding the idle timeout (Firebird 4+)
+    #[error("idle session timeout: {0}")]
+    IdleTimeout(String),
+
+    /// The database file is opened by another process with an incompatible
+    /// server mode (`isc_already_opened`), like an embedded engine in SuperServer
+    /// mode, that locks the file exclusively
+    #[error("database in use: {0}")]
+    DatabaseInUse(String),
 
     #[error("error: {0}")]
     Other(String),
 }
 
+impl From<std::io::Error> for FbError {
+    fn from(e: std::io::Error) -> Self {
+        // A `FbError` raised inside an io operation, like the `Timeout`
+        // of the streams with a read / write timeout configured
+        if matches!(e.get_ref(), Some(inner) if inner.is::<FbError>()) {
+            if let Some(Ok(inner)) = e.into_inner().map(|inner| inner.downcast::<FbError>()) {
+                return *inner;
+            }
+            unreachable!("checked to be a FbError");
+        }
+
+        Self::Io(e)
+    }
+}
+
 impl From<String> for FbError {
     fn from(msg: String) -> Self {
         Self::Other(msg)
@@ -42,6 +80,46 @@ impl From<Utf8Error> for FbError {
     }
 }
 
+/// Error of a status vector returned by the server, with the codes of its messages
+pub fn err_status_vector(gds_codes: &[u32], code: i32, msg: String) -> FbError {
+    let has = |c| gds_codes.contains(&c);
+
+    if has(ibase::isc_cfg_stmt_timeout)
+        || has(ibase::isc_att_stmt_timeout)
+        || has(ibase::isc_req_stmt_timeout)
+    {
+        FbError::StatementTimeout(msg)
+    } else if has(ibase::isc_att_shut_idle) {
+        FbError::IdleTimeout(msg)
+    } else if has(ibase::isc_already_opened) {
+        FbError::DatabaseInUse(msg)
+    } else if gds_codes.first() == Some(&ibase::isc_cancelled) {
+        FbError::Cancelled(msg)
+    } else {
+        FbError::Sql { msg, code }
+    }
+}
+
+/// `true` if the codes of a status vector indicates that the connection
+/// with the server was lost, so the attachment can't be used anymore
+pub fn is_connection_lost(gds_codes: &[u32]) -> bool {
+    gds_codes.iter().any(|code| {
+        matches!(
+            *code,
+            ibase::isc_network_error
+                | ibase::isc_net_read_err
+                | ibase::isc_net_write_err
+                | ibase::isc_lost_db_connection
+                | ibase::isc_conn_lost
+                | ibase::isc_shutdown
+                | ibase::isc_att_shutdown
+                | ibase::isc_net_server_shutdown
+                | ibase::isc_att_shut_killed
+                | ibase::isc_att_shut_idle
+        )
+    })
+}
+
 pub fn err_column_null(type_name: &str) -> FbError {
     FbError::Other(format!(
         "This is a null value. Use the Option<{}> to safe access this column and avoid errors",
@@ -424,6 +424,11 @@ pub const isc_dpb_config: u32 = 87;
 pub const isc_dpb_nolinger: u32 = 88;
 pub const isc_dpb_reset_icu: u32 = 89;
 pub const isc_dpb_map_attach: u32 = 90;
+pub const isc_dpb_session_time_zone: u32 = 91;
+pub const isc_dpb_set_db_replica: u32 = 92;
+pub const isc_dpb_set_bind: u32 = 93;
+pub const isc_dpb_decfloat_round: u32 = 94;
+pub const isc_dpb_decfloat_traps: u32 = 95;
 pub const isc_dpb_address: u32 = 1;
 pub const isc_dpb_addr_protocol: u32 = 1;
 pub const isc_dpb_addr_endpoint: u32 = 2;
@@ -1675,6 +1680,11 @@ pub const isc_login_error: u32 = 335545106;
 pub const isc_already_opened: u32 = 335545107;
 pub const isc_bad_crypt_key: u32 = 335545108;
 pub const isc_encrypt_error: u32 = 335545109;
+pub const isc_cfg_stmt_timeout: u32 = 335545127;
+pub const isc_att_stmt_timeout: u32 = 335545128;
+pub const isc_req_stmt_timeout: u32 = 335545129;
+pub const isc_att_shut_killed: u32 = 335545130;
+pub const isc_att_shut_idle: u32 = 335545131;
 pub const isc_gfix_db_name: u32 = 335740929;
 pub const isc_gfix_invalid_sw: u32 = 335740930;
 pub const isc_gfix_incmp_sw: u32 = 335740932;
@@ -3,8 +3,11 @@
 
 pub mod charset;
 mod connection;
+#[cfg(feature = "async")]
+mod connection_async;
 #[cfg(feature = "date_time")]
 pub mod date_time;
+mod dpb;
 pub(crate) mod error;
 pub mod ibase;
 mod params;
@@ -12,7 +15,10 @@ mod row;
 
 pub use charset::Charset;
 pub use connection::*;
-pub use error::FbError;
+#[cfg(feature = "async")]
+pub use connection_async::*;
+pub use dpb::{Dpb, SetBind};
+pub use error::{err_status_vector, is_connection_lost, FbError};
 pub use params::*;
 pub use row::*;
 
@@ -122,6 +122,7 @@ where
 }
 
 /// Parameters type
+#[derive(Clone)]
 pub enum ParamsType {
     /// Positional parameters, using '?'. This is the default option.
     ///
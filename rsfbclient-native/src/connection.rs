@@ -9,17 +9,23 @@ use crate::{
 };
 use rsfbclient_core::*;
 
-use std::{convert::TryFrom, ptr};
+use std::{convert::TryFrom, ptr, time::Duration};
 
 type NativeDbHandle = ibase::isc_db_handle;
 type NativeTrHandle = ibase::isc_tr_handle;
 type NativeStmtHandle = ibase::isc_stmt_handle;
 
-/// Client that wraps the native fbclient library
+/// Client that wraps the native fbclient library.
+///
+/// Uses only the legacy isc api, so the Firebird 4 batch api (`IBatch`)
+/// is not supported: the `Connection::execute_batch` executes the
+/// parameter sets one by one
 pub struct NativeFbClient<T: LinkageMarker> {
     ibase: T::L,
     status: Status,
     charset: Charset,
+    /// A network error was found, the attachment is unusable
+    broken: bool,
 }
 
 /// The remote part of native client configuration
@@ -28,6 +34,14 @@ pub struct RemoteConfig {
     pub host: String,
     pub port: u16,
     pub pass: String,
+    /// Maximum time to establish the connection (`isc_dpb_connect_timeout`)
+    pub connect_timeout: Option<Duration>,
+    /// Interval of the keepalive packets sent to the server (`isc_dpb_dummy_packet_interval`)
+    pub keepalive: Option<Duration>,
+    /// `WireCrypt` of the firebird.conf, overridden by the `isc_dpb_config`
+    pub wire_crypt: Option<WireCrypt>,
+    /// `WireCompression` of the firebird.conf, overridden by the `isc_dpb_config`
+    pub wire_compression: Option<bool>,
 }
 
 /// Data associated with a prepared statement
@@ -46,12 +60,17 @@ pub struct NativeFbAttachmentConfig {
     pub db_name: String,
     pub user: String,
     pub remote: Option<RemoteConfig>,
+    /// Items of the database parameter buffer, like the role
+    pub dpb: Dpb,
+    /// Authentication plugins, comma separated, overriding the
+    /// `AuthClient` of the firebird.conf (`isc_dpb_auth_plugin_list`)
+    pub auth_plugin_list: Option<String>,
 }
 
 /// A marker trait which can be used to
 /// obtain the associated client instance
 pub trait LinkageMarker: Send + Sync {
-    type L: IBase + Send;
+    type L: IBase + Send + Sync + Clone + 'static;
 }
 
 /// Configuration details for dynamic linking
@@ -70,6 +89,7 @@ impl DynLink {
             ibase: ibase::IBaseLinking,
             status: Default::default(),
             charset: self.0.clone(),
+            broken: false,
         };
         result
     }
@@ -97,19 +117,29 @@ impl DynLoad {
             ibase: load_result,
             status: Default::default(),
             charset: self.charset.clone(),
+            broken: false,
         };
 
         Ok(result)
     }
 }
 
-impl<T: LinkageMarker> FirebirdClientDbOps for NativeFbClient<T> {
-    type DbHandle = NativeDbHandle;
-    type AttachmentConfig = NativeFbAttachmentConfig;
+impl<T: LinkageMarker> NativeFbClient<T> {
+    /// Convert the status vector of the last call to an error,
+    /// marking the client as broken if the connection was lost
+    fn status_error(&mut self) -> FbError {
+        if self.status.is_connection_lost() {
+            self.broken = true;
+        }
+        self.status.as_error(&self.ibase)
+    }
 
-    fn attach_database(
+    /// Attach to the database, or create it if `create`
+    /// has the page size and dialect of the new database
+    fn attach_or_create(
         &mut self,
-        config: &Self::AttachmentConfig,
+        config: &NativeFbAttachmentConfig,
+        create: Option<(Option<u32>, Dialect)>,
     ) -> Result<NativeDbHandle, FbError> {
         let user = &config.user;
         let mut password = None;
@@ -144,33 +174,128 @@ impl<T: LinkageMarker> FirebirdClientDbOps for NativeFbClient<T> {
                 dpb.extend(pass_str.bytes());
             };
 
+            if let Some(remote_conf) = maybe_remote {
+                let secs_params = [
+                    (ibase::isc_dpb_connect_timeout, remote_conf.connect_timeout),
+                    (ibase::isc_dpb_dummy_packet_interval, remote_conf.keepalive),
+                ];
+
+                for (param, duration) in secs_params.iter() {
+                    if let Some(duration) = duration.filter(|d| !d.is_zero()) {
+                        // Values in seconds, at least 1 as 0 disables it.
+                        // A zero duration is ignored, like in the pure rust client
+                        let secs = duration.as_secs().max(1).min(u32::MAX as u64) as u32;
+
+                        dpb.extend(&[*param as u8, 4]);
+                        dpb.extend(&secs.to_le_bytes());
+                    }
+                }
+
+                // Entries of the firebird.conf, for this connection only
+                let mut conf = Vec::new();
+
+                if let Some(wire_crypt) = remote_conf.wire_crypt {
+                    conf.push(format!(
+                        "WireCrypt = {}",
+                        match wire_crypt {
+                            WireCrypt::Disabled => "Disabled",
+                            WireCrypt::Enabled => "Enabled",
+                            WireCrypt::Required => "Required",
+                        }
+                    ));
+                }
+
+                if let Some(wire_compression) = remote_conf.wire_compression {
+                    conf.push(format!("WireCompression = {}", wire_compression));
+                }
+
+                if !conf.is_empty() {
+                    let conf = conf.join("\n");
+
+                    dpb.extend(&[ibase::isc_dpb_config as u8, conf.len() as u8]);
+                    dpb.extend(conf.bytes());
+                }
+            }
+
             let charset = self.charset.on_firebird.bytes();
 
             dpb.extend(&[ibase::isc_dpb_lc_ctype as u8, charset.len() as u8]);
             dpb.extend(charset);
 
+            let mut items = config.dpb.clone();
+
+            if let Some(plugins) = &config.auth_plugin_list {
+                items.custom(ibase::isc_dpb_auth_plugin_list, plugins.as_bytes());
+            }
+
+            if let Some((page_size, dialect)) = create {
+                if let Some(page_size) = page_size {
+                    items.custom_int(ibase::isc_dpb_page_size, page_size);
+                }
+
+                items.custom_int(ibase::isc_dpb_sql_dialect, dialect as u32);
+            }
+
+            dpb.extend(items.to_bytes()?);
+
             dpb
         };
 
-        unsafe {
-            if self.ibase.isc_attach_database()(
-                &mut self.status[0],
-                conn_string.len() as i16,
-                conn_string.as_ptr() as *const _,
-                &mut handle,
-                dpb.len() as i16,
-                dpb.as_ptr() as *const _,
-            ) != 0
-            {
-                return Err(self.status.as_error(&self.ibase));
+        let res = unsafe {
+            if create.is_some() {
+                self.ibase.isc_create_database()(
+                    &mut self.status[0],
+                    conn_string.len() as i16,
+                    conn_string.as_ptr() as *const _,
+                    &mut handle,
+                    dpb.len() as i16,
+                    dpb.as_ptr() as *const _,
+                    0,
+                )
+            } else {
+                self.ibase.isc_attach_database()(
+                    &mut self.status[0],
+                    conn_string.len() as i16,
+                    conn_string.as_ptr() as *const _,
+                    &mut handle,
+                    dpb.len() as i16,
+                    dpb.as_ptr() as *const _,
+                )
             }
+        };
+
+        if res != 0 {
+            return Err(self.status_error());
         }
 
         // Assert that the handle is valid
         debug_assert_ne!(handle, 0);
 
+        self.broken = false;
+
         Ok(handle)
     }
+}
+
+impl<T: LinkageMarker> FirebirdClientDbOps for NativeFbClient<T> {
+    type DbHandle = NativeDbHandle;
+    type AttachmentConfig = NativeFbAttachmentConfig;
+
+    fn attach_database(
+        &mut self,
+        config: &Self::AttachmentConfig,
+    ) -> Result<NativeDbHandle, FbError> {
+        self.attach_or_create(config, None)
+    }
+
+    fn create_database(
+        &mut self,
+        config: &Self::AttachmentConfig,
+        page_size: Option<u32>,
+        dialect: Dialect,
+    ) -> Result<NativeDbHandle, FbError> {
+        self.attach_or_create(config, Some((page_size, dialect)))
+    }
 
     fn detach_database(&mut self, db_handle: &mut NativeDbHandle) -> Result<(), FbError> {
         unsafe {
@@ -178,7 +303,7 @@ impl<T: LinkageMarker> FirebirdClientDbOps for NativeFbClient<T> {
             if *db_handle != 0
                 && self.ibase.isc_detach_database()(&mut self.status[0], db_handle) != 0
             {
-                return Err(self.status.as_error(&self.ibase));
+                return Err(self.status_error());
             }
         }
         Ok(())
@@ -187,11 +312,58 @@ impl<T: LinkageMarker> FirebirdClientDbOps for NativeFbClient<T> {
     fn drop_database(&mut self, db_handle: &mut NativeDbHandle) -> Result<(), FbError> {
         unsafe {
             if self.ibase.isc_drop_database()(&mut self.status[0], db_handle) != 0 {
-                return Err(self.status.as_error(&self.ibase));
+                return Err(self.status_error());
             }
         }
         Ok(())
     }
+
+    fn cancel_handle(
+        &mut self,
+        db_handle: &mut NativeDbHandle,
+    ) -> Result<Box<dyn FirebirdClientCancel>, FbError> {
+        Ok(Box::new(NativeCancelHandle {
+            ibase: self.ibase.clone(),
+            db_handle: *db_handle,
+        }))
+    }
+
+    fn ping(&mut self, db_handle: &mut NativeDbHandle) -> Result<(), FbError> {
+        unsafe {
+            if self.ibase.fb_ping()(&mut self.status[0], db_handle) != 0 {
+                // A failed ping means the connection is unusable
+                self.broken = true;
+                return Err(self.status_error());
+            }
+        }
+        Ok(())
+    }
+
+    fn is_broken(&self) -> bool {
+        self.broken
+    }
+}
+
+/// Cancels the operations of an attachment with the `fb_cancel_operation`
+struct NativeCancelHandle<L> {
+    ibase: L,
+    db_handle: NativeDbHandle,
+}
+
+impl<L: IBase + Sync> FirebirdClientCancel for NativeCancelHandle<L> {
+    fn cancel_operation(&self, kind: CancelKind) -> Result<(), FbError> {
+        let mut status = Status::default();
+        // The handle is only read by the fbclient
+        let mut db_handle = self.db_handle;
+
+        unsafe {
+            if self.ibase.fb_cancel_operation()(&mut status[0], &mut db_handle, kind as u16) != 0 {
+                return Err(status.as_error(&self.ibase));
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl<T: LinkageMarker> FirebirdClientSqlOps for NativeFbClient<T> {
@@ -228,7 +400,7 @@ impl<T: LinkageMarker> FirebirdClientSqlOps for NativeFbClient<T> {
                 } as *mut _ as _,
             ) != 0
             {
-                return Err(self.status.as_error(&self.ibase));
+                return Err(self.status_error());
             }
         }
 
@@ -258,7 +430,7 @@ impl<T: LinkageMarker> FirebirdClientSqlOps for NativeFbClient<T> {
                 }
             } != 0
             {
-                return Err(self.status.as_error(&self.ibase));
+                return Err(self.status_error());
             }
         }
         Ok(())
@@ -284,7 +456,7 @@ impl<T: LinkageMarker> FirebirdClientSqlOps for NativeFbClient<T> {
                 ptr::null(),
             ) != 0
             {
-                return Err(self.status.as_error(&self.ibase));
+                return Err(self.status_error());
             }
         }
         Ok(())
@@ -309,7 +481,7 @@ impl<T: LinkageMarker> FirebirdClientSqlOps for NativeFbClient<T> {
             if self.ibase.isc_dsql_allocate_statement()(&mut self.status[0], db_handle, &mut handle)
                 != 0
             {
-                return Err(self.status.as_error(&self.ibase));
+                return Err(self.status_error());
             }
 
             if self.ibase.isc_dsql_prepare()(
@@ -322,7 +494,7 @@ impl<T: LinkageMarker> FirebirdClientSqlOps for NativeFbClient<T> {
                 &mut *xsqlda,
             ) != 0
             {
-                return Err(self.status.as_error(&self.ibase));
+                return Err(self.status_error());
             }
 
             let row_count = xsqlda.sqld;
@@ -334,7 +506,7 @@ impl<T: LinkageMarker> FirebirdClientSqlOps for NativeFbClient<T> {
                 if self.ibase.isc_dsql_describe()(&mut self.status[0], &mut handle, 1, &mut *xsqlda)
                     != 0
                 {
-                    return Err(self.status.as_error(&self.ibase));
+                    return Err(self.status_error());
                 }
             }
 
@@ -351,7 +523,7 @@ impl<T: LinkageMarker> FirebirdClientSqlOps for NativeFbClient<T> {
                 &mut info_buf[0],
             ) != 0
             {
-                return Err(self.status.as_error(&self.ibase));
+                return Err(self.status_error());
             }
 
             for &v in &info_buf[3..] {
@@ -399,13 +571,87 @@ impl<T: LinkageMarker> FirebirdClientSqlOps for NativeFbClient<T> {
                 op as u16,
             ) != 0
             {
-                return Err(self.status.as_error(&self.ibase));
+                return Err(self.status_error());
             }
         }
 
         Ok(())
     }
 
+    fn set_cursor_name(
+        &mut self,
+        stmt_handle: &mut Self::StmtHandle,
+        cursor_name: &str,
+    ) -> Result<(), FbError> {
+        // Needs to be null terminated
+        let cursor_name = std::ffi::CString::new(self.charset.encode(cursor_name)?)
+            .map_err(|_| FbError::from("The cursor name can't contain null characters"))?;
+
+        unsafe {
+            if self.ibase.isc_dsql_set_cursor_name()(
+                &mut self.status[0],
+                &mut stmt_handle.handle,
+                cursor_name.as_ptr(),
+                0, // Unused
+            ) != 0
+            {
+                return Err(self.status_error());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn affected_rows(&mut self, stmt_handle: &mut Self::StmtHandle) -> Result<u64, FbError> {
+        let info_req = [ibase::isc_info_sql_records as std::os::raw::c_char];
+        let mut info_buf = [0u8; 64];
+
+        unsafe {
+            if self.ibase.isc_dsql_sql_info()(
+                &mut self.status[0],
+                &mut stmt_handle.handle,
+                info_req.len() as i16,
+                &info_req[0],
+                info_buf.len() as i16,
+                info_buf.as_mut_ptr() as *mut std::os::raw::c_char,
+            ) != 0
+            {
+                return Err(self.status_error());
+            }
+        }
+
+        if info_buf[0] != ibase::isc_info_sql_records as u8 {
+            return Err("Invalid isc_info_sql_records response".into());
+        }
+
+        let mut affected = 0;
+        // Skip the item and total length
+        let mut pos = 3;
+
+        while pos + 3 <= info_buf.len() && info_buf[pos] as u32 != ibase::isc_info_end {
+            let item = info_buf[pos] as u32;
+            let len = u16::from_le_bytes([info_buf[pos + 1], info_buf[pos + 2]]) as usize;
+            pos += 3;
+
+            if pos + len > info_buf.len() {
+                return Err("Truncated isc_info_sql_records response".into());
+            }
+
+            if item == ibase::isc_info_req_insert_count
+                || item == ibase::isc_info_req_update_count
+                || item == ibase::isc_info_req_delete_count
+            {
+                let mut val = [0; 4];
+                val[..len.min(4)].copy_from_slice(&info_buf[pos..pos + len.min(4)]);
+                affected += u32::from_le_bytes(val) as u64;
+            }
+
+            pos += len;
+        }
+
+        Ok(affected)
+    }
+
     fn execute(
         &mut self,
         db_handle: &mut Self::DbHandle,
@@ -436,7 +682,7 @@ impl<T: LinkageMarker> FirebirdClientSqlOps for NativeFbClient<T> {
                 },
             ) != 0
             {
-                return Err(self.status.as_error(&self.ibase));
+                return Err(self.status_error());
             }
         }
 
@@ -466,7 +712,7 @@ impl<T: LinkageMarker> FirebirdClientSqlOps for NativeFbClient<T> {
             }
 
             if fetch_status != 0 {
-                return Err(self.status.as_error(&self.ibase));
+                return Err(self.status_error());
             };
         }
 
@@ -510,7 +756,7 @@ impl<T: LinkageMarker> FirebirdClientSqlOps for NativeFbClient<T> {
                 &*stmt_handle.xsqlda,
             ) != 0
             {
-                return Err(self.status.as_error(&self.ibase));
+                return Err(self.status_error());
             }
         }
 
@@ -17,6 +17,7 @@ macro_rules! parse_functions {
       }
 
       #[cfg(feature = "linking")]
+      #[derive(Clone)]
       pub struct IBaseLinking;
       #[cfg(feature = "linking")]
       impl IBase for IBaseLinking {
@@ -24,6 +25,7 @@ macro_rules! parse_functions {
       }
 
       #[cfg(feature = "dynamic_loading")]
+      #[derive(Clone)]
       pub struct IBaseDynLoading(std::sync::Arc<libloading::Library>);
       #[cfg(feature = "dynamic_loading")]
       impl IBase for IBaseDynLoading {
@@ -258,17 +260,17 @@ parse_functions! {
     //         arg7: *const ISC_SCHAR,
     //     ) -> ISC_STATUS;
     // }
-    // extern "C" {
-    //     pub fn isc_create_database(
-    //         arg1: *mut ISC_STATUS,
-    //         arg2: ::std::os::raw::c_short,
-    //         arg3: *const ISC_SCHAR,
-    //         arg4: *mut isc_db_handle,
-    //         arg5: ::std::os::raw::c_short,
-    //         arg6: *const ISC_SCHAR,
-    //         arg7: ::std::os::raw::c_short,
-    //     ) -> ISC_STATUS;
-    // }
+    extern "C" {
+        pub fn isc_create_database(
+            arg1: *mut ISC_STATUS,
+            arg2: ::std::os::raw::c_short,
+            arg3: *const ISC_SCHAR,
+            arg4: *mut isc_db_handle,
+            arg5: ::std::os::raw::c_short,
+            arg6: *const ISC_SCHAR,
+            arg7: ::std::os::raw::c_short,
+        ) -> ISC_STATUS;
+    }
     // extern "C" {
     //     pub fn isc_database_info(
     //         arg1: *mut ISC_STATUS,
@@ -406,14 +408,14 @@ parse_functions! {
             arg7: *mut XSQLDA,
         ) -> ISC_STATUS;
     }
-    // extern "C" {
-    //     pub fn isc_dsql_set_cursor_name(
-    //         arg1: *mut ISC_STATUS,
-    //         arg2: *mut isc_stmt_handle,
-    //         arg3: *const ISC_SCHAR,
-    //         arg4: ::std::os::raw::c_ushort,
-    //     ) -> ISC_STATUS;
-    // }
+    extern "C" {
+        pub fn isc_dsql_set_cursor_name(
+            arg1: *mut ISC_STATUS,
+            arg2: *mut isc_stmt_handle,
+            arg3: *const ISC_SCHAR,
+            arg4: ::std::os::raw::c_ushort,
+        ) -> ISC_STATUS;
+    }
     extern "C" {
         pub fn isc_dsql_sql_info(
             arg1: *mut ISC_STATUS,
@@ -1245,16 +1247,16 @@ parse_functions! {
     //         arg4: *mut ::std::os::raw::c_void,
     //     ) -> ISC_STATUS;
     // }
-    // extern "C" {
-    //     pub fn fb_cancel_operation(
-    //         arg1: *mut ISC_STATUS,
-    //         arg2: *mut isc_db_handle,
-    //         arg3: ISC_USHORT,
-    //     ) -> ISC_STATUS;
-    // }
-    // extern "C" {
-    //     pub fn fb_ping(arg1: *mut ISC_STATUS, arg2: *mut isc_db_handle) -> ISC_STATUS;
-    // }
+    extern "C" {
+        pub fn fb_cancel_operation(
+            arg1: *mut ISC_STATUS,
+            arg2: *mut isc_db_handle,
+            arg3: ISC_USHORT,
+        ) -> ISC_STATUS;
+    }
+    extern "C" {
+        pub fn fb_ping(arg1: *mut ISC_STATUS, arg2: *mut isc_db_handle) -> ISC_STATUS;
+    }
     // extern "C" {
     //     pub fn fb_get_database_handle(
     //         arg1: *mut ISC_STATUS,
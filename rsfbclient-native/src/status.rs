@@ -1,6 +1,7 @@
 //! Status of connetions, transactions...
 
 pub use rsfbclient_core::FbError;
+use rsfbclient_core::{err_status_vector, is_connection_lost};
 use std::{
     fmt::Write,
     ops::{Deref, DerefMut},
@@ -69,10 +70,31 @@ impl Status {
         msg
     }
 
-    pub fn as_error<T: IBase>(&self, ibase: &T) -> FbError {
-        FbError::Sql {
-            code: self.sql_code(ibase),
-            msg: self.message(ibase),
+    /// Codes of the error messages
+    pub fn gds_codes(&self) -> Vec<u32> {
+        let mut codes = Vec::new();
+        let mut i = 0;
+
+        while i + 1 < self.0.len() {
+            match self.0[i] as u32 {
+                ibase::isc_arg_end => break,
+                ibase::isc_arg_gds => codes.push(self.0[i + 1] as u32),
+                // Length and pointer
+                ibase::isc_arg_cstring => i += 1,
+                _ => {}
+            }
+            i += 2;
         }
+
+        codes
+    }
+
+    /// `true` if the error means that the connection with the server was lost
+    pub fn is_connection_lost(&self) -> bool {
+        is_connection_lost(&self.gds_codes())
+    }
+
+    pub fn as_error<T: IBase>(&self, ibase: &T) -> FbError {
+        err_status_vector(&self.gds_codes(), self.sql_code(ibase), self.message(ibase))
     }
 }
@@ -96,10 +96,10 @@ impl<S: Write> Write for Arc4Stream<S> {
         let max_len = buf.len().min(enc_buf.len());
         // Encrypt
         write_rc4.process(&buf[..max_len], &mut enc_buf[..max_len]);
-        // Write encrypted data
-        let len = stream.write(&enc_buf[..max_len])?;
+        // Write all the encrypted data, as the keystream already advanced
+        stream.write_all(&enc_buf[..max_len])?;
 
-        Ok(len)
+        Ok(max_len)
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
@@ -0,0 +1,167 @@
+//! Client side of the firebird authentication plugins (Wire Protocol 13)
+
+use bytes::Bytes;
+use std::sync::Arc;
+
+use crate::{
+    srp::*,
+    wire::{legacy_auth_hash, parse_srp_auth_data},
+};
+use rsfbclient_core::FbError;
+
+/// Client side of a firebird authentication plugin.
+///
+/// A new instance is created for each connection, so it can keep the
+/// state of the authentication
+pub trait AuthPlugin: Send {
+    /// Plugin name, must be the same as the server side plugin
+    fn name(&self) -> &str;
+
+    /// Data to start the authentication. Sent in the connection
+    /// request for the preferred plugin, or when the server requests
+    /// the plugin
+    fn initial_data(&mut self, user: &str, pass: &str) -> Result<Vec<u8>, FbError>;
+
+    /// Process the data sent by the server, returning the data to send back
+    fn continue_auth(
+        &mut self,
+        user: &str,
+        pass: &str,
+        server_data: &[u8],
+    ) -> Result<Vec<u8>, FbError>;
+
+    /// Key generated by the authentication, used in the wire encryption.
+    /// Only called after the server accepted the authentication
+    fn session_key(&self) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// Creates the authentication plugin for a new connection
+pub type AuthPluginFactory = Arc<dyn Fn() -> Box<dyn AuthPlugin> + Send + Sync>;
+
+/// Default authentication plugins, in order of preference
+pub fn default_auth_plugins() -> Vec<AuthPluginFactory> {
+    vec![
+        Arc::new(|| Box::new(Srp::sha1())),
+        Arc::new(|| Box::new(Srp::sha256())),
+        Arc::new(|| Box::new(LegacyAuth)),
+    ]
+}
+
+/// Secure remote password authentication (`Srp` and `Srp256` plugins)
+pub struct Srp {
+    /// Uses Sha256 for the proof (`Srp256`), instead of Sha1 (`Srp`)
+    sha256: bool,
+    /// Random private key
+    key: [u8; 32],
+    session_key: Option<Vec<u8>>,
+}
+
+impl Srp {
+    /// `Srp` plugin
+    pub fn sha1() -> Self {
+        Self {
+            sha256: false,
+            key: rand::random(),
+            session_key: None,
+        }
+    }
+
+    /// `Srp256` plugin
+    pub fn sha256() -> Self {
+        Self {
+            sha256: true,
+            ..Self::sha1()
+        }
+    }
+
+    /// Generates the proof with the salt and the public key received
+    /// from the server, storing the session key
+    fn proof<D: digest::Digest>(
+        &mut self,
+        user: &str,
+        pass: &str,
+        server_data: &[u8],
+    ) -> Result<Vec<u8>, FbError> {
+        let data = parse_srp_auth_data(&mut Bytes::copy_from_slice(server_data))?
+            .ok_or_else(|| FbError::from("Missing the srp data from the server"))?;
+
+        let srp = SrpClient::<D>::new(&self.key, &SRP_GROUP);
+
+        // Generate a private key with the salt received from the server
+        let private_key =
+            srp_private_key::<sha1::Sha1>(user.as_bytes(), pass.as_bytes(), &data.salt);
+
+        // Generate a verified with the private key above and the server public key received
+        let verifier = srp
+            .process_reply(user.as_bytes(), &data.salt, &private_key, &data.pub_key)
+            .map_err(|e| FbError::from(format!("Srp error: {}", e)))?;
+
+        // Generate a proof to send to the server so it can verify the password
+        let proof = hex::encode(verifier.get_proof());
+
+        self.session_key = Some(verifier.get_key().to_vec());
+
+        Ok(proof.into_bytes())
+    }
+}
+
+impl AuthPlugin for Srp {
+    fn name(&self) -> &str {
+        if self.sha256 {
+            "Srp256"
+        } else {
+            "Srp"
+        }
+    }
+
+    fn initial_data(&mut self, _user: &str, _pass: &str) -> Result<Vec<u8>, FbError> {
+        // The public key doesn't depend on the hash used for the proof
+        let srp = SrpClient::<sha1::Sha1>::new(&self.key, &SRP_GROUP);
+
+        Ok(hex::encode(srp.get_a_pub()).into_bytes())
+    }
+
+    fn continue_auth(
+        &mut self,
+        user: &str,
+        pass: &str,
+        server_data: &[u8],
+    ) -> Result<Vec<u8>, FbError> {
+        if self.sha256 {
+            self.proof::<sha2::Sha256>(user, pass, server_data)
+        } else {
+            self.proof::<sha1::Sha1>(user, pass, server_data)
+        }
+    }
+
+    fn session_key(&self) -> Option<Vec<u8>> {
+        self.session_key.clone()
+    }
+}
+
+/// Authentication with the password hash (`Legacy_Auth` plugin),
+/// used by the users created with the `Legacy_UserManager`.
+///
+/// Doesn't generate a session key, so the wire encryption is not available
+pub struct LegacyAuth;
+
+impl AuthPlugin for LegacyAuth {
+    fn name(&self) -> &str {
+        "Legacy_Auth"
+    }
+
+    fn initial_data(&mut self, _user: &str, pass: &str) -> Result<Vec<u8>, FbError> {
+        Ok(legacy_auth_hash(pass)?.into_bytes())
+    }
+
+    fn continue_auth(
+        &mut self,
+        _user: &str,
+        _pass: &str,
+        _server_data: &[u8],
+    ) -> Result<Vec<u8>, FbError> {
+        Err("The Legacy_Auth plugin doesn't support continuing the authentication".into())
+    }
+}
@@ -1,6 +1,9 @@
-use crate::{client::FirebirdWireConnection, consts};
+use crate::{
+    client::{BlobId, FirebirdWireConnection},
+    consts,
+};
 use bytes::{BufMut, Bytes, BytesMut};
-use rsfbclient_core::{FbError, SqlType};
+use rsfbclient_core::{Charset, FbError, SqlType};
 
 /// Maximum parameter data length
 pub const MAX_DATA_LENGTH: usize = 32767;
@@ -19,6 +22,24 @@ pub fn params_to_blr(
     conn: &mut FirebirdWireConnection,
     tr_handle: &mut crate::TrHandle,
     params: &[SqlType],
+) -> Result<ParamsBlr, FbError> {
+    let version = conn.version;
+    let charset = conn.charset.clone();
+
+    encode_params(version, &charset, params, |data| {
+        create_blob(conn, tr_handle, data)
+    })
+}
+
+/// Convert the parameters to a blr (binary representation), without any I/O.
+///
+/// The data too large to be sent inline is passed to `create_blob`,
+/// in the parameters order, that must return the id of a blob with the data
+pub fn encode_params(
+    version: consts::ProtocolVersion,
+    charset: &Charset,
+    params: &[SqlType],
+    mut create_blob: impl FnMut(&[u8]) -> Result<BlobId, FbError>,
 ) -> Result<ParamsBlr, FbError> {
     let mut blr = BytesMut::with_capacity(256);
     let mut values = BytesMut::with_capacity(256);
@@ -32,21 +53,14 @@ pub fn params_to_blr(
     // Message length, * 2 as there is 1 msg for the param type and another for the nullind
     blr.put_u16_le(params.len() as u16 * 2);
 
-    if conn.version >= consts::ProtocolVersion::V13 {
+    if version >= consts::ProtocolVersion::V13 {
         // Insert a null indicator bitmap
         null_bitmap(&mut values, params);
     }
 
     // Handle blob creation and blr conversion
-    let mut handle_blob = |conn: &mut FirebirdWireConnection,
-                           blr: &mut BytesMut,
-                           values: &mut BytesMut,
-                           data: &[u8]| {
-        let (blob_handle, id) = conn.create_blob(tr_handle)?;
-
-        conn.put_segments(blob_handle, &data)?;
-
-        conn.close_blob(blob_handle)?;
+    let mut handle_blob = |blr: &mut BytesMut, values: &mut BytesMut, data: &[u8]| {
+        let id = create_blob(data)?;
 
         blr.put_u8(consts::blr::QUAD);
         blr.put_u8(0); // Blob type
@@ -59,10 +73,10 @@ pub fn params_to_blr(
     for p in params {
         match p {
             SqlType::Text(s) => {
-                let bytes = conn.charset.encode(s)?;
+                let bytes = charset.encode(s)?;
                 if bytes.len() > MAX_DATA_LENGTH {
                     // Data too large, send as blob
-                    handle_blob(conn, &mut blr, &mut values, &bytes)?;
+                    handle_blob(&mut blr, &mut values, &bytes)?;
                 } else {
                     blr.put_u8(consts::blr::TEXT);
                     blr.put_u16_le(bytes.len() as u16);
@@ -70,12 +84,12 @@ pub fn params_to_blr(
                     values.put_slice(&bytes);
                     if bytes.len() % 4 != 0 {
                         // 4 byte align
-                        values.put_slice(&[0; 4][..4 - (bytes.len() as usize % 4)])
+                        values.put_slice(&[0; 4][..4 - (bytes.len() % 4)])
                     }
                 }
             }
 
-            SqlType::Binary(data) => handle_blob(conn, &mut blr, &mut values, &data)?,
+            SqlType::Binary(data) => handle_blob(&mut blr, &mut values, data)?,
 
             SqlType::Integer(i) => {
                 blr.put_slice(&[
@@ -114,7 +128,7 @@ pub fn params_to_blr(
             }
         }
 
-        if conn.version < consts::ProtocolVersion::V13 {
+        if version < consts::ProtocolVersion::V13 {
             // Null indicator
             values.put_i32_le(if p.is_null() { -1 } else { 0 });
         }
@@ -131,6 +145,251 @@ pub fn params_to_blr(
     })
 }
 
+/// Create a blob with the data, returning its id
+fn create_blob(
+    conn: &mut FirebirdWireConnection,
+    tr_handle: &mut crate::TrHandle,
+    data: &[u8],
+) -> Result<BlobId, FbError> {
+    let (blob_handle, id) = conn.create_blob(tr_handle)?;
+
+    conn.put_segments(blob_handle, &data)?;
+
+    conn.close_blob(blob_handle)?;
+
+    Ok(id)
+}
+
+#[derive(Debug)]
+/// Data for the parameter sets of a batch, all using the same message format
+pub struct BatchParamsBlr {
+    /// Definitions of the data types, common to all messages
+    pub(crate) blr: Bytes,
+    /// Length of a message in the server memory, as described by the blr
+    pub(crate) msg_len: u32,
+    /// Actual values of each parameter set
+    pub(crate) messages: Vec<BatchMessage>,
+}
+
+#[derive(Debug)]
+/// Values of a parameter set of a batch
+pub struct BatchMessage {
+    /// Actual values of the data
+    pub(crate) values: Bytes,
+    /// Blobs created for the values, need to be registered in the batch
+    pub(crate) blobs: Vec<BlobId>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Data type of a batch parameter, common to all parameter sets
+enum BatchParamType {
+    /// Null in all parameter sets
+    Null,
+    /// Text with the maximum length
+    Text(usize),
+    Blob,
+    Integer,
+    Floating,
+    #[cfg(feature = "date_time")]
+    Timestamp,
+    Boolean,
+}
+
+impl BatchParamType {
+    /// Data type needed to represent the value and the values already seen
+    fn merge(self, conn: &FirebirdWireConnection, p: &SqlType) -> Result<Self, FbError> {
+        use BatchParamType::*;
+
+        let ty = match p {
+            SqlType::Null => return Ok(self),
+            SqlType::Text(s) => {
+                let len = conn.charset.encode(s)?.len();
+
+                if len > MAX_DATA_LENGTH {
+                    Blob
+                } else {
+                    Text(len)
+                }
+            }
+            SqlType::Binary(_) => Blob,
+            SqlType::Integer(_) => Integer,
+            SqlType::Floating(_) => Floating,
+            #[cfg(feature = "date_time")]
+            SqlType::Timestamp(_) => Timestamp,
+            SqlType::Boolean(_) => Boolean,
+        };
+
+        Ok(match (self, ty) {
+            (Null, ty) => ty,
+            (Text(l1), Text(l2)) => Text(l1.max(l2)),
+            // Texts can be sent as blobs
+            (Text(_), Blob) | (Blob, Text(_)) => Blob,
+            (current, ty) if current == ty => ty,
+            _ => return Err("Parameter has different types between the parameter sets".into()),
+        })
+    }
+
+    /// Blr of the data type, returning its alignment and length in the server memory
+    fn put_blr(self, blr: &mut BytesMut) -> (usize, usize) {
+        use BatchParamType::*;
+
+        match self {
+            Null => {
+                // Represent as empty text
+                blr.put_u8(consts::blr::TEXT);
+                blr.put_u16_le(0);
+                (1, 0)
+            }
+            Text(len) => {
+                blr.put_u8(consts::blr::VARYING);
+                blr.put_u16_le(len as u16);
+                (2, len + 2)
+            }
+            Blob => {
+                blr.put_u8(consts::blr::QUAD);
+                blr.put_u8(0); // Blob type
+                (4, 8)
+            }
+            Integer => {
+                blr.put_slice(&[
+                    consts::blr::INT64,
+                    0, // Scale
+                ]);
+                (8, 8)
+            }
+            Floating => {
+                blr.put_u8(consts::blr::DOUBLE);
+                (8, 8)
+            }
+            #[cfg(feature = "date_time")]
+            Timestamp => {
+                blr.put_u8(consts::blr::TIMESTAMP);
+                (4, 8)
+            }
+            Boolean => {
+                blr.put_u8(consts::blr::BOOL);
+                (1, 1)
+            }
+        }
+    }
+}
+
+/// Convert the parameter sets of a batch to a blr (binary representation) common to all of them.
+///
+/// Only for the protocol 13 and up, as the null indicators are sent in a bitmap
+pub fn batch_params_to_blr(
+    conn: &mut FirebirdWireConnection,
+    tr_handle: &mut crate::TrHandle,
+    params: &[Vec<SqlType>],
+) -> Result<BatchParamsBlr, FbError> {
+    let param_count = params.first().map(|p| p.len()).unwrap_or(0);
+
+    let mut types = vec![BatchParamType::Null; param_count];
+    for set in params {
+        for (i, (ty, p)) in types.iter_mut().zip(set).enumerate() {
+            *ty = ty
+                .merge(conn, p)
+                .map_err(|e| FbError::from(format!("Batch parameter {}: {}", i + 1, e)))?;
+        }
+    }
+
+    let mut blr = BytesMut::with_capacity(256);
+
+    blr.put_slice(&[
+        consts::blr::VERSION5,
+        consts::blr::BEGIN,
+        consts::blr::MESSAGE,
+        0, // Message index
+    ]);
+    // Message length, * 2 as there is 1 msg for the param type and another for the nullind
+    blr.put_u16_le(param_count as u16 * 2);
+
+    let mut msg_len = 0;
+    for ty in &types {
+        let (align, len) = ty.put_blr(&mut blr);
+        msg_len = align_to(msg_len, align) + len;
+
+        // Null indicator type
+        blr.put_slice(&[consts::blr::SHORT, 0]);
+        msg_len = align_to(msg_len, 2) + 2;
+    }
+
+    blr.put_slice(&[consts::blr::END, consts::blr::EOC]);
+
+    let mut messages = Vec::with_capacity(params.len());
+    for set in params {
+        let mut values = BytesMut::with_capacity(256);
+        let mut blobs = Vec::new();
+
+        null_bitmap(&mut values, set);
+
+        for (ty, p) in types.iter().zip(set) {
+            match (ty, p) {
+                (_, SqlType::Null) => {}
+
+                (BatchParamType::Text(_), SqlType::Text(s)) => {
+                    let bytes = conn.charset.encode(s)?;
+
+                    values.put_u32(bytes.len() as u32);
+                    values.put_slice(&bytes);
+                    if bytes.len() % 4 != 0 {
+                        // 4 byte align
+                        values.put_slice(&[0; 4][..4 - (bytes.len() % 4)])
+                    }
+                }
+
+                (BatchParamType::Blob, SqlType::Text(s)) => {
+                    let bytes = conn.charset.encode(s)?;
+                    let id = create_blob(conn, tr_handle, &bytes)?;
+
+                    values.put_u64(id.0);
+                    blobs.push(id);
+                }
+
+                (BatchParamType::Blob, SqlType::Binary(data)) => {
+                    let id = create_blob(conn, tr_handle, &data)?;
+
+                    values.put_u64(id.0);
+                    blobs.push(id);
+                }
+
+                (_, SqlType::Integer(i)) => values.put_i64(*i),
+
+                (_, SqlType::Floating(f)) => values.put_f64(*f),
+
+                #[cfg(feature = "date_time")]
+                (_, SqlType::Timestamp(dt)) => {
+                    let ts = rsfbclient_core::date_time::encode_timestamp(*dt);
+                    values.put_i32(ts.timestamp_date);
+                    values.put_u32(ts.timestamp_time);
+                }
+
+                (_, SqlType::Boolean(b)) => {
+                    values.put_slice(if *b { &[1, 0, 0, 0] } else { &[0, 0, 0, 0] })
+                }
+
+                _ => unreachable!("Batch parameter types already checked"),
+            }
+        }
+
+        messages.push(BatchMessage {
+            values: values.freeze(),
+            blobs,
+        });
+    }
+
+    Ok(BatchParamsBlr {
+        blr: blr.freeze(),
+        msg_len: msg_len as u32,
+        messages,
+    })
+}
+
+/// Round up the offset to the alignment, that must be a power of 2
+pub fn align_to(offset: usize, align: usize) -> usize {
+    (offset + align - 1) & !(align - 1)
+}
+
 /// Create a null indicator bitmap and insert into the `values`
 ///
 /// The bitmap is a list of bytes,
@@ -0,0 +1,234 @@
+//! ChaCha20 stream cipher implementation for the firebird wire encryption (ChaCha and ChaCha64 plugins)
+
+use std::io::{Read, Write};
+
+use rsfbclient_core::FbError;
+
+/// "expand 32-byte k"
+const SIGMA: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+
+#[derive(Clone)]
+pub struct ChaCha {
+    state: [u32; 16],
+    /// Keystream of the current block
+    block: [u8; 64],
+    /// Position of the next keystream byte in the block
+    pos: usize,
+    /// Uses a 64 bit block counter (original variant), instead of 32 bit (RFC 7539)
+    counter64: bool,
+}
+
+impl ChaCha {
+    /// Create a new cipher. A 12 bytes nonce uses a 32 bit block counter,
+    /// as in the RFC 7539, and a 8 bytes nonce uses a 64 bit block counter
+    pub fn new(key: &[u8; 32], nonce: &[u8], counter: u64) -> Result<ChaCha, FbError> {
+        let mut state = [0; 16];
+        state[..4].copy_from_slice(&SIGMA);
+
+        for (i, k) in key.chunks(4).enumerate() {
+            state[4 + i] = u32::from_le_bytes([k[0], k[1], k[2], k[3]]);
+        }
+
+        let counter64 = match nonce.len() {
+            12 => {
+                state[12] = counter as u32;
+                false
+            }
+            8 => {
+                state[12] = counter as u32;
+                state[13] = (counter >> 32) as u32;
+                true
+            }
+            len => return Err(format!("Invalid ChaCha nonce length: {}", len).into()),
+        };
+
+        let nonce_start = 16 - nonce.len() / 4;
+        for (i, n) in nonce.chunks(4).enumerate() {
+            state[nonce_start + i] = u32::from_le_bytes([n[0], n[1], n[2], n[3]]);
+        }
+
+        Ok(ChaCha {
+            state,
+            block: [0; 64],
+            pos: 64,
+            counter64,
+        })
+    }
+
+    /// Generates the keystream for the current block and advances the counter
+    fn next_block(&mut self) {
+        let mut x = self.state;
+
+        for _ in 0..10 {
+            // Column rounds
+            quarter_round(&mut x, 0, 4, 8, 12);
+            quarter_round(&mut x, 1, 5, 9, 13);
+            quarter_round(&mut x, 2, 6, 10, 14);
+            quarter_round(&mut x, 3, 7, 11, 15);
+            // Diagonal rounds
+            quarter_round(&mut x, 0, 5, 10, 15);
+            quarter_round(&mut x, 1, 6, 11, 12);
+            quarter_round(&mut x, 2, 7, 8, 13);
+            quarter_round(&mut x, 3, 4, 9, 14);
+        }
+
+        for (i, (x, s)) in x.iter().zip(self.state.iter()).enumerate() {
+            self.block[i * 4..i * 4 + 4].copy_from_slice(&x.wrapping_add(*s).to_le_bytes());
+        }
+
+        self.state[12] = self.state[12].wrapping_add(1);
+        if self.counter64 && self.state[12] == 0 {
+            self.state[13] = self.state[13].wrapping_add(1);
+        }
+
+        self.pos = 0;
+    }
+
+    fn process(&mut self, input: &[u8], output: &mut [u8]) {
+        assert!(input.len() == output.len());
+        for (x, y) in input.iter().zip(output.iter_mut()) {
+            if self.pos == self.block.len() {
+                self.next_block();
+            }
+            *y = *x ^ self.block[self.pos];
+            self.pos += 1;
+        }
+    }
+}
+
+fn quarter_round(x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(16);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(12);
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(8);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(7);
+}
+
+/// Wraps a stream, encoding / decoding the data
+pub struct ChaChaStream<S> {
+    read_chacha: Box<ChaCha>,
+    write_chacha: Box<ChaCha>,
+    enc_buf: Box<[u8]>,
+    stream: S,
+}
+
+impl<S> ChaChaStream<S> {
+    pub fn new(stream: S, chacha: ChaCha, buf_len: usize) -> Self {
+        Self {
+            read_chacha: Box::new(chacha.clone()),
+            write_chacha: Box::new(chacha),
+            enc_buf: vec![0; buf_len].into_boxed_slice(),
+            stream,
+        }
+    }
+}
+
+impl<S: Read> Read for ChaChaStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let Self {
+            read_chacha,
+            enc_buf,
+            stream,
+            ..
+        } = self;
+
+        let max_len = buf.len().min(enc_buf.len());
+        // Read to the encrypted buffer
+        let len = stream.read(&mut enc_buf[..max_len])?;
+        // Decrypt
+        read_chacha.process(&enc_buf[..len], &mut buf[..len]);
+
+        Ok(len)
+    }
+}
+
+impl<S: Write> Write for ChaChaStream<S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let Self {
+            write_chacha,
+            enc_buf,
+            stream,
+            ..
+        } = self;
+
+        let max_len = buf.len().min(enc_buf.len());
+        // Encrypt
+        write_chacha.process(&buf[..max_len], &mut enc_buf[..max_len]);
+        // Write all the encrypted data, as the keystream already advanced
+        stream.write_all(&enc_buf[..max_len])?;
+
+        Ok(max_len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+#[test]
+fn chacha_test() {
+    // RFC 7539, section 2.4.2
+    let mut key = [0; 32];
+    for (i, k) in key.iter_mut().enumerate() {
+        *k = i as u8;
+    }
+    let nonce = [0, 0, 0, 0, 0, 0, 0, 0x4a, 0, 0, 0, 0];
+
+    let plain = b"Ladies and Gentlemen of the class of '99: If I";
+    let enc = &mut [0; 46];
+
+    let mut c1 = ChaCha::new(&key, &nonce, 1).unwrap();
+    // Split, to check the keystream position between calls
+    c1.process(&plain[..10], &mut enc[..10]);
+    c1.process(&plain[10..], &mut enc[10..]);
+    assert_eq!(
+        &enc[..],
+        &b"\x6e\x2e\x35\x9a\x25\x68\xf9\x80\x41\xba\x07\x28\xdd\x0d\x69\x81\
+           \xe9\x7e\x7a\xec\x1d\x43\x60\xc2\x0a\x27\xaf\xcc\xfd\x9f\xae\x0b\
+           \xf9\x1b\x65\xc5\x52\x47\x33\xab\x8f\x59\x3d\xab\xcd\x62"[..]
+    );
+
+    let mut c2 = ChaCha::new(&key, &nonce, 1).unwrap();
+    let dec = &mut [0; 46];
+    c2.process(enc, dec);
+    assert_eq!(&dec[..], &plain[..]);
+
+    // Zero key and nonce, 64 bit counter
+    let mut c3 = ChaCha::new(&[0; 32], &[0; 8], 0).unwrap();
+    let keystream = &mut [0; 8];
+    c3.process(&[0; 8], keystream);
+    assert_eq!(keystream, b"\x76\xb8\xe0\xad\xa0\xf1\x3d\x90");
+
+    assert!(ChaCha::new(&key, &[0; 16], 0).is_err());
+}
+
+#[test]
+fn chacha_short_write_test() {
+    /// Accepts at most 3 bytes in each write
+    struct ShortWriter(Vec<u8>);
+
+    impl Write for ShortWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let len = buf.len().min(3);
+            self.0.extend(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let chacha = ChaCha::new(&[1; 32], &[2; 12], 0).unwrap();
+    let plain = b"a message longer than the writes";
+
+    let mut stream = ChaChaStream::new(ShortWriter(vec![]), chacha.clone(), 16);
+    stream.write_all(plain).unwrap();
+
+    let mut dec = vec![0; plain.len()];
+    chacha.clone().process(&stream.stream.0, &mut dec);
+    assert_eq!(&dec[..], &plain[..]);
+}
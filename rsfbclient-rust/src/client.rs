@@ -2,23 +2,30 @@
 
 use bytes::{BufMut, Bytes, BytesMut};
 use std::{
+    collections::VecDeque,
     env,
-    io::{Read, Write},
-    net::TcpStream,
+    io::{self, Read, Write},
+    mem,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
 };
 
 use crate::{
     arc4::*,
+    auth::{default_auth_plugins, AuthPlugin, AuthPluginFactory},
     blr,
-    consts::{AuthPluginType, ProtocolVersion, WireOp},
-    srp::*,
+    chacha::*,
+    consts::{crypt_keys, ProtocolVersion, WireOp},
+    transport::{open_transport, SplitTransport, TransportConnector},
     util::*,
     wire::*,
     xsqlda::{parse_xsqlda, xsqlda_to_blr, PrepareInfo, XSqlVar},
+    zlib::ZlibStream,
 };
 use rsfbclient_core::{
-    ibase, Charset, Column, Dialect, FbError, FirebirdClientDbOps, FirebirdClientSqlOps,
-    FreeStmtOp, SqlType, StmtType, TrIsolationLevel, TrOp,
+    err_batch_unsupported, ibase, BatchRowState, CancelKind, Charset, Column, Dialect, Dpb,
+    FbError, FirebirdClientCancel, FirebirdClientDbOps, FirebirdClientSqlOps, FreeStmtOp, SqlType,
+    StmtType, TrIsolationLevel, TrOp, WireCrypt, WireCryptPlugin,
 };
 
 type RustDbHandle = DbHandle;
@@ -31,14 +38,81 @@ pub struct RustFbClient {
     charset: Charset,
 }
 
+/// Default number of rows requested from the server in each fetch
+pub const DEFAULT_FETCH_SIZE: u32 = 400;
+
+/// Size of the buffer used by the server to store the messages of a batch
+const BATCH_BUFFER_SIZE: u32 = 16 * 1024 * 1024;
+
 /// Required configuration for an attachment with the pure rust client
-#[derive(Default, Clone)]
+#[derive(Clone)]
 pub struct RustFbClientAttachmentConfig {
     pub host: String,
     pub port: u16,
     pub db_name: String,
     pub user: String,
     pub pass: String,
+    /// Number of rows requested from the server in each fetch
+    pub fetch_size: u32,
+    /// Request the zlib compression of the network traffic
+    pub wire_compression: bool,
+    /// Wire encryption level
+    pub wire_crypt: WireCrypt,
+    /// Wire encryption plugins, in order of preference
+    pub wire_crypt_plugins: Vec<WireCryptPlugin>,
+    /// Authentication plugins, in order of preference
+    pub auth_plugins: Vec<AuthPluginFactory>,
+    /// Opens the stream to the server, instead of a tcp connection
+    pub transport: Option<TransportConnector>,
+    /// Maximum time to establish the tcp connection
+    pub connect_timeout: Option<Duration>,
+    /// Maximum time for each read or write in the tcp connection
+    pub io_timeout: Option<Duration>,
+    /// Idle time before sending the tcp keepalive probes
+    pub keepalive: Option<Duration>,
+    /// Items of the database parameter buffer, like the role
+    pub dpb: Dpb,
+}
+
+impl Default for RustFbClientAttachmentConfig {
+    fn default() -> Self {
+        Self {
+            host: Default::default(),
+            port: Default::default(),
+            db_name: Default::default(),
+            user: Default::default(),
+            pass: Default::default(),
+            fetch_size: DEFAULT_FETCH_SIZE,
+            wire_compression: false,
+            wire_crypt: WireCrypt::default(),
+            wire_crypt_plugins: WireCryptPlugin::DEFAULT.to_vec(),
+            auth_plugins: default_auth_plugins(),
+            transport: None,
+            connect_timeout: None,
+            io_timeout: None,
+            keepalive: None,
+            dpb: Dpb::default(),
+        }
+    }
+}
+
+/// Database parameters of the configuration not sent by the `attach`. The
+/// `create` has the page size and dialect of a new database
+pub(crate) fn extra_dpb(
+    config: &RustFbClientAttachmentConfig,
+    create: Option<(Option<u32>, Dialect)>,
+) -> Result<Vec<u8>, FbError> {
+    let mut dpb = config.dpb.clone();
+
+    if let Some((page_size, dialect)) = create {
+        if let Some(page_size) = page_size {
+            dpb.custom_int(ibase::isc_dpb_page_size, page_size);
+        }
+
+        dpb.custom_int(ibase::isc_dpb_sql_dialect, dialect as u32);
+    }
+
+    dpb.to_bytes()
 }
 
 /// A Connection to a firebird server
@@ -52,19 +126,124 @@ pub struct FirebirdWireConnection {
     /// Buffer to read the network data
     buff: Box<[u8]>,
 
+    /// Data received from the server, but not yet parsed
+    recv: Bytes,
+
+    /// Server accepted deferred requests
+    lazy_send: bool,
+
+    /// Number of deferred requests that still need to have the response read
+    deferred: usize,
+
     pub(crate) charset: Charset,
+
+    /// Default number of rows requested in each fetch for new statements
+    pub(crate) fetch_size: u32,
 }
 
 /// Data to keep track about a prepared statement
 pub struct StmtHandleData {
     /// Statement handle
-    handle: RustStmtHandle,
+    pub(crate) handle: RustStmtHandle,
     /// Output xsqlda
-    xsqlda: Vec<XSqlVar>,
+    pub(crate) xsqlda: Vec<XSqlVar>,
     /// Blr representation of the above
-    blr: Bytes,
+    pub(crate) blr: Bytes,
     /// Number of parameters
-    param_count: usize,
+    pub(crate) param_count: usize,
+    /// Number of rows requested in each fetch
+    pub(crate) fetch_size: u32,
+    /// Timeout of the executions in milliseconds, 0 for the timeout of the session
+    pub(crate) timeout: u32,
+    /// Rows already received from the server, but not yet fetched
+    pub(crate) rows: VecDeque<Vec<ParsedColumn>>,
+    /// All rows of the cursor were received
+    pub(crate) end_of_cursor: bool,
+    /// Rows were requested from the server for the current cursor
+    pub(crate) fetched: bool,
+    /// A cursor name was set, so the rows must be fetched one at a time
+    pub(crate) named_cursor: bool,
+}
+
+impl StmtHandleData {
+    /// Data of a statement prepared with the `xsqlda` received,
+    /// coercing the output columns to the types supported
+    pub(crate) fn new(
+        handle: RustStmtHandle,
+        mut xsqlda: Vec<XSqlVar>,
+        param_count: usize,
+        fetch_size: u32,
+    ) -> Result<Self, FbError> {
+        // Coerce the output columns and transform to blr
+        for var in xsqlda.iter_mut() {
+            var.coerce()?;
+        }
+        let blr = xsqlda_to_blr(&xsqlda)?;
+
+        Ok(Self {
+            handle,
+            xsqlda,
+            blr,
+            param_count,
+            fetch_size,
+            timeout: 0,
+            rows: VecDeque::new(),
+            end_of_cursor: false,
+            fetched: false,
+            named_cursor: false,
+        })
+    }
+
+    /// Returns an error if the number of `params` is not the expected by the statement
+    pub(crate) fn check_param_count(&self, params: &[SqlType]) -> Result<(), FbError> {
+        if params.len() != self.param_count {
+            return Err(format!(
+                "Tried to execute a statement that has {} parameters while providing {}",
+                self.param_count,
+                params.len()
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Set the number of rows requested in each fetch. Named cursors
+    /// keep fetching one row at a time, see `set_named_cursor`
+    pub(crate) fn set_fetch_size(&mut self, fetch_size: u32) {
+        self.fetch_size = if self.named_cursor {
+            1
+        } else {
+            fetch_size.max(1)
+        };
+    }
+
+    /// Returns an error if rows were already requested for the current cursor,
+    /// as the current row in the server may be ahead of the last row fetched
+    pub(crate) fn check_cursor_name(&self) -> Result<(), FbError> {
+        if self.fetched {
+            return Err(
+                "The cursor name must be set before fetching the rows of the cursor".into(),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Mark the cursor as named, fetching one row at a time from now on, so the current
+    /// row of the cursor in the server is always the last row fetched, as expected by
+    /// the `WHERE CURRENT OF` statements
+    pub(crate) fn set_named_cursor(&mut self) {
+        self.named_cursor = true;
+        self.fetch_size = 1;
+    }
+
+    /// Discard the rows received for the current cursor
+    pub(crate) fn reset_rows(&mut self) {
+        self.rows.clear();
+        self.end_of_cursor = false;
+        self.fetched = false;
+    }
 }
 
 impl RustFbClient {
@@ -85,26 +264,25 @@ impl FirebirdClientDbOps for RustFbClient {
         &mut self,
         config: &Self::AttachmentConfig,
     ) -> Result<RustDbHandle, FbError> {
-        let host = config.host.as_str();
-        let port = config.port;
         let db_name = config.db_name.as_str();
         let user = config.user.as_str();
         let pass = config.pass.as_str();
 
-        // Take the existing connection, or connects
+        // Take the existing connection, or connects if there is none or it is broken
         let mut conn = match self.conn.take() {
-            Some(conn) => conn,
-            None => FirebirdWireConnection::connect(
-                host,
-                port,
-                db_name,
-                user,
-                pass,
-                self.charset.clone(),
-            )?,
+            Some(conn) if !conn.is_broken() => conn,
+            _ => FirebirdWireConnection::connect(config, self.charset.clone())?,
         };
 
-        let attach_result = conn.attach_database(db_name, user, pass);
+        conn.fetch_size = config.fetch_size;
+
+        let attach_result = conn.attach_or_create(
+            WireOp::Attach,
+            db_name,
+            user,
+            pass,
+            &extra_dpb(config, None)?,
+        );
 
         // Put the connection back
         self.conn.replace(conn);
@@ -112,6 +290,32 @@ impl FirebirdClientDbOps for RustFbClient {
         attach_result
     }
 
+    fn create_database(
+        &mut self,
+        config: &Self::AttachmentConfig,
+        page_size: Option<u32>,
+        dialect: Dialect,
+    ) -> Result<RustDbHandle, FbError> {
+        let mut conn = match self.conn.take() {
+            Some(conn) if !conn.is_broken() => conn,
+            _ => FirebirdWireConnection::connect(config, self.charset.clone())?,
+        };
+
+        conn.fetch_size = config.fetch_size;
+
+        let create_result = conn.attach_or_create(
+            WireOp::Create,
+            &config.db_name,
+            &config.user,
+            &config.pass,
+            &extra_dpb(config, Some((page_size, dialect)))?,
+        );
+
+        self.conn.replace(conn);
+
+        create_result
+    }
+
     fn detach_database(&mut self, db_handle: &mut RustDbHandle) -> Result<(), FbError> {
         self.conn
             .as_mut()
@@ -125,6 +329,31 @@ impl FirebirdClientDbOps for RustFbClient {
             .map(|conn| conn.drop_database(db_handle))
             .unwrap_or_else(err_client_not_connected)
     }
+
+    fn cancel_handle(
+        &mut self,
+        _db_handle: &mut RustDbHandle,
+    ) -> Result<Box<dyn FirebirdClientCancel>, FbError> {
+        self.conn
+            .as_ref()
+            .map(|conn| Ok(conn.cancel_handle()))
+            .unwrap_or_else(err_client_not_connected)
+    }
+
+    fn ping(&mut self, db_handle: &mut RustDbHandle) -> Result<(), FbError> {
+        self.conn
+            .as_mut()
+            .map(|conn| conn.ping(db_handle))
+            .unwrap_or_else(err_client_not_connected)
+    }
+
+    fn is_broken(&self) -> bool {
+        // Without a connection the client can't be used
+        self.conn
+            .as_ref()
+            .map(|conn| conn.is_broken())
+            .unwrap_or(true)
+    }
 }
 
 impl FirebirdClientSqlOps for RustFbClient {
@@ -191,6 +420,40 @@ impl FirebirdClientSqlOps for RustFbClient {
             .unwrap_or_else(err_client_not_connected)
     }
 
+    fn set_cursor_name(
+        &mut self,
+        stmt_handle: &mut Self::StmtHandle,
+        cursor_name: &str,
+    ) -> Result<(), FbError> {
+        self.conn
+            .as_mut()
+            .map(|conn| conn.set_cursor_name(stmt_handle, cursor_name))
+            .unwrap_or_else(err_client_not_connected)
+    }
+
+    fn set_fetch_size(&mut self, stmt_handle: &mut Self::StmtHandle, fetch_size: u32) {
+        let fetch_size = match (fetch_size, &self.conn) {
+            (0, Some(conn)) => conn.fetch_size,
+            _ => fetch_size,
+        };
+
+        stmt_handle.set_fetch_size(fetch_size);
+    }
+
+    fn supports_statement_timeout(&self) -> bool {
+        matches!(&self.conn, Some(conn) if conn.version >= ProtocolVersion::V16)
+    }
+
+    fn set_statement_timeout(
+        &mut self,
+        stmt_handle: &mut Self::StmtHandle,
+        timeout: Option<Duration>,
+    ) {
+        stmt_handle.timeout = timeout
+            .map(|t| t.as_millis().min(u32::MAX as u128) as u32)
+            .unwrap_or(0);
+    }
+
     fn execute(
         &mut self,
         _db_handle: &mut Self::DbHandle,
@@ -228,6 +491,30 @@ impl FirebirdClientSqlOps for RustFbClient {
             .map(|conn| conn.fetch(tr_handle, stmt_handle))
             .unwrap_or_else(err_client_not_connected)
     }
+
+    fn affected_rows(&mut self, stmt_handle: &mut Self::StmtHandle) -> Result<u64, FbError> {
+        self.conn
+            .as_mut()
+            .map(|conn| conn.affected_rows(stmt_handle))
+            .unwrap_or_else(err_client_not_connected)
+    }
+
+    fn supports_batch(&self) -> bool {
+        matches!(&self.conn, Some(conn) if conn.version >= ProtocolVersion::V16)
+    }
+
+    fn execute_batch(
+        &mut self,
+        _db_handle: &mut Self::DbHandle,
+        tr_handle: &mut Self::TrHandle,
+        stmt_handle: &mut Self::StmtHandle,
+        params: Vec<Vec<SqlType>>,
+    ) -> Result<Vec<BatchRowState>, FbError> {
+        self.conn
+            .as_mut()
+            .map(|conn| conn.execute_batch(tr_handle, stmt_handle, &params))
+            .unwrap_or_else(err_client_not_connected)
+    }
 }
 
 fn err_client_not_connected<T>() -> Result<T, FbError> {
@@ -237,124 +524,92 @@ fn err_client_not_connected<T>() -> Result<T, FbError> {
 impl FirebirdWireConnection {
     /// Start a connection to the firebird server
     pub fn connect(
-        host: &str,
-        port: u16,
-        db_name: &str,
-        user: &str,
-        pass: &str,
+        config: &RustFbClientAttachmentConfig,
         charset: Charset,
     ) -> Result<Self, FbError> {
-        let socket = TcpStream::connect((host, port))?;
-
-        // System username
-        let username =
-            env::var("USER").unwrap_or_else(|_| env::var("USERNAME").unwrap_or_default());
-        let hostname = socket
-            .local_addr()
-            .map(|addr| addr.to_string())
-            .unwrap_or_default();
-
-        let mut socket = FbStream::Plain(socket);
-
-        // Random key for the srp
-        let srp_key: [u8; 32] = rand::random();
-
-        let req = connect(db_name, false, user, &username, &hostname, &srp_key);
-        socket.write_all(&req)?;
-        socket.flush()?;
+        let mut auth = Authenticator::new(config)?;
 
         // May be a bit too much
         let mut buff = vec![0; BUFFER_LENGTH as usize * 2].into_boxed_slice();
 
-        let len = socket.read(&mut buff)?;
-        let mut resp = Bytes::copy_from_slice(&buff[..len]);
+        let (mut socket, mut resp) =
+            connect_request(config, &mut buff, |hostname| auth.connect(config, hostname))?;
+
+        if resp.get(..4) == Some(&(WireOp::Reject as u32).to_be_bytes()[..]) {
+            // Servers older than Firebird 3 may reject the plugins data, so
+            // connect again with only the legacy protocols
+            let (legacy_socket, legacy_resp) = connect_request(config, &mut buff, |hostname| {
+                Authenticator::connect_legacy(config, hostname)
+            })?;
+
+            socket = legacy_socket;
+            resp = legacy_resp;
+        }
 
         let ConnectionResponse {
             version,
-            auth_plugin,
+            lazy_send,
+            compress,
+            auth_data,
+            keys,
         } = parse_accept(&mut resp)?;
 
-        if let Some(mut auth_plugin) = auth_plugin {
-            loop {
-                match auth_plugin.kind {
-                    plugin @ AuthPluginType::Srp => {
-                        let srp = SrpClient::<sha1::Sha1>::new(&srp_key, &SRP_GROUP);
-
-                        if let Some(data) = auth_plugin.data {
-                            socket = srp_auth(socket, &mut buff, srp, plugin, user, pass, data)?;
-
-                            // Authentication Ok
-                            break;
-                        } else {
-                            // Server requested a different authentication method than the client specified
-                            // in the initial connection
-
-                            socket.write_all(&cont_auth(
-                                hex::encode(srp.get_a_pub()).as_bytes(),
-                                plugin,
-                                AuthPluginType::plugin_list(),
-                                &[],
-                            ))?;
-                            socket.flush()?;
-
-                            let len = socket.read(&mut buff)?;
-                            let mut resp = Bytes::copy_from_slice(&buff[..len]);
-
-                            auth_plugin = parse_cont_auth(&mut resp)?;
-                        }
-                    }
-                    plugin @ AuthPluginType::Srp256 => {
-                        let srp = SrpClient::<sha2::Sha256>::new(&srp_key, &SRP_GROUP);
-
-                        if let Some(data) = auth_plugin.data {
-                            socket = srp_auth(socket, &mut buff, srp, plugin, user, pass, data)?;
-
-                            // Authentication Ok
-                            break;
-                        } else {
-                            // Server requested a different authentication method than the client specified
-                            // in the initial connection
-
-                            socket.write_all(&cont_auth(
-                                &hex::encode(srp.get_a_pub()).as_bytes(),
-                                plugin,
-                                AuthPluginType::plugin_list(),
-                                &[],
-                            ))?;
-                            socket.flush()?;
-
-                            let len = socket.read(&mut buff)?;
-                            let mut resp = Bytes::copy_from_slice(&buff[..len]);
-
-                            auth_plugin = parse_cont_auth(&mut resp)?;
-                        }
-                    }
-                }
-            }
+        let mut server_keys = ServerKeys::default();
+        parse_server_keys(&mut keys.clone(), &mut server_keys)?;
+
+        if compress {
+            // All the packets after the accept are compressed
+            socket.compress(buff.len());
+        }
+
+        let mut next_data = auth_data;
+
+        while let Some(auth_data) = next_data.take() {
+            socket.write_all(&auth.continue_auth(config, &auth_data)?)?;
+            socket.flush()?;
+
+            next_data = read_auth_result(&mut socket, &mut buff, &mut server_keys)?;
         }
 
+        let socket = enable_wire_crypt(
+            socket,
+            &mut buff,
+            auth.session_key().as_deref(),
+            &server_keys,
+            config,
+        )?;
+
         Ok(Self {
             socket,
             version,
             buff,
+            recv: Bytes::new(),
+            lazy_send,
+            deferred: 0,
             charset,
+            fetch_size: DEFAULT_FETCH_SIZE,
         })
     }
 
-    /// Connect to a database, returning a database handle
-    pub fn attach_database(
+    /// Connect to a database with the `WireOp::Attach`, or create it
+    /// with the `WireOp::Create`, returning a database handle
+    pub fn attach_or_create(
         &mut self,
+        op: WireOp,
         db_name: &str,
         user: &str,
         pass: &str,
+        extra_dpb: &[u8],
     ) -> Result<DbHandle, FbError> {
         self.socket.write_all(&attach(
+            op,
             db_name,
             user,
             pass,
             self.version,
             self.charset.clone(),
-        ))?;
+            extra_dpb,
+        )?)?;
         self.socket.flush()?;
 
         let resp = self.read_response()?;
@@ -362,6 +617,35 @@ impl FirebirdWireConnection {
         Ok(DbHandle(resp.handle))
     }
 
+    /// Create a handle to cancel the operations of the connection from other threads
+    pub fn cancel_handle(&self) -> Box<dyn FirebirdClientCancel> {
+        Box::new(RustCancelHandle {
+            writer: self.socket.writer.clone(),
+            cancellable: self.socket.cancellable,
+            version: self.version,
+        })
+    }
+
+    /// Check if the server is reachable. Uses the `op_ping` if
+    /// supported, or an empty database info request otherwise
+    pub fn ping(&mut self, db_handle: &mut DbHandle) -> Result<(), FbError> {
+        if self.version >= ProtocolVersion::V13 {
+            self.socket.write_all(&ping())?;
+        } else {
+            self.socket.write_all(&info_database_ping(db_handle.0))?;
+        }
+        self.socket.flush()?;
+
+        self.read_response()?;
+
+        Ok(())
+    }
+
+    /// `true` if an I/O error was found in the connection, so it can't be used anymore
+    pub fn is_broken(&self) -> bool {
+        self.socket.broken
+    }
+
     /// Disconnect from the database
     pub fn detach_database(&mut self, db_handle: &mut DbHandle) -> Result<(), FbError> {
         self.socket.write_all(&detach(db_handle.0))?;
@@ -443,37 +727,54 @@ impl FirebirdWireConnection {
         dialect: Dialect,
         sql: &str,
     ) -> Result<(StmtType, StmtHandleData), FbError> {
-        // Alloc statement
-        self.socket.write_all(&allocate_statement(db_handle.0))?;
-        // Prepare statement
-        self.socket.write_all(&prepare_statement(
-            tr_handle.0,
-            u32::MAX,
-            dialect as u32,
-            sql,
-            &self.charset,
-        )?)?;
-        self.socket.flush()?;
+        let (stmt_handle, prepare_resp) = if self.lazy_send {
+            // Alloc and prepare in a single round trip,
+            // using the handle of the statement allocated in the previous request
+            self.socket.write_all(&allocate_statement(db_handle.0))?;
+            self.socket.write_all(&prepare_statement(
+                tr_handle.0,
+                u32::MAX,
+                dialect as u32,
+                sql,
+                &self.charset,
+            )?)?;
+            self.socket.flush()?;
 
-        let (op_code, mut resp) = self.read_packet()?;
+            // Both responses need to be read, even if the alloc failed
+            let alloc_resp = self.read_response();
+            let prepare_resp = self.read_response();
 
-        // Alloc resp
-        if op_code != WireOp::Response as u32 {
-            return err_conn_rejected(op_code);
-        }
+            (StmtHandle(alloc_resp?.handle), prepare_resp)
+        } else {
+            self.socket.write_all(&allocate_statement(db_handle.0))?;
+            self.socket.flush()?;
 
-        let stmt_handle = StmtHandle(parse_response(&mut resp)?.handle);
+            let stmt_handle = StmtHandle(self.read_response()?.handle);
 
-        // Prepare resp
-        let op_code = resp.get_u32()?;
+            self.socket.write_all(&prepare_statement(
+                tr_handle.0,
+                stmt_handle.0,
+                dialect as u32,
+                sql,
+                &self.charset,
+            )?)?;
+            self.socket.flush()?;
 
-        if op_code != WireOp::Response as u32 {
-            return err_conn_rejected(op_code);
-        }
+            (stmt_handle, self.read_response())
+        };
+
+        let mut resp = match prepare_resp {
+            Ok(resp) => resp,
+            Err(e) => {
+                // Release the allocated statement
+                self.free_statement_handle(stmt_handle, FreeStmtOp::Drop)?;
+
+                return Err(e);
+            }
+        };
 
         let mut xsqlda = Vec::new();
 
-        let mut resp = parse_response(&mut resp)?;
         let PrepareInfo {
             stmt_type,
             mut param_count,
@@ -493,20 +794,9 @@ impl FirebirdWireConnection {
             param_count = parse_resp.param_count;
         }
 
-        // Coerce the output columns and transform to blr
-        for var in xsqlda.iter_mut() {
-            var.coerce()?;
-        }
-        let blr = xsqlda_to_blr(&xsqlda)?;
-
         Ok((
             stmt_type,
-            StmtHandleData {
-                handle: stmt_handle,
-                xsqlda,
-                blr,
-                param_count,
-            },
+            StmtHandleData::new(stmt_handle, xsqlda, param_count, self.fetch_size)?,
         ))
     }
 
@@ -516,9 +806,49 @@ impl FirebirdWireConnection {
         stmt_handle: &mut StmtHandleData,
         op: FreeStmtOp,
     ) -> Result<(), FbError> {
-        self.socket
-            .write_all(&free_statement(stmt_handle.handle.0, op))?;
-        // Obs.: Lazy response
+        stmt_handle.reset_rows();
+
+        self.free_statement_handle(stmt_handle.handle, op)
+    }
+
+    /// Closes or drops a statement handle. If the server accepts
+    /// deferred requests, will be sent together with the next request
+    fn free_statement_handle(&mut self, handle: StmtHandle, op: FreeStmtOp) -> Result<(), FbError> {
+        self.socket.write_all(&free_statement(handle.0, op))?;
+
+        if self.lazy_send {
+            // The response will be read before the response of the next request
+            self.deferred += 1;
+        } else {
+            self.socket.flush()?;
+
+            self.read_response()?;
+        }
+
+        Ok(())
+    }
+
+    /// Set the name of the statement cursor. Fails if rows were already fetched from the cursor.
+    ///
+    /// Also sets the fetch size to 1, so the current row of the cursor in the server
+    /// is always the last row fetched, as expected by the `WHERE CURRENT OF` statements
+    pub fn set_cursor_name(
+        &mut self,
+        stmt_handle: &mut StmtHandleData,
+        cursor_name: &str,
+    ) -> Result<(), FbError> {
+        stmt_handle.check_cursor_name()?;
+
+        self.socket.write_all(&set_cursor(
+            stmt_handle.handle.0,
+            cursor_name,
+            &self.charset,
+        )?)?;
+        self.socket.flush()?;
+
+        self.read_response()?;
+
+        stmt_handle.set_named_cursor();
 
         Ok(())
     }
@@ -530,22 +860,19 @@ impl FirebirdWireConnection {
         stmt_handle: &mut StmtHandleData,
         params: &[SqlType],
     ) -> Result<(), FbError> {
-        if params.len() != stmt_handle.param_count {
-            return Err(format!(
-                "Tried to execute a statement that has {} parameters while providing {}",
-                stmt_handle.param_count,
-                params.len()
-            )
-            .into());
-        }
+        stmt_handle.check_param_count(params)?;
 
         let params = blr::params_to_blr(self, tr_handle, params)?;
 
+        stmt_handle.reset_rows();
+
         self.socket.write_all(&execute(
             tr_handle.0,
             stmt_handle.handle.0,
             &params.blr,
             &params.values,
+            stmt_handle.timeout,
+            self.version,
         ))?;
         self.socket.flush()?;
 
@@ -554,6 +881,17 @@ impl FirebirdWireConnection {
         Ok(())
     }
 
+    /// Number of rows inserted, updated or deleted by the last execution of the statement
+    pub fn affected_rows(&mut self, stmt_handle: &mut StmtHandleData) -> Result<u64, FbError> {
+        self.socket
+            .write_all(&info_sql_records(stmt_handle.handle.0))?;
+        self.socket.flush()?;
+
+        let mut data = self.read_response()?.data;
+
+        parse_info_sql_records(&mut data)
+    }
+
     /// Execute the prepared statement with parameters, returning data
     pub fn execute2(
         &mut self,
@@ -561,14 +899,7 @@ impl FirebirdWireConnection {
         stmt_handle: &mut StmtHandleData,
         params: &[SqlType],
     ) -> Result<Vec<Column>, FbError> {
-        if params.len() != stmt_handle.param_count {
-            return Err(format!(
-                "Tried to execute a statement that has {} parameters while providing {}",
-                stmt_handle.param_count,
-                params.len()
-            )
-            .into());
-        }
+        stmt_handle.check_param_count(params)?;
 
         let params = blr::params_to_blr(self, tr_handle, params)?;
 
@@ -578,24 +909,22 @@ impl FirebirdWireConnection {
             &params.blr,
             &params.values,
             &stmt_handle.blr,
+            stmt_handle.timeout,
+            self.version,
         ))?;
         self.socket.flush()?;
 
-        let (op_code, mut resp) = read_packet(&mut self.socket, &mut self.buff)?;
+        self.read_deferred()?;
 
-        if op_code == WireOp::Response as u32 {
-            // An error ocurred
-            parse_response(&mut resp)?;
-        }
-
-        if op_code != WireOp::SqlResponse as u32 {
-            return err_conn_rejected(op_code);
-        }
+        let version = self.version;
+        let charset = self.charset.clone();
 
         let parsed_cols =
-            parse_sql_response(&mut resp, &stmt_handle.xsqlda, self.version, &self.charset)?;
+            read_and_parse(&mut self.socket, &mut self.buff, &mut self.recv, |resp| {
+                parse_sql_response_packet(resp, &stmt_handle.xsqlda, version, &charset)
+            })?;
 
-        parse_response(&mut resp)?;
+        self.read_response()?;
 
         let mut cols = Vec::with_capacity(parsed_cols.len());
 
@@ -606,31 +935,123 @@ impl FirebirdWireConnection {
         Ok(cols)
     }
 
-    /// Fetch rows from the executed statement, coercing the types
-    /// according to the provided blr
-    pub fn fetch(
+    /// Execute the prepared statement once for each parameter set, returning their completion states.
+    ///
+    /// Requires the batch api of the server (Firebird 4+), failing with older servers
+    pub fn execute_batch(
         &mut self,
         tr_handle: &mut TrHandle,
         stmt_handle: &mut StmtHandleData,
-    ) -> Result<Option<Vec<Column>>, FbError> {
-        self.socket
-            .write_all(&fetch(stmt_handle.handle.0, &stmt_handle.blr))?;
-        self.socket.flush()?;
+        params: &[Vec<SqlType>],
+    ) -> Result<Vec<BatchRowState>, FbError> {
+        for p in params {
+            stmt_handle.check_param_count(p)?;
+        }
 
-        let (op_code, mut resp) = read_packet(&mut self.socket, &mut self.buff)?;
+        if self.version < ProtocolVersion::V16 {
+            return err_batch_unsupported();
+        }
 
-        if op_code == WireOp::Response as u32 {
-            // An error ocurred
-            parse_response(&mut resp)?;
+        if params.is_empty() {
+            return Ok(Vec::new());
         }
 
-        if op_code != WireOp::FetchResponse as u32 {
-            return err_conn_rejected(op_code);
+        let batch = blr::batch_params_to_blr(self, tr_handle, params)?;
+
+        stmt_handle.reset_rows();
+
+        // Split the messages, so each execution fits in the server buffer
+        let msg_len = blr::align_to(batch.msg_len as usize, 8);
+        let chunk_len = (BATCH_BUFFER_SIZE as usize / 2 / msg_len.max(1)).max(1);
+
+        let mut states = Vec::with_capacity(params.len());
+
+        for messages in batch.messages.chunks(chunk_len) {
+            let handle = stmt_handle.handle.0;
+
+            let mut data = BytesMut::with_capacity(messages.iter().map(|m| m.values.len()).sum());
+            for m in messages {
+                data.put_slice(&m.values);
+            }
+
+            let blobs: Vec<BlobId> = messages
+                .iter()
+                .flat_map(|m| m.blobs.iter().copied())
+                .collect();
+
+            // Send all requests in a single round trip
+            self.socket.write_all(&batch_create(
+                handle,
+                &batch.blr,
+                batch.msg_len,
+                BATCH_BUFFER_SIZE,
+            ))?;
+            for &id in &blobs {
+                self.socket.write_all(&batch_regblob(handle, id))?;
+            }
+            self.socket
+                .write_all(&batch_msg(handle, messages.len() as u32, &data))?;
+            self.socket.write_all(&batch_exec(handle, tr_handle.0))?;
+            self.socket.flush()?;
+
+            // Read all the responses, even if one of them failed
+            let mut res = Ok(());
+            for _ in 0..blobs.len() + 2 {
+                if let Err(e) = self.read_response() {
+                    if res.is_ok() {
+                        res = Err(e);
+                    }
+                }
+            }
+
+            let cs = read_and_parse(
+                &mut self.socket,
+                &mut self.buff,
+                &mut self.recv,
+                parse_batch_cs_packet,
+            );
+
+            let rls = self.release_batch(handle);
+
+            res?;
+            states.extend(cs?);
+            rls?;
+        }
+
+        Ok(states)
+    }
+
+    /// Releases the batch of the statement. If the server accepts
+    /// deferred requests, will be sent together with the next request
+    fn release_batch(&mut self, handle: u32) -> Result<(), FbError> {
+        self.socket.write_all(&batch_rls(handle))?;
+
+        if self.lazy_send {
+            // The response will be read before the response of the next request
+            self.deferred += 1;
+        } else {
+            self.socket.flush()?;
+
+            self.read_response()?;
+        }
+
+        Ok(())
+    }
+
+    /// Fetch rows from the executed statement, coercing the types
+    /// according to the provided blr
+    ///
+    /// The rows are requested from the server in batches of `fetch_size` rows
+    pub fn fetch(
+        &mut self,
+        tr_handle: &mut TrHandle,
+        stmt_handle: &mut StmtHandleData,
+    ) -> Result<Option<Vec<Column>>, FbError> {
+        if stmt_handle.rows.is_empty() && !stmt_handle.end_of_cursor {
+            self.fetch_batch(stmt_handle)?;
         }
 
-        if let Some(parsed_cols) =
-            parse_fetch_response(&mut resp, &stmt_handle.xsqlda, self.version, &self.charset)?
-        {
+        if let Some(parsed_cols) = stmt_handle.rows.pop_front() {
             let mut cols = Vec::with_capacity(parsed_cols.len());
 
             for pc in parsed_cols {
@@ -643,6 +1064,40 @@ impl FirebirdWireConnection {
         }
     }
 
+    /// Request the next batch of rows from the server, storing them in the statement
+    fn fetch_batch(&mut self, stmt_handle: &mut StmtHandleData) -> Result<(), FbError> {
+        stmt_handle.fetched = true;
+
+        self.socket.write_all(&fetch(
+            stmt_handle.handle.0,
+            &stmt_handle.blr,
+            stmt_handle.fetch_size,
+        ))?;
+        self.socket.flush()?;
+
+        self.read_deferred()?;
+
+        let version = self.version;
+        let charset = self.charset.clone();
+
+        loop {
+            let resp = read_and_parse(&mut self.socket, &mut self.buff, &mut self.recv, |resp| {
+                parse_fetch_response_packet(resp, &stmt_handle.xsqlda, version, &charset)
+            })?;
+
+            match resp {
+                FetchResponse::Row(parsed_cols) => stmt_handle.rows.push_back(parsed_cols),
+                FetchResponse::EndOfBatch => break,
+                FetchResponse::EndOfCursor => {
+                    stmt_handle.end_of_cursor = true;
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     /// Create a new blob, returning the blob handle and id
     pub fn create_blob(
         &mut self,
@@ -688,24 +1143,12 @@ impl FirebirdWireConnection {
         self.socket.write_all(&get_segment(blob_handle.0))?;
         self.socket.flush()?;
 
-        let mut blob_data = BytesMut::with_capacity(256);
-
-        let resp = self.read_response()?;
-        let mut data = resp.data;
+        let mut blob_data = Vec::with_capacity(256);
 
-        loop {
-            if data.remaining() < 2 {
-                break;
-            }
-            let len = data.get_u16_le()? as usize;
-            if data.remaining() < len {
-                return err_invalid_response();
-            }
-            blob_data.put_slice(&data[..len]);
-            data.advance(len)?;
-        }
+        let mut resp = self.read_response()?;
+        parse_segments(&mut resp.data, &mut blob_data)?;
 
-        Ok((blob_data.freeze(), resp.handle == 2))
+        Ok((blob_data.into(), resp.handle == 2))
     }
 
     /// Closes a blob handle
@@ -720,12 +1163,39 @@ impl FirebirdWireConnection {
 
     /// Read a server response
     fn read_response(&mut self) -> Result<Response, FbError> {
-        read_response(&mut self.socket, &mut self.buff)
+        self.read_deferred()?;
+
+        let res = read_and_parse(
+            &mut self.socket,
+            &mut self.buff,
+            &mut self.recv,
+            parse_response_packet,
+        );
+
+        // The server closed the session
+        if let Err(FbError::IdleTimeout(_)) = res {
+            self.socket.broken = true;
+        }
+
+        res
     }
 
-    /// Reads a packet from the socket
-    fn read_packet(&mut self) -> Result<(u32, Bytes), FbError> {
-        read_packet(&mut self.socket, &mut self.buff)
+    /// Read the responses of the deferred requests, that must come before the
+    /// response of the current request. Errors returned by the server are ignored,
+    /// as they are not related to the current request
+    fn read_deferred(&mut self) -> Result<(), FbError> {
+        while self.deferred > 0 {
+            read_and_parse(
+                &mut self.socket,
+                &mut self.buff,
+                &mut self.recv,
+                parse_deferred_packet,
+            )?;
+
+            self.deferred -= 1;
+        }
+
+        Ok(())
     }
 }
 
@@ -756,122 +1226,681 @@ fn read_packet(socket: &mut impl Read, buff: &mut [u8]) -> Result<(u32, Bytes),
     }
     let mut resp = resp.freeze();
 
-    let op_code = loop {
-        let op_code = resp.get_u32()?;
+    let op_code = parse_op_code(&mut resp)?;
 
-        if op_code != WireOp::Dummy as u32 {
-            break op_code;
+    Ok((op_code, resp))
+}
+
+/// Parses the data received, reading more from the socket while the data is incomplete.
+///
+/// The parsed bytes are consumed from `data` even on errors, as the server errors
+/// are only returned after the whole packet was parsed
+fn read_and_parse<T>(
+    socket: &mut impl Read,
+    buff: &mut [u8],
+    data: &mut Bytes,
+    mut parse: impl FnMut(&mut Bytes) -> Result<T, FbError>,
+) -> Result<T, FbError> {
+    loop {
+        if let Some(v) = try_parse(data, &mut parse)? {
+            return Ok(v);
         }
-    };
 
-    Ok((op_code, resp))
+        let len = socket.read(buff)?;
+        if len == 0 {
+            return err_invalid_response();
+        }
+
+        append_received(data, &buff[..len]);
+    }
 }
 
-/// Performs the srp authentication with the server, returning the encrypted stream
-fn srp_auth<D>(
-    mut socket: FbStream,
+/// Authentication plugins of a connection, keeping the state of the authentication
+pub(crate) struct Authenticator {
+    plugins: Vec<Box<dyn AuthPlugin>>,
+    /// Names of the plugins, separated by commas
+    plugin_list: String,
+    /// Plugin that sent the last data to the server
+    current: usize,
+    /// Plugin that completed the authentication
+    authenticated_by: Option<usize>,
+}
+
+impl Authenticator {
+    pub(crate) fn new(config: &RustFbClientAttachmentConfig) -> Result<Self, FbError> {
+        let plugins: Vec<_> = config.auth_plugins.iter().map(|f| f()).collect();
+        if plugins.is_empty() {
+            return Err("No authentication plugin configured".into());
+        }
+
+        let plugin_list = plugins
+            .iter()
+            .map(|p| p.name())
+            .collect::<Vec<_>>()
+            .join(",");
+
+        Ok(Self {
+            plugins,
+            plugin_list,
+            current: 0,
+            authenticated_by: None,
+        })
+    }
+
+    /// Connection request, with the data of the preferred plugin
+    pub(crate) fn connect(
+        &mut self,
+        config: &RustFbClientAttachmentConfig,
+        hostname: &str,
+    ) -> Result<Bytes, FbError> {
+        let plugin_data = self.plugins[0].initial_data(&config.user, &config.pass)?;
+
+        Ok(connect(
+            &config.db_name,
+            false,
+            &config.user,
+            &system_username(),
+            hostname,
+            self.plugins[0].name(),
+            &self.plugin_list,
+            &plugin_data,
+            config.wire_compression,
+            config.wire_crypt,
+        ))
+    }
+
+    /// Connection request with only the legacy protocols, for the servers
+    /// older than Firebird 3 that rejected the plugins data
+    pub(crate) fn connect_legacy(
+        config: &RustFbClientAttachmentConfig,
+        hostname: &str,
+    ) -> Result<Bytes, FbError> {
+        let pass_hash = legacy_auth_hash(&config.pass)?;
+
+        Ok(connect_legacy(
+            &config.db_name,
+            &config.user,
+            &system_username(),
+            hostname,
+            &pass_hash,
+        ))
+    }
+
+    /// Process the authentication data sent by the server, returning the answer
+    pub(crate) fn continue_auth(
+        &mut self,
+        config: &RustFbClientAttachmentConfig,
+        auth_data: &ServerAuthData,
+    ) -> Result<Bytes, FbError> {
+        let (user, pass) = (config.user.as_str(), config.pass.as_str());
+
+        let index = self
+            .plugins
+            .iter()
+            .position(|p| p.name() == auth_data.plugin)
+            .ok_or_else(|| {
+                FbError::from(format!(
+                    "Server requested the unsupported auth plugin: {}",
+                    auth_data.plugin
+                ))
+            })?;
+        let plugin = &mut self.plugins[index];
+
+        let data = if self.current == index && !auth_data.data.is_empty() {
+            plugin.continue_auth(user, pass, &auth_data.data)?
+        } else {
+            // Server requested a different authentication method than the client
+            // specified in the last request
+            plugin.initial_data(user, pass)?
+        };
+        self.current = index;
+        self.authenticated_by = Some(index);
+
+        Ok(cont_auth(&data, plugin.name(), &self.plugin_list, &[]))
+    }
+
+    /// Key generated by the authentication, used in the wire encryption
+    pub(crate) fn session_key(&self) -> Option<Vec<u8>> {
+        self.authenticated_by
+            .and_then(|i| self.plugins[i].session_key())
+    }
+}
+
+/// Name of the user in the client system
+fn system_username() -> String {
+    env::var("USER").unwrap_or_else(|_| env::var("USERNAME").unwrap_or_default())
+}
+
+/// Opens the socket and sends the connection request, returning the server answer
+fn connect_request(
+    config: &RustFbClientAttachmentConfig,
     buff: &mut [u8],
-    srp: SrpClient<D>,
-    plugin: AuthPluginType,
-    user: &str,
-    pass: &str,
-    data: SrpAuthData,
-) -> Result<FbStream, FbError>
-where
-    D: digest::Digest,
-{
-    // Generate a private key with the salt received from the server
-    let private_key = srp_private_key::<sha1::Sha1>(user.as_bytes(), pass.as_bytes(), &data.salt);
-
-    // Generate a verified with the private key above and the server public key received
-    let verifier = srp
-        .process_reply(user.as_bytes(), &data.salt, &private_key, &data.pub_key)
-        .map_err(|e| FbError::from(format!("Srp error: {}", e)))?;
-
-    // Generate a proof to send to the server so it can verify the password
-    let proof = hex::encode(verifier.get_proof());
-
-    // Send proof data
-    socket.write_all(&cont_auth(
-        &proof.as_bytes(),
-        plugin,
-        AuthPluginType::plugin_list(),
-        &[],
-    ))?;
+    req: impl FnOnce(&str) -> Result<Bytes, FbError>,
+) -> Result<(FbStream, Bytes), FbError> {
+    let transport = open_transport(config)?;
+    let hostname = transport.local_addr.clone();
+
+    let mut socket = FbStream::new(transport);
+
+    socket.write_all(&req(&hostname)?)?;
     socket.flush()?;
 
-    read_response(&mut socket, buff)?;
+    let len = socket.read(buff)?;
+    let resp = Bytes::copy_from_slice(&buff[..len]);
+
+    Ok((socket, resp))
+}
+
+/// Reads the server answer to the authentication data, returning the next
+/// plugin requested by the server or `None` if the authentication is complete.
+/// The wire encryption keys sent by the server are added to `server_keys`
+fn read_auth_result(
+    socket: &mut FbStream,
+    buff: &mut [u8],
+    server_keys: &mut ServerKeys,
+) -> Result<Option<ServerAuthData>, FbError> {
+    let (op_code, mut resp) = read_packet(socket, buff)?;
+
+    if op_code == WireOp::ContAuth as u32 {
+        let auth_data = parse_cont_auth(&mut resp)?;
+        parse_server_keys(&mut auth_data.keys.clone(), server_keys)?;
+
+        return Ok(Some(auth_data));
+    }
+
+    if op_code != WireOp::Response as u32 {
+        return err_conn_rejected(op_code);
+    }
+
+    let mut resp = parse_response(&mut resp)?;
+    parse_server_keys(&mut resp.data, server_keys)?;
 
-    // Enable wire encryption
-    socket.write_all(&crypt("Arc4", "Symmetric"))?;
+    Ok(None)
+}
+
+/// Enables the wire encryption with the first plugin of the configuration supported by the server
+fn enable_wire_crypt(
+    mut socket: FbStream,
+    buff: &mut [u8],
+    session_key: Option<&[u8]>,
+    server_keys: &ServerKeys,
+    config: &RustFbClientAttachmentConfig,
+) -> Result<FbStream, FbError> {
+    let (key, plugin) = match select_wire_crypt(session_key, server_keys, config)? {
+        Some(selected) => selected,
+        None => return Ok(socket),
+    };
+
+    socket.write_all(&crypt(plugin.name(), crypt_keys::KEY_TYPE_SYMMETRIC))?;
     socket.flush()?;
 
-    socket = FbStream::Arc4(Arc4Stream::new(
-        match socket {
-            FbStream::Plain(s) => s,
-            _ => unreachable!("Stream was already encrypted!"),
-        },
-        &verifier.get_key(),
-        buff.len(),
-    ));
+    match plugin {
+        WireCryptPlugin::Arc4 => socket.arc4(key, buff.len()),
+        WireCryptPlugin::ChaCha | WireCryptPlugin::ChaCha64 => {
+            let chacha = chacha_cipher(key, server_keys.specific_data(plugin))?;
+            socket.chacha(chacha, buff.len())
+        }
+    }
 
     read_response(&mut socket, buff)?;
 
     Ok(socket)
 }
 
+/// Selects the first wire encryption plugin of the configuration supported by the server,
+/// returning `None` if the encryption should not be enabled
+pub(crate) fn select_wire_crypt<'k>(
+    session_key: Option<&'k [u8]>,
+    server_keys: &ServerKeys,
+    config: &RustFbClientAttachmentConfig,
+) -> Result<Option<(&'k [u8], WireCryptPlugin)>, FbError> {
+    if config.wire_crypt == WireCrypt::Disabled {
+        return Ok(None);
+    }
+
+    let selected = session_key.and_then(|key| {
+        config
+            .wire_crypt_plugins
+            .iter()
+            .copied()
+            .find(|&plugin| {
+                server_keys.supports(plugin)
+                    // Servers that don't send their keys only support Arc4
+                    || (server_keys.plugins.is_empty() && plugin == WireCryptPlugin::Arc4)
+            })
+            .map(|plugin| (key, plugin))
+    });
+
+    match selected {
+        None if config.wire_crypt == WireCrypt::Required => {
+            Err("Wire encryption is required, but no encryption plugin is supported by both the client and the server".into())
+        }
+        selected => Ok(selected),
+    }
+}
+
+/// Creates the ChaCha cipher from the session key and the nonce sent by the server
+pub(crate) fn chacha_cipher(key: &[u8], iv: Option<&Bytes>) -> Result<ChaCha, FbError> {
+    let iv = iv.ok_or_else(|| FbError::from("Missing the ChaCha nonce from the server"))?;
+
+    // The key is stretched to the 32 bytes needed by the cipher
+    let mut stretched = [0; 32];
+    stretched.copy_from_slice(&<sha2::Sha256 as digest::Digest>::digest(key));
+
+    if iv.len() == 16 {
+        // Nonce followed by the initial counter
+        let counter = u32::from_le_bytes([iv[12], iv[13], iv[14], iv[15]]);
+        ChaCha::new(&stretched, &iv[..12], counter as u64)
+    } else {
+        ChaCha::new(&stretched, iv, 0)
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 /// A database handle
-pub struct DbHandle(u32);
+pub struct DbHandle(pub(crate) u32);
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 /// A transaction handle
-pub struct TrHandle(u32);
+pub struct TrHandle(pub(crate) u32);
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 /// A statement handle
-pub struct StmtHandle(u32);
+pub struct StmtHandle(pub(crate) u32);
 
 #[derive(Debug, Clone, Copy)]
 /// A blob handle
-pub struct BlobHandle(u32);
+pub struct BlobHandle(pub(crate) u32);
 
 #[derive(Debug, Clone, Copy)]
 /// A blob Identificator
 pub struct BlobId(pub(crate) u64);
 
-/// Firebird tcp stream, may be encrypted
-enum FbStream {
+/// Write half of the stream, shared with the cancel handles
+type SharedWriter = Arc<Mutex<StreamLayers<Box<dyn Write + Send>>>>;
+
+/// Firebird stream, may be encrypted and / or compressed.
+///
+/// The write half is shared, so the operations can be cancelled while
+/// the connection is waiting for the server response
+struct FbStream {
+    reader: StreamLayers<Box<dyn Read + Send>>,
+    writer: SharedWriter,
+    /// The write half can be used while the read half is blocked
+    cancellable: bool,
+    /// An I/O error was found, the stream is in an unknown state
+    broken: bool,
+}
+
+impl FbStream {
+    fn new(transport: SplitTransport) -> Self {
+        Self {
+            reader: StreamLayers::Plain(transport.reader),
+            writer: Arc::new(Mutex::new(StreamLayers::Plain(transport.writer))),
+            cancellable: transport.independent,
+            broken: false,
+        }
+    }
+
+    /// Apply the same layer to both halves
+    fn map_layers(
+        &mut self,
+        f: impl Fn(StreamLayers<Box<dyn Read + Send>>) -> StreamLayers<Box<dyn Read + Send>>,
+        g: impl Fn(StreamLayers<Box<dyn Write + Send>>) -> StreamLayers<Box<dyn Write + Send>>,
+    ) {
+        let reader = mem::replace(&mut self.reader, StreamLayers::Plain(Box::new(io::empty())));
+        self.reader = f(reader);
+
+        let mut writer = lock_writer(&self.writer).expect("Stream writer lock poisoned");
+        let old = mem::replace(&mut *writer, StreamLayers::Plain(Box::new(io::sink())));
+        *writer = g(old);
+    }
+
+    /// Enable the zlib compression
+    fn compress(&mut self, buf_len: usize) {
+        self.map_layers(|s| s.compress(buf_len), |s| s.compress(buf_len))
+    }
+
+    /// Enable the Arc4 encryption. If compressed, the compressed data will be encrypted
+    fn arc4(&mut self, key: &[u8], buf_len: usize) {
+        self.map_layers(|s| s.arc4(key, buf_len), |s| s.arc4(key, buf_len))
+    }
+
+    /// Enable the ChaCha encryption. If compressed, the compressed data will be encrypted
+    fn chacha(&mut self, chacha: ChaCha, buf_len: usize) {
+        self.map_layers(
+            |s| s.chacha(chacha.clone(), buf_len),
+            |s| s.chacha(chacha.clone(), buf_len),
+        )
+    }
+}
+
+fn lock_writer(
+    writer: &SharedWriter,
+) -> io::Result<MutexGuard<'_, StreamLayers<Box<dyn Write + Send>>>> {
+    writer
+        .lock()
+        .map_err(|_| io::Error::other("Stream writer lock poisoned"))
+}
+
+impl FbStream {
+    /// Mark the stream as broken on errors
+    fn check<T>(&mut self, res: io::Result<T>) -> io::Result<T> {
+        if res.is_err() {
+            self.broken = true;
+        }
+        res
+    }
+}
+
+impl Read for FbStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let res = self.reader.read(buf);
+
+        // End of stream, the server closed the connection
+        if let Ok(0) = res {
+            if !buf.is_empty() {
+                self.broken = true;
+            }
+        }
+
+        self.check(res)
+    }
+}
+
+impl Write for FbStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let res = lock_writer(&self.writer).and_then(|mut w| w.write(buf));
+        self.check(res)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        // Keep the lock for the whole packet, so a cancel request is not mixed in
+        let res = lock_writer(&self.writer).and_then(|mut w| w.write_all(buf));
+        self.check(res)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let res = lock_writer(&self.writer).and_then(|mut w| w.flush());
+        self.check(res)
+    }
+}
+
+/// One half of the firebird stream, may be encrypted and / or compressed
+pub(crate) enum StreamLayers<S> {
     /// Plaintext stream
-    Plain(TcpStream),
+    Plain(S),
 
     /// Arc4 ecrypted stream
-    Arc4(Arc4Stream<TcpStream>),
+    Arc4(Arc4Stream<S>),
+
+    /// ChaCha ecrypted stream
+    ChaCha(ChaChaStream<S>),
+
+    /// Zlib compressed stream, over a plaintext or encrypted stream
+    Compressed(Box<ZlibStream<StreamLayers<S>>>),
 }
 
-impl Read for FbStream {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+impl<S> StreamLayers<S> {
+    /// Enable the zlib compression
+    pub(crate) fn compress(self, buf_len: usize) -> Self {
+        StreamLayers::Compressed(Box::new(ZlibStream::new(self, buf_len)))
+    }
+
+    /// Enable the Arc4 encryption. If compressed, the compressed data will be encrypted
+    pub(crate) fn arc4(self, key: &[u8], buf_len: usize) -> Self {
         match self {
-            FbStream::Plain(s) => s.read(buf),
-            FbStream::Arc4(s) => s.read(buf),
+            StreamLayers::Plain(s) => StreamLayers::Arc4(Arc4Stream::new(s, key, buf_len)),
+            StreamLayers::Compressed(s) => {
+                StreamLayers::Compressed(Box::new(s.map_stream(|s| s.arc4(key, buf_len))))
+            }
+            StreamLayers::Arc4(_) | StreamLayers::ChaCha(_) => {
+                unreachable!("Stream was already encrypted!")
+            }
+        }
+    }
+
+    /// Enable the ChaCha encryption. If compressed, the compressed data will be encrypted
+    pub(crate) fn chacha(self, chacha: ChaCha, buf_len: usize) -> Self {
+        match self {
+            StreamLayers::Plain(s) => StreamLayers::ChaCha(ChaChaStream::new(s, chacha, buf_len)),
+            StreamLayers::Compressed(s) => {
+                StreamLayers::Compressed(Box::new(s.map_stream(|s| s.chacha(chacha, buf_len))))
+            }
+            StreamLayers::Arc4(_) | StreamLayers::ChaCha(_) => {
+                unreachable!("Stream was already encrypted!")
+            }
         }
     }
 }
 
-impl Write for FbStream {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+impl<S: Read> Read for StreamLayers<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         match self {
-            FbStream::Plain(s) => s.write(buf),
-            FbStream::Arc4(s) => s.write(buf),
+            StreamLayers::Plain(s) => s.read(buf),
+            StreamLayers::Arc4(s) => s.read(buf),
+            StreamLayers::ChaCha(s) => s.read(buf),
+            StreamLayers::Compressed(s) => s.read(buf),
         }
     }
+}
 
-    fn flush(&mut self) -> std::io::Result<()> {
+impl<S: Write> Write for StreamLayers<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         match self {
-            FbStream::Plain(s) => s.flush(),
-            FbStream::Arc4(s) => s.flush(),
+            StreamLayers::Plain(s) => s.write(buf),
+            StreamLayers::Arc4(s) => s.write(buf),
+            StreamLayers::ChaCha(s) => s.write(buf),
+            StreamLayers::Compressed(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            StreamLayers::Plain(s) => s.flush(),
+            StreamLayers::Arc4(s) => s.flush(),
+            StreamLayers::ChaCha(s) => s.flush(),
+            StreamLayers::Compressed(s) => s.flush(),
+        }
+    }
+}
+
+/// Cancels the operations of the connection with the `op_cancel` request
+struct RustCancelHandle {
+    writer: SharedWriter,
+    cancellable: bool,
+    version: ProtocolVersion,
+}
+
+impl FirebirdClientCancel for RustCancelHandle {
+    fn cancel_operation(&self, kind: CancelKind) -> Result<(), FbError> {
+        if !self.cancellable {
+            return Err("Cancel is not supported with custom transports".into());
+        }
+
+        if self.version < ProtocolVersion::V12 {
+            return Err("Cancel needs the protocol 12 (Firebird 2.5+)".into());
+        }
+
+        // The server doesn't answer the cancel request
+        let mut writer = lock_writer(&self.writer)?;
+        writer.write_all(&cancel(kind))?;
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+#[test]
+fn transport_test() {
+    use rsfbclient_core::charset::UTF_8;
+    use std::{
+        io::Cursor,
+        sync::{Arc, Mutex},
+    };
+
+    /// Answers with the canned data, keeping the data sent
+    struct Canned(Cursor<Vec<u8>>, Arc<Mutex<Vec<u8>>>);
+
+    impl Read for Canned {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Write for Canned {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.1.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Connection refused with the "login" error
+    let mut resp = BytesMut::new();
+    resp.put_u32(WireOp::Response as u32);
+    resp.put_u32(0); // Handle
+    resp.put_u64(0); // Object id
+    resp.put_u32(0); // Data
+    resp.put_u32(ibase::isc_arg_gds);
+    resp.put_u32(335544472);
+    resp.put_u32(ibase::isc_arg_end);
+
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let sent_conn = sent.clone();
+
+    let config = RustFbClientAttachmentConfig {
+        host: "tunnel".to_string(),
+        port: 1234,
+        transport: Some(Arc::new(move |host, port| {
+            assert_eq!((host, port), ("tunnel", 1234));
+
+            Ok(Box::new(Canned(
+                Cursor::new(resp.to_vec()),
+                sent_conn.clone(),
+            )))
+        })),
+        ..Default::default()
+    };
+
+    let err = FirebirdWireConnection::connect(&config, UTF_8)
+        .err()
+        .expect("The connection must be refused");
+    assert!(matches!(err, FbError::Sql { .. }));
+
+    // Connection request
+    assert_eq!(
+        sent.lock().unwrap()[..4],
+        (WireOp::Connect as u32).to_be_bytes()
+    );
+}
+
+#[test]
+fn cancel_handle_test() {
+    /// Keeps the data sent
+    struct Sent(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Sent {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
         }
     }
+
+    let sent = Arc::new(Mutex::new(Vec::new()));
+
+    let mut handle = RustCancelHandle {
+        writer: Arc::new(Mutex::new(StreamLayers::Plain(Box::new(Sent(
+            sent.clone(),
+        ))))),
+        cancellable: true,
+        version: ProtocolVersion::V13,
+    };
+
+    handle.cancel_operation(CancelKind::Raise).unwrap();
+    assert_eq!(
+        &sent.lock().unwrap()[..],
+        b"\x00\x00\x00\x5b\x00\x00\x00\x03"
+    );
+
+    handle.version = ProtocolVersion::V10;
+    assert!(handle.cancel_operation(CancelKind::Raise).is_err());
+
+    handle.version = ProtocolVersion::V13;
+    handle.cancellable = false;
+    assert!(handle.cancel_operation(CancelKind::Raise).is_err());
+}
+
+#[test]
+fn broken_stream_test() {
+    let mut stream = FbStream::new(SplitTransport {
+        reader: Box::new(io::Cursor::new(vec![0, 0, 0, WireOp::Response as u8])),
+        writer: Box::new(io::sink()),
+        independent: true,
+        local_addr: String::new(),
+    });
+
+    let mut buf = [0; 8];
+    assert_eq!(stream.read(&mut buf).unwrap(), 4);
+    stream.write_all(&ping()).unwrap();
+    assert!(!stream.broken);
+
+    // The server closed the connection
+    assert_eq!(stream.read(&mut buf).unwrap(), 0);
+    assert!(stream.broken);
+}
+
+#[test]
+fn read_and_parse_test() {
+    // Delivers the data in small chunks, like a slow network
+    struct Chunked(Vec<u8>);
+
+    impl Read for Chunked {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = self.0.len().min(buf.len()).min(3);
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0.drain(..len);
+            Ok(len)
+        }
+    }
+
+    let mut socket = Chunked((1..=4u32).flat_map(|i| i.to_be_bytes().to_vec()).collect());
+    let mut buff = [0; 16];
+    let mut data = Bytes::new();
+
+    for i in 1..=4 {
+        let v = read_and_parse(&mut socket, &mut buff, &mut data, |resp| resp.get_u32()).unwrap();
+        assert_eq!(v, i);
+    }
+
+    // No more data
+    assert!(read_and_parse(&mut socket, &mut buff, &mut data, |resp| resp.get_u32()).is_err());
+}
+
+#[test]
+fn named_cursor_test() {
+    let mut stmt = StmtHandleData::new(StmtHandle(1), Vec::new(), 0, DEFAULT_FETCH_SIZE).unwrap();
+
+    stmt.check_cursor_name().unwrap();
+    stmt.set_named_cursor();
+    assert_eq!(1, stmt.fetch_size);
+
+    // Named cursors keep fetching one row at a time
+    stmt.set_fetch_size(100);
+    assert_eq!(1, stmt.fetch_size);
+
+    // The server cursor may be ahead of the rows fetched
+    stmt.fetched = true;
+    assert!(stmt.check_cursor_name().is_err());
+
+    // A new execution opens a new cursor
+    stmt.reset_rows();
+    stmt.check_cursor_name().unwrap();
 }
 
 #[test]
@@ -883,10 +1912,20 @@ fn connection_test() {
     let user = "SYSDBA";
     let pass = "masterkey";
 
-    let mut conn =
-        FirebirdWireConnection::connect("127.0.0.1", 3050, db_name, user, pass, UTF_8).unwrap();
+    let config = RustFbClientAttachmentConfig {
+        host: "127.0.0.1".to_string(),
+        port: 3050,
+        db_name: db_name.to_string(),
+        user: user.to_string(),
+        pass: pass.to_string(),
+        ..Default::default()
+    };
+
+    let mut conn = FirebirdWireConnection::connect(&config, UTF_8).unwrap();
 
-    let mut db_handle = conn.attach_database(db_name, user, pass).unwrap();
+    let mut db_handle = conn
+        .attach_or_create(WireOp::Attach, db_name, user, pass, &[])
+        .unwrap();
 
     let mut tr_handle = conn
         .begin_transaction(&mut db_handle, TrIsolationLevel::Concurrency)
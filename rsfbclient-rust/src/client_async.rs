@@ -0,0 +1,1314 @@
+//! Async `FirebirdConnection` implementation for the pure rust firebird client, running on tokio
+//!
+//! Uses the same request encoders and response parsers of the blocking client,
+//! only the network I/O is async
+
+use async_trait::async_trait;
+use bytes::{BufMut, Bytes, BytesMut};
+use socket2::{SockRef, TcpKeepalive};
+use std::{
+    collections::VecDeque,
+    future::Future,
+    io::{self, Read, Write},
+    mem,
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time,
+};
+
+use crate::{
+    blr::{self, ParamsBlr},
+    chacha::ChaCha,
+    client::{
+        chacha_cipher, extra_dpb, select_wire_crypt, Authenticator, BlobHandle, BlobId, DbHandle,
+        RustFbClientAttachmentConfig, StmtHandle, StmtHandleData, StreamLayers, TrHandle,
+        DEFAULT_FETCH_SIZE,
+    },
+    consts::{crypt_keys, ProtocolVersion, WireOp},
+    util::*,
+    wire::*,
+    xsqlda::{parse_xsqlda, PrepareInfo},
+};
+use rsfbclient_core::{
+    ibase, Charset, Column, Dialect, FbError, FirebirdClientDbOpsAsync, FirebirdClientSqlOpsAsync,
+    FreeStmtOp, SqlType, StmtType, TrIsolationLevel, TrOp, WireCryptPlugin,
+};
+
+/// Firebird client implemented in pure rust, using the tokio runtime for the network I/O
+pub struct AsyncRustFbClient {
+    conn: Option<AsyncFirebirdWireConnection>,
+    charset: Charset,
+}
+
+impl AsyncRustFbClient {
+    ///Construct a new instance of the async pure rust client
+    pub fn new(charset: Charset) -> Self {
+        Self {
+            conn: None,
+            charset,
+        }
+    }
+
+    /// Start an operation in the connection to the server, or an error if not connected
+    fn start_op(&mut self) -> Result<OpGuard<'_>, FbError> {
+        let conn = self.conn.as_mut().ok_or_else(|| {
+            FbError::from("Client not connected to the server, call `attach_database` to connect")
+        })?;
+
+        Ok(OpGuard {
+            conn,
+            finished: false,
+        })
+    }
+}
+
+/// Operation in progress in the connection. If dropped before `finish`, as when
+/// the future of the operation is cancelled, marks the connection as broken,
+/// as a request may have been partially sent or its responses left unread
+struct OpGuard<'c> {
+    conn: &'c mut AsyncFirebirdWireConnection,
+    finished: bool,
+}
+
+impl OpGuard<'_> {
+    /// The operation completed, returning its result
+    fn finish<T>(mut self, res: T) -> T {
+        self.finished = true;
+        res
+    }
+}
+
+impl Deref for OpGuard<'_> {
+    type Target = AsyncFirebirdWireConnection;
+
+    fn deref(&self) -> &Self::Target {
+        self.conn
+    }
+}
+
+impl DerefMut for OpGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.conn
+    }
+}
+
+impl Drop for OpGuard<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.conn.socket.broken = true;
+        }
+    }
+}
+
+#[async_trait]
+impl FirebirdClientDbOpsAsync for AsyncRustFbClient {
+    type DbHandle = DbHandle;
+    type AttachmentConfig = RustFbClientAttachmentConfig;
+
+    async fn attach_database(
+        &mut self,
+        config: &Self::AttachmentConfig,
+    ) -> Result<DbHandle, FbError> {
+        // Take the existing connection, or connects if there is none or it is broken
+        let mut conn = match self.conn.take() {
+            Some(conn) if !conn.is_broken() => conn,
+            _ => AsyncFirebirdWireConnection::connect(config, self.charset.clone()).await?,
+        };
+
+        conn.fetch_size = config.fetch_size;
+
+        let attach_result = conn
+            .attach_database(
+                &config.db_name,
+                &config.user,
+                &config.pass,
+                &extra_dpb(config, None)?,
+            )
+            .await;
+
+        // Put the connection back
+        self.conn.replace(conn);
+
+        attach_result
+    }
+
+    async fn detach_database(&mut self, db_handle: &mut DbHandle) -> Result<(), FbError> {
+        let mut conn = self.start_op()?;
+        let res = conn.detach_database(db_handle).await;
+        conn.finish(res)
+    }
+
+    async fn drop_database(&mut self, db_handle: &mut DbHandle) -> Result<(), FbError> {
+        let mut conn = self.start_op()?;
+        let res = conn.drop_database(db_handle).await;
+        conn.finish(res)
+    }
+
+    async fn ping(&mut self, db_handle: &mut DbHandle) -> Result<(), FbError> {
+        let mut conn = self.start_op()?;
+        let res = conn.ping(db_handle).await;
+        conn.finish(res)
+    }
+
+    fn is_broken(&self) -> bool {
+        // Without a connection the client can't be used
+        self.conn
+            .as_ref()
+            .map(|conn| conn.is_broken())
+            .unwrap_or(true)
+    }
+}
+
+#[async_trait]
+impl FirebirdClientSqlOpsAsync for AsyncRustFbClient {
+    type DbHandle = DbHandle;
+    type TrHandle = TrHandle;
+    type StmtHandle = StmtHandleData;
+
+    async fn begin_transaction(
+        &mut self,
+        db_handle: &mut Self::DbHandle,
+        isolation_level: TrIsolationLevel,
+    ) -> Result<Self::TrHandle, FbError> {
+        let mut conn = self.start_op()?;
+        let res = conn.begin_transaction(db_handle, isolation_level).await;
+        conn.finish(res)
+    }
+
+    async fn transaction_operation(
+        &mut self,
+        tr_handle: &mut Self::TrHandle,
+        op: TrOp,
+    ) -> Result<(), FbError> {
+        let mut conn = self.start_op()?;
+        let res = conn.transaction_operation(tr_handle, op).await;
+        conn.finish(res)
+    }
+
+    async fn exec_immediate(
+        &mut self,
+        _db_handle: &mut Self::DbHandle,
+        tr_handle: &mut Self::TrHandle,
+        dialect: Dialect,
+        sql: &str,
+    ) -> Result<(), FbError> {
+        let mut conn = self.start_op()?;
+        let res = conn.exec_immediate(tr_handle, dialect, sql).await;
+        conn.finish(res)
+    }
+
+    async fn prepare_statement(
+        &mut self,
+        db_handle: &mut Self::DbHandle,
+        tr_handle: &mut Self::TrHandle,
+        dialect: Dialect,
+        sql: &str,
+    ) -> Result<(StmtType, Self::StmtHandle), FbError> {
+        let mut conn = self.start_op()?;
+        let res = conn
+            .prepare_statement(db_handle, tr_handle, dialect, sql)
+            .await;
+        conn.finish(res)
+    }
+
+    async fn free_statement(
+        &mut self,
+        stmt_handle: &mut Self::StmtHandle,
+        op: FreeStmtOp,
+    ) -> Result<(), FbError> {
+        let mut conn = self.start_op()?;
+        let res = conn.free_statement(stmt_handle, op).await;
+        conn.finish(res)
+    }
+
+    async fn set_cursor_name(
+        &mut self,
+        stmt_handle: &mut Self::StmtHandle,
+        cursor_name: &str,
+    ) -> Result<(), FbError> {
+        let mut conn = self.start_op()?;
+        let res = conn.set_cursor_name(stmt_handle, cursor_name).await;
+        conn.finish(res)
+    }
+
+    fn set_fetch_size(&mut self, stmt_handle: &mut Self::StmtHandle, fetch_size: u32) {
+        let fetch_size = match (fetch_size, &self.conn) {
+            (0, Some(conn)) => conn.fetch_size,
+            _ => fetch_size,
+        };
+
+        stmt_handle.set_fetch_size(fetch_size);
+    }
+
+    async fn execute(
+        &mut self,
+        _db_handle: &mut Self::DbHandle,
+        tr_handle: &mut Self::TrHandle,
+        stmt_handle: &mut Self::StmtHandle,
+        params: Vec<SqlType>,
+    ) -> Result<(), FbError> {
+        let mut conn = self.start_op()?;
+        let res = conn.execute(tr_handle, stmt_handle, &params).await;
+        conn.finish(res)
+    }
+
+    async fn execute2(
+        &mut self,
+        _db_handle: &mut Self::DbHandle,
+        tr_handle: &mut Self::TrHandle,
+        stmt_handle: &mut Self::StmtHandle,
+        params: Vec<SqlType>,
+    ) -> Result<Vec<Column>, FbError> {
+        let mut conn = self.start_op()?;
+        let res = conn.execute2(tr_handle, stmt_handle, &params).await;
+        conn.finish(res)
+    }
+
+    async fn fetch(
+        &mut self,
+        _db_handle: &mut Self::DbHandle,
+        tr_handle: &mut Self::TrHandle,
+        stmt_handle: &mut Self::StmtHandle,
+    ) -> Result<Option<Vec<Column>>, FbError> {
+        let mut conn = self.start_op()?;
+        let res = conn.fetch(tr_handle, stmt_handle).await;
+        conn.finish(res)
+    }
+
+    async fn affected_rows(&mut self, stmt_handle: &mut Self::StmtHandle) -> Result<u64, FbError> {
+        let mut conn = self.start_op()?;
+        let res = conn.affected_rows(stmt_handle).await;
+        conn.finish(res)
+    }
+}
+
+/// An async connection to a firebird server
+pub struct AsyncFirebirdWireConnection {
+    /// Connection socket
+    socket: AsyncFbStream,
+
+    /// Wire protocol version
+    version: ProtocolVersion,
+
+    /// Buffer to read the network data
+    buff: Box<[u8]>,
+
+    /// Data received from the server, but not yet parsed
+    recv: Bytes,
+
+    /// Server accepted deferred requests
+    lazy_send: bool,
+
+    /// Number of deferred requests that still need to have the response read
+    deferred: usize,
+
+    charset: Charset,
+
+    /// Default number of rows requested in each fetch for new statements
+    fetch_size: u32,
+}
+
+impl AsyncFirebirdWireConnection {
+    /// Start a connection to the firebird server
+    pub async fn connect(
+        config: &RustFbClientAttachmentConfig,
+        charset: Charset,
+    ) -> Result<Self, FbError> {
+        if config.transport.is_some() {
+            return Err("Custom transports are not supported by the async client".into());
+        }
+
+        let mut auth = Authenticator::new(config)?;
+
+        // May be a bit too much
+        let mut buff = vec![0; BUFFER_LENGTH as usize * 2].into_boxed_slice();
+
+        let (mut socket, mut resp) =
+            connect_request(config, &mut buff, |hostname| auth.connect(config, hostname)).await?;
+
+        if resp.get(..4) == Some(&(WireOp::Reject as u32).to_be_bytes()[..]) {
+            // Servers older than Firebird 3 may reject the plugins data, so
+            // connect again with only the legacy protocols
+            let (legacy_socket, legacy_resp) = connect_request(config, &mut buff, |hostname| {
+                Authenticator::connect_legacy(config, hostname)
+            })
+            .await?;
+
+            socket = legacy_socket;
+            resp = legacy_resp;
+        }
+
+        let ConnectionResponse {
+            version,
+            lazy_send,
+            compress,
+            auth_data,
+            keys,
+        } = parse_accept(&mut resp)?;
+
+        let mut server_keys = ServerKeys::default();
+        parse_server_keys(&mut keys.clone(), &mut server_keys)?;
+
+        if compress {
+            // All the packets after the accept are compressed
+            socket.compress(buff.len());
+        }
+
+        let mut next_data = auth_data;
+
+        while let Some(auth_data) = next_data.take() {
+            socket.write_all(&auth.continue_auth(config, &auth_data)?)?;
+            socket.flush().await?;
+
+            next_data = read_auth_result(&mut socket, &mut buff, &mut server_keys).await?;
+        }
+
+        let session_key = auth.session_key();
+
+        // Enable the wire encryption with the first plugin of the configuration supported by the server
+        if let Some((key, plugin)) =
+            select_wire_crypt(session_key.as_deref(), &server_keys, config)?
+        {
+            socket.write_all(&crypt(plugin.name(), crypt_keys::KEY_TYPE_SYMMETRIC))?;
+            socket.flush().await?;
+
+            match plugin {
+                WireCryptPlugin::Arc4 => socket.arc4(key, buff.len()),
+                WireCryptPlugin::ChaCha | WireCryptPlugin::ChaCha64 => {
+                    let chacha = chacha_cipher(key, server_keys.specific_data(plugin))?;
+                    socket.chacha(chacha, buff.len())
+                }
+            }
+
+            let (op_code, mut resp) = read_packet(&mut socket, &mut buff).await?;
+            if op_code != WireOp::Response as u32 {
+                return err_conn_rejected(op_code);
+            }
+            parse_response(&mut resp)?;
+        }
+
+        Ok(Self {
+            socket,
+            version,
+            buff,
+            recv: Bytes::new(),
+            lazy_send,
+            deferred: 0,
+            charset,
+            fetch_size: DEFAULT_FETCH_SIZE,
+        })
+    }
+
+    /// Connect to a database, returning a database handle
+    pub async fn attach_database(
+        &mut self,
+        db_name: &str,
+        user: &str,
+        pass: &str,
+        extra_dpb: &[u8],
+    ) -> Result<DbHandle, FbError> {
+        self.socket.write_all(&attach(
+            WireOp::Attach,
+            db_name,
+            user,
+            pass,
+            self.version,
+            self.charset.clone(),
+            extra_dpb,
+        )?)?;
+        self.socket.flush().await?;
+
+        let resp = self.read_response().await?;
+
+        Ok(DbHandle(resp.handle))
+    }
+
+    /// Check if the server is reachable. Uses the `op_ping` if
+    /// supported, or an empty database info request otherwise
+    pub async fn ping(&mut self, db_handle: &mut DbHandle) -> Result<(), FbError> {
+        if self.version >= ProtocolVersion::V13 {
+            self.socket.write_all(&ping())?;
+        } else {
+            self.socket.write_all(&info_database_ping(db_handle.0))?;
+        }
+        self.socket.flush().await?;
+
+        self.read_response().await?;
+
+        Ok(())
+    }
+
+    /// `true` if an I/O error was found in the connection or an operation
+    /// was cancelled before completing, so it can't be used anymore
+    pub fn is_broken(&self) -> bool {
+        self.socket.broken
+    }
+
+    /// Disconnect from the database
+    pub async fn detach_database(&mut self, db_handle: &mut DbHandle) -> Result<(), FbError> {
+        self.socket.write_all(&detach(db_handle.0))?;
+        self.socket.flush().await?;
+
+        self.read_response().await?;
+
+        Ok(())
+    }
+
+    /// Drop the database
+    pub async fn drop_database(&mut self, db_handle: &mut DbHandle) -> Result<(), FbError> {
+        self.socket.write_all(&drop_database(db_handle.0))?;
+        self.socket.flush().await?;
+
+        self.read_response().await?;
+
+        Ok(())
+    }
+
+    /// Start a new transaction, with the specified transaction parameter buffer
+    pub async fn begin_transaction(
+        &mut self,
+        db_handle: &mut DbHandle,
+        isolation_level: TrIsolationLevel,
+    ) -> Result<TrHandle, FbError> {
+        let tpb = [ibase::isc_tpb_version3 as u8, isolation_level as u8];
+
+        self.socket.write_all(&transaction(db_handle.0, &tpb))?;
+        self.socket.flush().await?;
+
+        let resp = self.read_response().await?;
+
+        Ok(TrHandle(resp.handle))
+    }
+
+    /// Commit / Rollback a transaction
+    pub async fn transaction_operation(
+        &mut self,
+        tr_handle: &mut TrHandle,
+        op: TrOp,
+    ) -> Result<(), FbError> {
+        self.socket
+            .write_all(&transaction_operation(tr_handle.0, op))?;
+        self.socket.flush().await?;
+
+        self.read_response().await?;
+
+        Ok(())
+    }
+
+    /// Execute a sql immediately, without returning rows
+    pub async fn exec_immediate(
+        &mut self,
+        tr_handle: &mut TrHandle,
+        dialect: Dialect,
+        sql: &str,
+    ) -> Result<(), FbError> {
+        self.socket.write_all(&exec_immediate(
+            tr_handle.0,
+            dialect as u32,
+            sql,
+            &self.charset,
+        )?)?;
+        self.socket.flush().await?;
+
+        self.read_response().await?;
+
+        Ok(())
+    }
+
+    /// Alloc and prepare a statement
+    ///
+    /// Returns the statement type, handle and xsqlda describing the columns
+    pub async fn prepare_statement(
+        &mut self,
+        db_handle: &mut DbHandle,
+        tr_handle: &mut TrHandle,
+        dialect: Dialect,
+        sql: &str,
+    ) -> Result<(StmtType, StmtHandleData), FbError> {
+        let (stmt_handle, prepare_resp) = if self.lazy_send {
+            // Alloc and prepare in a single round trip,
+            // using the handle of the statement allocated in the previous request
+            self.socket.write_all(&allocate_statement(db_handle.0))?;
+            self.socket.write_all(&prepare_statement(
+                tr_handle.0,
+                u32::MAX,
+                dialect as u32,
+                sql,
+                &self.charset,
+            )?)?;
+            self.socket.flush().await?;
+
+            // Both responses need to be read, even if the alloc failed
+            let alloc_resp = self.read_response().await;
+            let prepare_resp = self.read_response().await;
+
+            (StmtHandle(alloc_resp?.handle), prepare_resp)
+        } else {
+            self.socket.write_all(&allocate_statement(db_handle.0))?;
+            self.socket.flush().await?;
+
+            let stmt_handle = StmtHandle(self.read_response().await?.handle);
+
+            self.socket.write_all(&prepare_statement(
+                tr_handle.0,
+                stmt_handle.0,
+                dialect as u32,
+                sql,
+                &self.charset,
+            )?)?;
+            self.socket.flush().await?;
+
+            (stmt_handle, self.read_response().await)
+        };
+
+        let mut resp = match prepare_resp {
+            Ok(resp) => resp,
+            Err(e) => {
+                // Release the allocated statement
+                self.free_statement_handle(stmt_handle, FreeStmtOp::Drop)
+                    .await?;
+
+                return Err(e);
+            }
+        };
+
+        let mut xsqlda = Vec::new();
+
+        let PrepareInfo {
+            stmt_type,
+            mut param_count,
+            mut truncated,
+        } = parse_xsqlda(&mut resp.data, &mut xsqlda)?;
+
+        while truncated {
+            // Get more info on the types
+            self.socket
+                .write_all(&info_sql(stmt_handle.0, xsqlda.len()))?;
+            self.socket.flush().await?;
+
+            let mut data = self.read_response().await?.data;
+
+            let parse_resp = parse_xsqlda(&mut data, &mut xsqlda)?;
+            truncated = parse_resp.truncated;
+            param_count = parse_resp.param_count;
+        }
+
+        Ok((
+            stmt_type,
+            StmtHandleData::new(stmt_handle, xsqlda, param_count, self.fetch_size)?,
+        ))
+    }
+
+    /// Closes or drops a statement
+    pub async fn free_statement(
+        &mut self,
+        stmt_handle: &mut StmtHandleData,
+        op: FreeStmtOp,
+    ) -> Result<(), FbError> {
+        stmt_handle.reset_rows();
+
+        self.free_statement_handle(stmt_handle.handle, op).await
+    }
+
+    /// Closes or drops a statement handle. If the server accepts
+    /// deferred requests, will be sent together with the next request
+    async fn free_statement_handle(
+        &mut self,
+        handle: StmtHandle,
+        op: FreeStmtOp,
+    ) -> Result<(), FbError> {
+        self.socket.write_all(&free_statement(handle.0, op))?;
+
+        if self.lazy_send {
+            // The response will be read before the response of the next request
+            self.deferred += 1;
+        } else {
+            self.socket.flush().await?;
+
+            self.read_response().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Set the name of the statement cursor. Fails if rows were already fetched from the cursor.
+    ///
+    /// Also sets the fetch size to 1, so the current row of the cursor in the server
+    /// is always the last row fetched, as expected by the `WHERE CURRENT OF` statements
+    pub async fn set_cursor_name(
+        &mut self,
+        stmt_handle: &mut StmtHandleData,
+        cursor_name: &str,
+    ) -> Result<(), FbError> {
+        stmt_handle.check_cursor_name()?;
+
+        self.socket.write_all(&set_cursor(
+            stmt_handle.handle.0,
+            cursor_name,
+            &self.charset,
+        )?)?;
+        self.socket.flush().await?;
+
+        self.read_response().await?;
+
+        stmt_handle.set_named_cursor();
+
+        Ok(())
+    }
+
+    /// Execute the prepared statement with parameters
+    pub async fn execute(
+        &mut self,
+        tr_handle: &mut TrHandle,
+        stmt_handle: &mut StmtHandleData,
+        params: &[SqlType],
+    ) -> Result<(), FbError> {
+        stmt_handle.check_param_count(params)?;
+
+        let params = self.params_to_blr(tr_handle, params).await?;
+
+        stmt_handle.reset_rows();
+
+        self.socket.write_all(&execute(
+            tr_handle.0,
+            stmt_handle.handle.0,
+            &params.blr,
+            &params.values,
+            stmt_handle.timeout,
+            self.version,
+        ))?;
+        self.socket.flush().await?;
+
+        self.read_response().await?;
+
+        Ok(())
+    }
+
+    /// Number of rows inserted, updated or deleted by the last execution of the statement
+    pub async fn affected_rows(
+        &mut self,
+        stmt_handle: &mut StmtHandleData,
+    ) -> Result<u64, FbError> {
+        self.socket
+            .write_all(&info_sql_records(stmt_handle.handle.0))?;
+        self.socket.flush().await?;
+
+        let mut data = self.read_response().await?.data;
+
+        parse_info_sql_records(&mut data)
+    }
+
+    /// Execute the prepared statement with parameters, returning data
+    pub async fn execute2(
+        &mut self,
+        tr_handle: &mut TrHandle,
+        stmt_handle: &mut StmtHandleData,
+        params: &[SqlType],
+    ) -> Result<Vec<Column>, FbError> {
+        stmt_handle.check_param_count(params)?;
+
+        let params = self.params_to_blr(tr_handle, params).await?;
+
+        self.socket.write_all(&execute2(
+            tr_handle.0,
+            stmt_handle.handle.0,
+            &params.blr,
+            &params.values,
+            &stmt_handle.blr,
+            stmt_handle.timeout,
+            self.version,
+        ))?;
+        self.socket.flush().await?;
+
+        self.read_deferred().await?;
+
+        let version = self.version;
+        let charset = self.charset.clone();
+        let xsqlda = &stmt_handle.xsqlda;
+
+        let parsed_cols = self
+            .read_and_parse(|resp| parse_sql_response_packet(resp, xsqlda, version, &charset))
+            .await?;
+
+        self.read_response().await?;
+
+        self.complete_columns(tr_handle, parsed_cols).await
+    }
+
+    /// Fetch rows from the executed statement, coercing the types
+    /// according to the provided blr
+    ///
+    /// The rows are requested from the server in batches of `fetch_size` rows
+    pub async fn fetch(
+        &mut self,
+        tr_handle: &mut TrHandle,
+        stmt_handle: &mut StmtHandleData,
+    ) -> Result<Option<Vec<Column>>, FbError> {
+        if stmt_handle.rows.is_empty() && !stmt_handle.end_of_cursor {
+            self.fetch_batch(stmt_handle).await?;
+        }
+
+        if let Some(parsed_cols) = stmt_handle.rows.pop_front() {
+            Ok(Some(self.complete_columns(tr_handle, parsed_cols).await?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Request the next batch of rows from the server, storing them in the statement
+    async fn fetch_batch(&mut self, stmt_handle: &mut StmtHandleData) -> Result<(), FbError> {
+        stmt_handle.fetched = true;
+
+        self.socket.write_all(&fetch(
+            stmt_handle.handle.0,
+            &stmt_handle.blr,
+            stmt_handle.fetch_size,
+        ))?;
+        self.socket.flush().await?;
+
+        self.read_deferred().await?;
+
+        let version = self.version;
+        let charset = self.charset.clone();
+
+        loop {
+            let xsqlda = &stmt_handle.xsqlda;
+
+            let resp = self
+                .read_and_parse(|resp| parse_fetch_response_packet(resp, xsqlda, version, &charset))
+                .await?;
+
+            match resp {
+                FetchResponse::Row(parsed_cols) => stmt_handle.rows.push_back(parsed_cols),
+                FetchResponse::EndOfBatch => break,
+                FetchResponse::EndOfCursor => {
+                    stmt_handle.end_of_cursor = true;
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the rest of the data needed for the columns, reading the blobs
+    async fn complete_columns(
+        &mut self,
+        tr_handle: &mut TrHandle,
+        parsed_cols: Vec<ParsedColumn>,
+    ) -> Result<Vec<Column>, FbError> {
+        let mut cols = Vec::with_capacity(parsed_cols.len());
+
+        for pc in parsed_cols {
+            cols.push(match pc {
+                ParsedColumn::Complete(c) => c,
+                ParsedColumn::Blob {
+                    binary,
+                    id,
+                    col_name,
+                } => {
+                    let data = self.read_blob(tr_handle, id).await?;
+
+                    blob_column(col_name, binary, data, &self.charset)?
+                }
+            });
+        }
+
+        Ok(cols)
+    }
+
+    /// Convert the parameters to a blr, sending the data too large to be sent inline as blobs
+    async fn params_to_blr(
+        &mut self,
+        tr_handle: &mut TrHandle,
+        params: &[SqlType],
+    ) -> Result<ParamsBlr, FbError> {
+        // The blobs can't be created inside the encoder, so collect their data first
+        let mut blobs_data = Vec::new();
+        blr::encode_params(self.version, &self.charset, params, |data| {
+            blobs_data.push(data.to_vec());
+            Ok(BlobId(0))
+        })?;
+
+        let mut blob_ids = Vec::with_capacity(blobs_data.len());
+        for data in blobs_data {
+            blob_ids.push(self.write_blob(tr_handle, &data).await?);
+        }
+
+        let mut blob_ids = blob_ids.into_iter();
+        blr::encode_params(self.version, &self.charset, params, |_| {
+            blob_ids
+                .next()
+                .ok_or_else(|| "Blob parameters changed while encoding".into())
+        })
+    }
+
+    /// Create a new blob with the data, returning the blob id
+    async fn write_blob(
+        &mut self,
+        tr_handle: &mut TrHandle,
+        data: &[u8],
+    ) -> Result<BlobId, FbError> {
+        self.socket.write_all(&create_blob(tr_handle.0))?;
+        self.socket.flush().await?;
+
+        let resp = self.read_response().await?;
+        let (blob_handle, id) = (BlobHandle(resp.handle), BlobId(resp.object_id));
+
+        for segment in data.chunks(blr::MAX_DATA_LENGTH) {
+            self.socket
+                .write_all(&put_segment(blob_handle.0, segment))?;
+            self.socket.flush().await?;
+
+            self.read_response().await?;
+        }
+
+        self.close_blob(blob_handle).await?;
+
+        Ok(id)
+    }
+
+    /// Read all the data of a blob
+    async fn read_blob(
+        &mut self,
+        tr_handle: &mut TrHandle,
+        id: BlobId,
+    ) -> Result<Vec<u8>, FbError> {
+        self.socket.write_all(&open_blob(tr_handle.0, id.0))?;
+        self.socket.flush().await?;
+
+        let blob_handle = BlobHandle(self.read_response().await?.handle);
+
+        let mut blob_data = Vec::with_capacity(256);
+
+        loop {
+            self.socket.write_all(&get_segment(blob_handle.0))?;
+            self.socket.flush().await?;
+
+            let mut resp = self.read_response().await?;
+            parse_segments(&mut resp.data, &mut blob_data)?;
+
+            // No more segments
+            if resp.handle == 2 {
+                break;
+            }
+        }
+
+        self.close_blob(blob_handle).await?;
+
+        Ok(blob_data)
+    }
+
+    /// Closes a blob handle
+    async fn close_blob(&mut self, blob_handle: BlobHandle) -> Result<(), FbError> {
+        self.socket.write_all(&close_blob(blob_handle.0))?;
+        self.socket.flush().await?;
+
+        self.read_response().await?;
+
+        Ok(())
+    }
+
+    /// Read a server response
+    async fn read_response(&mut self) -> Result<Response, FbError> {
+        self.read_deferred().await?;
+
+        let res = self.read_and_parse(parse_response_packet).await;
+
+        // The server closed the session
+        if let Err(FbError::IdleTimeout(_)) = res {
+            self.socket.broken = true;
+        }
+
+        res
+    }
+
+    /// Read the responses of the deferred requests, that must come before the
+    /// response of the current request. Errors returned by the server are ignored,
+    /// as they are not related to the current request
+    async fn read_deferred(&mut self) -> Result<(), FbError> {
+        while self.deferred > 0 {
+            self.read_and_parse(parse_deferred_packet).await?;
+
+            self.deferred -= 1;
+        }
+
+        Ok(())
+    }
+
+    /// Parses the data received, reading more from the socket while the data is incomplete.
+    ///
+    /// The parsed bytes are consumed even on errors, as the server errors
+    /// are only returned after the whole packet was parsed
+    async fn read_and_parse<T>(
+        &mut self,
+        mut parse: impl FnMut(&mut Bytes) -> Result<T, FbError>,
+    ) -> Result<T, FbError> {
+        loop {
+            if let Some(v) = try_parse(&mut self.recv, &mut parse)? {
+                return Ok(v);
+            }
+
+            let len = self.socket.read(&mut self.buff).await?;
+            if len == 0 {
+                return err_invalid_response();
+            }
+
+            append_received(&mut self.recv, &self.buff[..len]);
+        }
+    }
+}
+
+/// Reads a packet from the socket
+async fn read_packet(socket: &mut AsyncFbStream, buff: &mut [u8]) -> Result<(u32, Bytes), FbError> {
+    let mut len = socket.read(buff).await?;
+    let mut resp = BytesMut::from(&buff[..len]);
+
+    while len == buff.len() {
+        // The buffer was not large enough, so read more
+        len = socket.read(buff).await?;
+        resp.put_slice(&buff[..len]);
+    }
+    let mut resp = resp.freeze();
+
+    let op_code = parse_op_code(&mut resp)?;
+
+    Ok((op_code, resp))
+}
+
+/// Opens the socket and sends the connection request, returning the server answer
+async fn connect_request(
+    config: &RustFbClientAttachmentConfig,
+    buff: &mut [u8],
+    req: impl FnOnce(&str) -> Result<Bytes, FbError>,
+) -> Result<(AsyncFbStream, Bytes), FbError> {
+    let tcp = tcp_connect(config).await?;
+
+    if let Some(time) = config.keepalive {
+        SockRef::from(&tcp).set_tcp_keepalive(&TcpKeepalive::new().with_time(time))?;
+    }
+
+    let hostname = tcp
+        .local_addr()
+        .map(|addr| addr.to_string())
+        .unwrap_or_default();
+
+    let mut socket = AsyncFbStream::new(tcp, buff.len(), config.io_timeout);
+
+    socket.write_all(&req(&hostname)?)?;
+    socket.flush().await?;
+
+    let len = socket.read(buff).await?;
+    let resp = Bytes::copy_from_slice(&buff[..len]);
+
+    Ok((socket, resp))
+}
+
+/// Connects to the configured host, respecting the connect timeout
+async fn tcp_connect(config: &RustFbClientAttachmentConfig) -> Result<TcpStream, FbError> {
+    let connect = TcpStream::connect((config.host.as_str(), config.port));
+
+    match config.connect_timeout {
+        Some(timeout) => time::timeout(timeout, connect).await.map_err(|_| {
+            FbError::Timeout(format!(
+                "Could not connect to '{}:{}' in {:?}",
+                config.host, config.port, timeout
+            ))
+        })?,
+        None => connect.await,
+    }
+    .map_err(FbError::from)
+}
+
+/// Reads the server answer to the authentication data, returning the next
+/// plugin requested by the server or `None` if the authentication is complete.
+/// The wire encryption keys sent by the server are added to `server_keys`
+async fn read_auth_result(
+    socket: &mut AsyncFbStream,
+    buff: &mut [u8],
+    server_keys: &mut ServerKeys,
+) -> Result<Option<ServerAuthData>, FbError> {
+    let (op_code, mut resp) = read_packet(socket, buff).await?;
+
+    if op_code == WireOp::ContAuth as u32 {
+        let auth_data = parse_cont_auth(&mut resp)?;
+        parse_server_keys(&mut auth_data.keys.clone(), server_keys)?;
+
+        return Ok(Some(auth_data));
+    }
+
+    if op_code != WireOp::Response as u32 {
+        return err_conn_rejected(op_code);
+    }
+
+    let mut resp = parse_response(&mut resp)?;
+    parse_server_keys(&mut resp.data, server_keys)?;
+
+    Ok(None)
+}
+
+/// In memory byte queue, the innermost stream of the encryption and compression layers
+#[derive(Clone, Default)]
+struct MemPipe(Arc<Mutex<VecDeque<u8>>>);
+
+impl MemPipe {
+    fn lock(&self) -> io::Result<MutexGuard<'_, VecDeque<u8>>> {
+        self.0
+            .lock()
+            .map_err(|_| io::Error::other("Buffer lock poisoned"))
+    }
+}
+
+impl Read for MemPipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.lock()?.read(buf)
+    }
+}
+
+impl Write for MemPipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.lock()?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Firebird stream over a tokio tcp connection, may be encrypted and / or compressed.
+///
+/// The layers are the same used by the blocking client, running over in memory
+/// buffers: the data written is only sent on `flush`, and the data received is
+/// decoded as it is read
+struct AsyncFbStream {
+    tcp: TcpStream,
+    /// Decodes the data in `received`
+    reader: StreamLayers<MemPipe>,
+    /// Encodes the data to `to_send`
+    writer: StreamLayers<MemPipe>,
+    /// Raw data received from the server, but not yet decoded
+    received: MemPipe,
+    /// Raw data waiting to be sent to the server
+    to_send: MemPipe,
+    /// Buffer to read the raw data from the socket
+    raw_buff: Box<[u8]>,
+    /// Maximum time for each read or write in the tcp connection
+    io_timeout: Option<Duration>,
+    /// An I/O error was found or an operation was cancelled, the stream is in an unknown state
+    broken: bool,
+}
+
+impl AsyncFbStream {
+    fn new(tcp: TcpStream, buf_len: usize, io_timeout: Option<Duration>) -> Self {
+        let received = MemPipe::default();
+        let to_send = MemPipe::default();
+
+        Self {
+            tcp,
+            reader: StreamLayers::Plain(received.clone()),
+            writer: StreamLayers::Plain(to_send.clone()),
+            received,
+            to_send,
+            raw_buff: vec![0; buf_len].into_boxed_slice(),
+            io_timeout,
+            broken: false,
+        }
+    }
+
+    /// Apply the same layer to both halves
+    fn map_layers(&mut self, f: impl Fn(StreamLayers<MemPipe>) -> StreamLayers<MemPipe>) {
+        let reader = mem::replace(&mut self.reader, StreamLayers::Plain(MemPipe::default()));
+        self.reader = f(reader);
+
+        let writer = mem::replace(&mut self.writer, StreamLayers::Plain(MemPipe::default()));
+        self.writer = f(writer);
+    }
+
+    /// Enable the zlib compression
+    fn compress(&mut self, buf_len: usize) {
+        self.map_layers(|s| s.compress(buf_len))
+    }
+
+    /// Enable the Arc4 encryption. If compressed, the compressed data will be encrypted
+    fn arc4(&mut self, key: &[u8], buf_len: usize) {
+        self.map_layers(|s| s.arc4(key, buf_len))
+    }
+
+    /// Enable the ChaCha encryption. If compressed, the compressed data will be encrypted
+    fn chacha(&mut self, chacha: ChaCha, buf_len: usize) {
+        self.map_layers(|s| s.chacha(chacha.clone(), buf_len))
+    }
+
+    /// Mark the stream as broken on errors
+    fn check<T>(&mut self, res: Result<T, FbError>) -> Result<T, FbError> {
+        if res.is_err() {
+            self.broken = true;
+        }
+        res
+    }
+
+    /// Encodes the data, that will only be sent in the next `flush`
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), FbError> {
+        let res = self.writer.write_all(buf).map_err(FbError::from);
+        self.check(res)
+    }
+
+    /// Sends all the data written
+    async fn flush(&mut self) -> Result<(), FbError> {
+        let res = self.writer.flush().map_err(FbError::from);
+        self.check(res)?;
+
+        let data: Vec<u8> = self.to_send.lock()?.iter().copied().collect();
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        let res = with_timeout(self.io_timeout, self.tcp.write_all(&data)).await;
+        self.check(res)?;
+
+        // Only discarded after sent, so nothing is lost if the write is interrupted
+        self.to_send.lock()?.drain(..data.len());
+
+        Ok(())
+    }
+
+    /// Reads and decodes the data received. Returns 0 if the server closed the connection
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, FbError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            let res = self.reader.read(buf).map_err(FbError::from);
+            let len = self.check(res)?;
+            if len > 0 {
+                return Ok(len);
+            }
+
+            // All the data received was decoded, so wait for more
+            let res = with_timeout(self.io_timeout, self.tcp.read(&mut self.raw_buff)).await;
+            let raw_len = self.check(res)?;
+
+            if raw_len == 0 {
+                // End of stream, the server closed the connection
+                self.broken = true;
+                return Ok(0);
+            }
+
+            self.received.lock()?.extend(&self.raw_buff[..raw_len]);
+        }
+    }
+}
+
+/// Runs the I/O operation, failing with `FbError::Timeout` if it takes longer than `timeout`
+async fn with_timeout<T>(
+    timeout: Option<Duration>,
+    op: impl Future<Output = io::Result<T>>,
+) -> Result<T, FbError> {
+    match timeout {
+        Some(timeout) => time::timeout(timeout, op)
+            .await
+            .map_err(|_| {
+                FbError::Timeout(format!("Network operation timed out after {:?}", timeout))
+            })?
+            .map_err(FbError::from),
+        None => op.await.map_err(FbError::from),
+    }
+}
+
+#[tokio::test]
+async fn async_stream_test() {
+    use tokio::net::TcpListener;
+
+    // Echoes all the data received
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let (mut reader, mut writer) = socket.split();
+        tokio::io::copy(&mut reader, &mut writer).await.unwrap();
+    });
+
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    // Small buffer, to force multiple reads
+    let mut socket = AsyncFbStream::new(tcp, 16, Some(Duration::from_secs(5)));
+
+    socket.compress(16);
+    socket.arc4(b"a key", 16);
+
+    // Same states in both halves, so the echoed data is decoded back
+    let data: Vec<u8> = (0..100u8).cycle().take(1000).collect();
+    socket.write_all(&data).unwrap();
+    socket.flush().await.unwrap();
+
+    let mut received = Vec::new();
+    let mut buf = [0; 64];
+    while received.len() < data.len() {
+        let len = socket.read(&mut buf).await.unwrap();
+        assert!(len > 0);
+        received.extend_from_slice(&buf[..len]);
+    }
+    assert_eq!(received, data);
+    assert!(!socket.broken);
+}
+
+#[tokio::test]
+async fn async_io_timeout_test() {
+    use tokio::net::TcpListener;
+
+    // Accepts the connection, but never answers
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+    let config = RustFbClientAttachmentConfig {
+        host: "127.0.0.1".to_string(),
+        port: listener.local_addr().unwrap().port(),
+        connect_timeout: Some(Duration::from_secs(5)),
+        io_timeout: Some(Duration::from_millis(50)),
+        keepalive: Some(Duration::from_secs(60)),
+        ..Default::default()
+    };
+
+    let res = AsyncFirebirdWireConnection::connect(&config, rsfbclient_core::charset::UTF_8).await;
+
+    assert!(matches!(res, Err(FbError::Timeout(_))));
+}
+
+#[tokio::test]
+async fn cancelled_op_test() {
+    use tokio::net::TcpListener;
+
+    // Accepts the connection, but never answers
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let tcp = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (_server, _) = listener.accept().await.unwrap();
+
+    let buff = vec![0; BUFFER_LENGTH as usize].into_boxed_slice();
+    let mut client = AsyncRustFbClient {
+        conn: Some(AsyncFirebirdWireConnection {
+            socket: AsyncFbStream::new(tcp, buff.len(), None),
+            version: ProtocolVersion::V13,
+            buff,
+            recv: Bytes::new(),
+            lazy_send: true,
+            deferred: 0,
+            charset: rsfbclient_core::charset::UTF_8,
+            fetch_size: DEFAULT_FETCH_SIZE,
+        }),
+        charset: rsfbclient_core::charset::UTF_8,
+    };
+    assert!(!client.is_broken());
+
+    // Cancelled while waiting for the response
+    let res = time::timeout(Duration::from_millis(50), client.ping(&mut DbHandle(0))).await;
+    assert!(res.is_err());
+
+    assert!(client.is_broken());
+}
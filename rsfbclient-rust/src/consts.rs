@@ -11,8 +11,31 @@ pub enum ProtocolVersion {
     V11 = 0xFFFF800B,
     V12 = 0xFFFF800C,
     V13 = 0xFFFF800D,
+    /// Adds statement timeouts and the batch api (Firebird 4)
+    V16 = 0xFFFF8010,
 }
 
+#[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq, TryFromPrimitive)]
+#[repr(u32)]
+/// Wire protocol type, defines how the requests can be sent to the server
+pub enum ProtocolType {
+    /// One response for each request
+    Rpc = 2,
+    /// Multiple requests can be sent before reading the responses
+    BatchSend = 3,
+    /// Batch send, with out of band data
+    OutOfBand = 4,
+    /// Batch send, where some requests (like freeing statements) can be deferred and
+    /// sent together with the next request
+    LazySend = 5,
+}
+
+/// Mask to get the `ProtocolType` from the accepted type, removing the flags
+pub const PROTOCOL_TYPE_MASK: u32 = 0xFF;
+
+/// Protocol flag to request / accept the zlib wire compression, only for the protocol 13 and up
+pub const PFLAG_COMPRESS: u32 = 0x100;
+
 #[derive(Debug, TryFromPrimitive)]
 #[repr(u8)]
 /// Wire protocol operation
@@ -93,6 +116,8 @@ pub enum WireOp {
     FreeStatement = 67,
     /// Prepare a statement
     PrepareStatement = 68,
+    /// Set the cursor name of a statement
+    SetCursor = 69,
     /// Statement info
     InfoSql = 70,
 
@@ -134,6 +159,19 @@ pub enum WireOp {
     /// Server accepts connection, returns some data to client
     /// and asks client to continue authentication before attach call
     CondAccept = 98,
+
+    /// Create a batch for a prepared statement
+    BatchCreate = 99,
+    /// Add messages (parameter sets) to the batch
+    BatchMsg = 100,
+    /// Execute the batch
+    BatchExec = 101,
+    /// Release the batch
+    BatchRls = 102,
+    /// Batch completion state, response of the batch execution
+    BatchCs = 103,
+    /// Register an existing blob to be used in the batch messages
+    BatchRegblob = 104,
 }
 
 #[derive(Debug)]
@@ -166,36 +204,6 @@ pub enum Cnct {
     ClientCrypt = 11,
 }
 
-#[derive(Debug)]
-pub enum AuthPluginType {
-    Srp256,
-    Srp,
-}
-
-impl AuthPluginType {
-    /// Plugin name
-    pub fn name(&self) -> &'static str {
-        match self {
-            Self::Srp256 => "Srp256",
-            Self::Srp => "Srp",
-        }
-    }
-
-    /// List with the plugins
-    pub fn plugin_list() -> String {
-        [AuthPluginType::Srp.name(), AuthPluginType::Srp256.name()].join(",")
-    }
-
-    pub fn parse(name: &[u8]) -> Result<Self, rsfbclient_core::FbError> {
-        match name {
-            b"Srp256" => Ok(Self::Srp256),
-            b"Srp" => Ok(Self::Srp),
-
-            name => Err(format!("Invalid auth plugin: {}", String::from_utf8_lossy(name)).into()),
-        }
-    }
-}
-
 #[cfg(not(tarpaulin_include))]
 /// Converts a gds_code to a error message
 pub fn gds_to_msg(gds_code: u32) -> &'static str {
@@ -1943,3 +1951,42 @@ pub mod blr {
     pub const SUBFUNC: u8 = 208;
     pub const RECORD_VERSION2: u8 = 209;
 }
+
+/// Batch parameters block constants
+pub mod batch {
+    pub const VERSION1: u8 = 1;
+
+    /// Continue executing the messages after an error
+    pub const TAG_MULTIERROR: u8 = 1;
+    /// Return the number of affected rows of each message
+    pub const TAG_RECORD_COUNTS: u8 = 2;
+    /// Size of the buffer used to store the messages in the server
+    pub const TAG_BUFFER_BYTES_SIZE: u8 = 3;
+    /// How the blobs used in the messages are identified
+    pub const TAG_BLOB_POLICY: u8 = 4;
+    /// Number of detailed errors returned in the completion state
+    pub const TAG_DETAILED_ERRORS: u8 = 5;
+
+    /// The blobs ids in the messages are defined by the client
+    pub const BLOB_ID_USER: u32 = 2;
+
+    /// Affected rows count of a message that failed
+    pub const EXECUTE_FAILED: i32 = -1;
+    /// Message executed, but without the number of affected rows
+    pub const SUCCESS_NO_INFO: i32 = -2;
+}
+
+/// Wire encryption keys sent by the server, as `tag` `length` `data` items
+pub mod crypt_keys {
+    /// Key type, followed by a `TAG_KEY_PLUGINS` item
+    pub const TAG_KEY_TYPE: u8 = 0;
+    /// Plugins that can use the key, separated by spaces
+    pub const TAG_KEY_PLUGINS: u8 = 1;
+    /// Plugins known by the server
+    pub const TAG_KNOWN_PLUGINS: u8 = 2;
+    /// Plugin name, a zero byte and the plugin specific data
+    pub const TAG_PLUGIN_SPECIFIC: u8 = 3;
+
+    /// Key type generated by the authentication, used by the wire encryption
+    pub const KEY_TYPE_SYMMETRIC: &str = "Symmetric";
+}
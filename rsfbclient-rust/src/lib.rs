@@ -1,15 +1,25 @@
 //! Firebird client implementation in pure rust
 
 mod arc4;
+mod auth;
 mod blr;
+mod chacha;
 mod client;
+#[cfg(feature = "async-tokio")]
+mod client_async;
 mod consts;
 mod srp;
+mod transport;
 mod util;
 mod wire;
 mod xsqlda;
+mod zlib;
 
+pub use auth::{AuthPlugin, AuthPluginFactory, LegacyAuth, Srp};
 pub use client::{DbHandle, RustFbClient, RustFbClientAttachmentConfig, StmtHandle, TrHandle};
+#[cfg(feature = "async-tokio")]
+pub use client_async::AsyncRustFbClient;
+pub use transport::{Transport, TransportConnector};
 
 #[cfg(feature = "fuzz_testing")]
 pub use self::{blr::*, wire::*, xsqlda::*};
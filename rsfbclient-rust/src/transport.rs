@@ -0,0 +1,208 @@
+//! Streams used to communicate with the firebird server
+
+use socket2::{SockRef, TcpKeepalive};
+use std::{
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use crate::client::RustFbClientAttachmentConfig;
+use rsfbclient_core::FbError;
+
+/// Stream used to communicate with the server, like a tcp
+/// connection, a ssh tunnel or an in memory stream
+pub trait Transport: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Transport for T {}
+
+/// Opens a stream to the server with the configured host and port.
+/// Called for each new connection
+pub type TransportConnector =
+    Arc<dyn Fn(&str, u16) -> Result<Box<dyn Transport>, FbError> + Send + Sync>;
+
+/// Read and write halves of an opened transport
+pub(crate) struct SplitTransport {
+    pub reader: Box<dyn Read + Send>,
+    pub writer: Box<dyn Write + Send>,
+    /// The writer can be used while the reader is blocked, needed to cancel operations
+    pub independent: bool,
+    /// Local address, if known
+    pub local_addr: String,
+}
+
+/// Opens the configured transport, or a tcp connection by default
+///
+/// The timeouts and keepalive are only applied to the tcp connection
+pub(crate) fn open_transport(
+    config: &RustFbClientAttachmentConfig,
+) -> Result<SplitTransport, FbError> {
+    if let Some(connector) = &config.transport {
+        let shared = SharedTransport(Arc::new(Mutex::new(connector(&config.host, config.port)?)));
+
+        return Ok(SplitTransport {
+            reader: Box::new(shared.clone()),
+            writer: Box::new(shared),
+            independent: false,
+            local_addr: String::new(),
+        });
+    }
+
+    let socket = tcp_connect(config)?;
+
+    socket.set_read_timeout(config.io_timeout)?;
+    socket.set_write_timeout(config.io_timeout)?;
+
+    if let Some(time) = config.keepalive {
+        SockRef::from(&socket).set_tcp_keepalive(&TcpKeepalive::new().with_time(time))?;
+    }
+
+    let local_addr = socket
+        .local_addr()
+        .map(|addr| addr.to_string())
+        .unwrap_or_default();
+
+    let (reader, writer): (Box<dyn Read + Send>, Box<dyn Write + Send>) = match config.io_timeout {
+        Some(timeout) => (
+            Box::new(TimeoutStream(socket.try_clone()?, timeout)),
+            Box::new(TimeoutStream(socket, timeout)),
+        ),
+        None => (Box::new(socket.try_clone()?), Box::new(socket)),
+    };
+
+    Ok(SplitTransport {
+        reader,
+        writer,
+        independent: true,
+        local_addr,
+    })
+}
+
+/// Tcp stream with a read / write timeout, returning the expired
+/// timeouts as a `FbError::Timeout` wrapped in the io error
+struct TimeoutStream(TcpStream, Duration);
+
+impl TimeoutStream {
+    fn map_err(&self, e: io::Error) -> io::Error {
+        match e.kind() {
+            // A read / write timeout returns `WouldBlock` on unix platforms
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => io::Error::new(
+                io::ErrorKind::TimedOut,
+                FbError::Timeout(format!("Network operation timed out after {:?}", self.1)),
+            ),
+            _ => e,
+        }
+    }
+}
+
+impl Read for TimeoutStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf).map_err(|e| self.map_err(e))
+    }
+}
+
+impl Write for TimeoutStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf).map_err(|e| self.map_err(e))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush().map_err(|e| self.map_err(e))
+    }
+}
+
+/// Transport used by both halves, locked in each call
+#[derive(Clone)]
+struct SharedTransport(Arc<Mutex<Box<dyn Transport>>>);
+
+impl SharedTransport {
+    fn lock(&self) -> io::Result<MutexGuard<'_, Box<dyn Transport>>> {
+        self.0
+            .lock()
+            .map_err(|_| io::Error::other("Transport lock poisoned"))
+    }
+}
+
+impl Read for SharedTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.lock()?.read(buf)
+    }
+}
+
+impl Write for SharedTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.lock()?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.lock()?.flush()
+    }
+}
+
+/// Connects to the first address of the host that accepts the connection
+fn tcp_connect(config: &RustFbClientAttachmentConfig) -> Result<TcpStream, FbError> {
+    let timeout = match config.connect_timeout {
+        Some(timeout) => timeout,
+        None => return Ok(TcpStream::connect((config.host.as_str(), config.port))?),
+    };
+
+    let mut last_err = None;
+
+    for addr in (config.host.as_str(), config.port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(socket) => return Ok(socket),
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                last_err = Some(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    FbError::Timeout(format!(
+                        "Could not connect to '{}:{}' in {:?}",
+                        config.host, config.port, timeout
+                    )),
+                ))
+            }
+            Err(e) => last_err = Some(e),
+        }
+    }
+
+    Err(last_err
+        .unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Could not resolve the host '{}'", config.host),
+            )
+        })
+        .into())
+}
+
+#[test]
+fn io_timeout_test() {
+    use std::{net::TcpListener, time::Duration};
+
+    // Accepts the connection, but never answers
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+
+    let config = RustFbClientAttachmentConfig {
+        host: "127.0.0.1".to_string(),
+        port: listener.local_addr().unwrap().port(),
+        connect_timeout: Some(Duration::from_secs(5)),
+        io_timeout: Some(Duration::from_millis(50)),
+        keepalive: Some(Duration::from_secs(60)),
+        ..Default::default()
+    };
+
+    let mut transport = open_transport(&config).unwrap();
+    assert!(transport.local_addr.starts_with("127.0.0.1:"));
+    assert!(transport.independent);
+
+    let err = FbError::from(transport.reader.read(&mut [0; 8]).unwrap_err());
+    assert!(matches!(err, FbError::Timeout(_)));
+}
+
+#[test]
+fn io_error_without_timeout_test() {
+    // Without a configured timeout, the error is not a `Timeout`
+    let err = FbError::from(io::Error::from(io::ErrorKind::WouldBlock));
+    assert!(matches!(err, FbError::Io(_)));
+    assert!(std::error::Error::source(&err).is_some());
+}
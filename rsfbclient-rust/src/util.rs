@@ -176,8 +176,17 @@ impl BytesWireExt for Bytes {
     }
 }
 
+/// Message of the error returned when the response is incomplete
+const MISSING_BYTES_MSG: &str = "Invalid server response, missing bytes";
+
 pub fn err_invalid_response<T>() -> Result<T, FbError> {
-    Err("Invalid server response, missing bytes".into())
+    Err(MISSING_BYTES_MSG.into())
+}
+
+/// Returns `true` if the error was caused by an incomplete response,
+/// so parsing it again after receiving more data may succeed
+pub fn is_missing_bytes(e: &FbError) -> bool {
+    matches!(e, FbError::Other(msg) if msg == MISSING_BYTES_MSG)
 }
 
 pub fn err_conn_rejected<T>(op_code: u32) -> Result<T, FbError> {
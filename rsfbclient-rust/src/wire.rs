@@ -7,31 +7,52 @@ use std::{convert::TryFrom, str};
 
 use crate::{
     client::{BlobId, FirebirdWireConnection},
-    consts::{gds_to_msg, AuthPluginType, Cnct, ProtocolVersion, WireOp},
-    srp::*,
+    consts::{
+        batch, crypt_keys, gds_to_msg, Cnct, ProtocolType, ProtocolVersion, WireOp, PFLAG_COMPRESS,
+        PROTOCOL_TYPE_MASK,
+    },
     util::*,
     xsqlda::{XSqlVar, XSQLDA_DESCRIBE_VARS},
 };
-use rsfbclient_core::{ibase, Charset, Column, FbError, FreeStmtOp, SqlType, TrOp};
+use rsfbclient_core::{
+    err_status_vector, ibase, BatchRowState, CancelKind, Charset, Column, FbError, FreeStmtOp,
+    SqlType, TrOp, WireCrypt, WireCryptPlugin,
+};
 
 /// Buffer length to use in the connection
 pub const BUFFER_LENGTH: u32 = 1024;
 
 /// Connection request
+#[allow(clippy::too_many_arguments)]
 pub fn connect(
     db_name: &str,
     create_db: bool,
     user: &str,
     username: &str,
     hostname: &str,
-    srp_key: &[u8],
+    plugin: &str,
+    plugin_list: &str,
+    plugin_data: &[u8],
+    compress: bool,
+    wire_crypt: WireCrypt,
 ) -> Bytes {
+    // Allow up to the lazy send type, so the server can accept deferred requests
+    let max_type = ProtocolType::LazySend as u32;
+
+    // Compression is only supported from the protocol 13
+    let max_type_v13 = if compress {
+        max_type | PFLAG_COMPRESS
+    } else {
+        max_type
+    };
+
     let protocols = [
         // PROTOCOL_VERSION, Arch type (Generic=1), min, max, weight
-        [ProtocolVersion::V10 as u32, 1, 0, 5, 2],
-        [ProtocolVersion::V11 as u32, 1, 0, 5, 4],
-        [ProtocolVersion::V12 as u32, 1, 0, 5, 6],
-        [ProtocolVersion::V13 as u32, 1, 0, 5, 8],
+        [ProtocolVersion::V10 as u32, 1, 0, max_type, 2],
+        [ProtocolVersion::V11 as u32, 1, 0, max_type, 4],
+        [ProtocolVersion::V12 as u32, 1, 0, max_type, 6],
+        [ProtocolVersion::V13 as u32, 1, 0, max_type_v13, 8],
+        [ProtocolVersion::V16 as u32, 1, 0, max_type_v13, 10],
     ];
 
     let mut connect = BytesMut::with_capacity(256);
@@ -51,44 +72,103 @@ pub fn connect(
     // Protocol versions understood
     connect.put_u32(protocols.len() as u32);
 
-    // Request SRP by default, so use Sha1
-    let srp = SrpClient::<sha1::Sha1>::new(srp_key, &SRP_GROUP);
-
     let uid = {
         let mut uid = BytesMut::new();
 
-        let pubkey = hex::encode(srp.get_a_pub());
-
         // Database username
         uid.put_u8(Cnct::Login as u8);
         uid.put_u8(user.len() as u8);
         uid.put(user.as_bytes());
 
-        // Request SRP by default
-        let plugin = AuthPluginType::Srp.name();
-
+        // Preferred plugin, the server will use the data if it supports it
         uid.put_u8(Cnct::PluginName as u8);
         uid.put_u8(plugin.len() as u8);
         uid.put(plugin.as_bytes());
 
-        let plugin_list = AuthPluginType::plugin_list();
-
         uid.put_u8(Cnct::PluginList as u8);
         uid.put_u8(plugin_list.len() as u8);
         uid.put(plugin_list.as_bytes());
 
-        for (i, pk_chunk) in pubkey.as_bytes().chunks(254).enumerate() {
+        for (i, chunk) in plugin_data.chunks(254).enumerate() {
             uid.put_u8(Cnct::SpecificData as u8);
-            uid.put_u8(pk_chunk.len() as u8 + 1);
+            uid.put_u8(chunk.len() as u8 + 1);
             uid.put_u8(i as u8);
-            uid.put(pk_chunk);
+            uid.put(chunk);
         }
 
-        let wire_crypt = "\x01\x00\x00\x00";
+        let wire_crypt = (wire_crypt as u32).to_le_bytes();
 
         uid.put_u8(Cnct::ClientCrypt as u8);
         uid.put_u8(wire_crypt.len() as u8);
-        uid.put(wire_crypt.as_bytes());
+        uid.put(&wire_crypt[..]);
+
+        // System username
+        uid.put_u8(Cnct::User as u8);
+        uid.put_u8(username.len() as u8);
+        uid.put(username.as_bytes());
+
+        uid.put_u8(Cnct::Host as u8);
+        uid.put_u8(hostname.len() as u8);
+        uid.put(hostname.as_bytes());
+
+        uid.put_u8(Cnct::UserVerification as u8);
+        uid.put_u8(0);
+
+        uid.freeze()
+    };
+    connect.put_wire_bytes(&uid);
+
+    // Protocols
+    for i in protocols.iter().flatten() {
+        connect.put_u32(*i);
+    }
+
+    connect.freeze()
+}
+
+/// Connection request for the servers older than Firebird 3, without the
+/// authentication plugins. The password is verified in the attach
+pub fn connect_legacy(
+    db_name: &str,
+    user: &str,
+    username: &str,
+    hostname: &str,
+    pass_hash: &str,
+) -> Bytes {
+    let max_type = ProtocolType::LazySend as u32;
+
+    let protocols = [
+        // PROTOCOL_VERSION, Arch type (Generic=1), min, max, weight
+        [ProtocolVersion::V10 as u32, 1, 0, max_type, 2],
+        [ProtocolVersion::V11 as u32, 1, 0, max_type, 4],
+        [ProtocolVersion::V12 as u32, 1, 0, max_type, 6],
+    ];
+
+    let mut connect = BytesMut::with_capacity(256);
+
+    connect.put_u32(WireOp::Connect as u32);
+    connect.put_u32(WireOp::Attach as u32);
+    connect.put_u32(3); // CONNECT_VERSION
+    connect.put_u32(1); // arch_generic
+
+    // Db file path / name
+    connect.put_wire_bytes(db_name.as_bytes());
+
+    // Protocol versions understood
+    connect.put_u32(protocols.len() as u32);
+
+    let uid = {
+        let mut uid = BytesMut::new();
+
+        // Database username
+        uid.put_u8(Cnct::Login as u8);
+        uid.put_u8(user.len() as u8);
+        uid.put(user.as_bytes());
+
+        // Hashed password
+        uid.put_u8(Cnct::Passwd as u8);
+        uid.put_u8(pass_hash.len() as u8);
+        uid.put(pass_hash.as_bytes());
 
         // System username
         uid.put_u8(Cnct::User as u8);
@@ -114,15 +194,24 @@ pub fn connect(
     connect.freeze()
 }
 
+/// Password hash used by the `Legacy_Auth` plugin and the protocols older than 13
+pub fn legacy_auth_hash(pass: &str) -> Result<String, FbError> {
+    #[allow(deprecated)]
+    let enc_pass = pwhash::unix_crypt::hash_with("9z", pass)
+        .map_err(|e| FbError::from(format!("Error hashing the password: {}", e)))?;
+
+    // Without the salt
+    Ok(enc_pass[2..].to_string())
+}
+
 /// Continue authentication request
-pub fn cont_auth(data: &[u8], plugin: AuthPluginType, plugin_list: String, keys: &[u8]) -> Bytes {
-    let mut req = BytesMut::with_capacity(
-        20 + data.len() + plugin.name().len() + plugin_list.len() + keys.len(),
-    );
+pub fn cont_auth(data: &[u8], plugin: &str, plugin_list: &str, keys: &[u8]) -> Bytes {
+    let mut req =
+        BytesMut::with_capacity(20 + data.len() + plugin.len() + plugin_list.len() + keys.len());
 
     req.put_u32(WireOp::ContAuth as u32);
     req.put_wire_bytes(data);
-    req.put_wire_bytes(plugin.name().as_bytes());
+    req.put_wire_bytes(plugin.as_bytes());
     req.put_wire_bytes(plugin_list.as_bytes());
     req.put_wire_bytes(keys);
 
@@ -142,14 +231,27 @@ pub fn crypt(algo: &str, kind: &str) -> Bytes {
     req.freeze()
 }
 
-/// Attach request
+/// Cancel request, for the operation in progress on the connection
+pub fn cancel(kind: CancelKind) -> Bytes {
+    let mut req = BytesMut::with_capacity(8);
+
+    req.put_u32(WireOp::Cancel as u32);
+    req.put_u32(kind as u32);
+
+    req.freeze()
+}
+
+/// Attach request, or create request with `WireOp::Create`.
+/// The `extra_dpb` items are appended to the database parameter buffer
 pub fn attach(
+    op: WireOp,
     db_name: &str,
     user: &str,
     pass: &str,
     protocol: ProtocolVersion,
     charset: Charset,
-) -> Bytes {
+    extra_dpb: &[u8],
+) -> Result<Bytes, FbError> {
     let dpb = {
         let mut dpb = BytesMut::with_capacity(64);
 
@@ -172,31 +274,31 @@ pub fn attach(
 
             // Hashed password
             ProtocolVersion::V11 | ProtocolVersion::V12 => {
-                #[allow(deprecated)]
-                let enc_pass = pwhash::unix_crypt::hash_with("9z", pass).unwrap();
-                let enc_pass = &enc_pass[2..];
+                let enc_pass = legacy_auth_hash(pass)?;
 
                 dpb.put_slice(&[ibase::isc_dpb_password_enc as u8, enc_pass.len() as u8]);
                 dpb.put_slice(enc_pass.as_bytes());
             }
 
             // Password already verified
-            ProtocolVersion::V13 => {}
+            ProtocolVersion::V13 | ProtocolVersion::V16 => {}
         }
 
+        dpb.put_slice(extra_dpb);
+
         dpb.freeze()
     };
 
     let mut attach = BytesMut::with_capacity(16 + db_name.len() + dpb.len());
 
-    attach.put_u32(WireOp::Attach as u32);
+    attach.put_u32(op as u32);
     attach.put_u32(0); // Database Object ID
 
     attach.put_wire_bytes(db_name.as_bytes());
 
     attach.put_wire_bytes(&dpb);
 
-    attach.freeze()
+    Ok(attach.freeze())
 }
 
 /// Detach from the database request
@@ -209,6 +311,28 @@ pub fn detach(db_handle: u32) -> Bytes {
     tr.freeze()
 }
 
+/// Ping request, to check if the server is reachable (protocol 13+)
+pub fn ping() -> Bytes {
+    let mut req = BytesMut::with_capacity(4);
+
+    req.put_u32(WireOp::Ping as u32);
+
+    req.freeze()
+}
+
+/// Database info request without items, used as a ping for the older protocols
+pub fn info_database_ping(db_handle: u32) -> Bytes {
+    let mut req = BytesMut::with_capacity(20);
+
+    req.put_u32(WireOp::InfoDatabase as u32);
+    req.put_u32(db_handle);
+    req.put_u32(0); // Incarnation of object
+    req.put_wire_bytes(&[ibase::isc_info_end as u8]);
+    req.put_u32(16); // Buffer length
+
+    req.freeze()
+}
+
 /// Drop database request
 pub fn drop_database(db_handle: u32) -> Bytes {
     let mut tr = BytesMut::with_capacity(8);
@@ -339,9 +463,37 @@ pub fn free_statement(stmt_handle: u32, op: FreeStmtOp) -> Bytes {
     req.freeze()
 }
 
-/// Execute prepared statement request.
-pub fn execute(tr_handle: u32, stmt_handle: u32, input_blr: &[u8], input_data: &[u8]) -> Bytes {
-    let mut req = BytesMut::with_capacity(36 + input_blr.len() + input_data.len());
+/// Set cursor name request
+pub fn set_cursor(
+    stmt_handle: u32,
+    cursor_name: &str,
+    charset: &Charset,
+) -> Result<Bytes, FbError> {
+    let mut bytes = charset.encode(cursor_name)?.into_owned();
+    // The server expects a null terminated name
+    bytes.push(0);
+
+    let mut req = BytesMut::with_capacity(16 + bytes.len());
+
+    req.put_u32(WireOp::SetCursor as u32);
+    req.put_u32(stmt_handle);
+    req.put_wire_bytes(&bytes);
+    req.put_u32(0); // Cursor type, unused
+
+    Ok(req.freeze())
+}
+
+/// Execute prepared statement request. The `timeout` in milliseconds
+/// is sent in the protocol 16+, 0 for the timeout of the session
+pub fn execute(
+    tr_handle: u32,
+    stmt_handle: u32,
+    input_blr: &[u8],
+    input_data: &[u8],
+    timeout: u32,
+    version: ProtocolVersion,
+) -> Bytes {
+    let mut req = BytesMut::with_capacity(40 + input_blr.len() + input_data.len());
 
     req.put_u32(WireOp::Execute as u32);
     req.put_u32(stmt_handle);
@@ -353,6 +505,10 @@ pub fn execute(tr_handle: u32, stmt_handle: u32, input_blr: &[u8], input_data: &
 
     req.put_slice(input_data);
 
+    if version >= ProtocolVersion::V16 {
+        req.put_u32(timeout); // Statement timeout, in milliseconds
+    }
+
     req.freeze()
 }
 
@@ -363,9 +519,11 @@ pub fn execute2(
     input_blr: &[u8],
     input_data: &[u8],
     output_blr: &[u8],
+    timeout: u32,
+    version: ProtocolVersion,
 ) -> Bytes {
     let mut req =
-        BytesMut::with_capacity(40 + input_blr.len() + input_data.len() + output_blr.len());
+        BytesMut::with_capacity(44 + input_blr.len() + input_data.len() + output_blr.len());
 
     req.put_u32(WireOp::Execute2 as u32);
     req.put_u32(stmt_handle);
@@ -380,18 +538,22 @@ pub fn execute2(
     req.put_wire_bytes(output_blr);
     req.put_u32(0); // Output message number
 
+    if version >= ProtocolVersion::V16 {
+        req.put_u32(timeout); // Statement timeout, in milliseconds
+    }
+
     req.freeze()
 }
 
-/// Fetch row request
-pub fn fetch(stmt_handle: u32, blr: &[u8]) -> Bytes {
+/// Fetch rows request, the server will return up to `count` rows
+pub fn fetch(stmt_handle: u32, blr: &[u8], count: u32) -> Bytes {
     let mut req = BytesMut::with_capacity(20 + blr.len());
 
     req.put_u32(WireOp::Fetch as u32);
     req.put_u32(stmt_handle);
     req.put_wire_bytes(blr);
     req.put_u32(0); // Message number
-    req.put_u32(1); // Message count TODO: increase to return more rows in one fetch request
+    req.put_u32(count); // Message count
 
     req.freeze()
 }
@@ -430,6 +592,20 @@ pub fn get_segment(blob_handle: u32) -> Bytes {
     req.freeze()
 }
 
+/// Parse the data of a get blob segment response, appending the segments to `blob_data`
+pub fn parse_segments(data: &mut Bytes, blob_data: &mut Vec<u8>) -> Result<(), FbError> {
+    while data.remaining() >= 2 {
+        let len = data.get_u16_le()? as usize;
+        if data.remaining() < len {
+            return err_invalid_response();
+        }
+        blob_data.put_slice(&data[..len]);
+        data.advance(len)?;
+    }
+
+    Ok(())
+}
+
 /// Put blob segment request
 pub fn put_segment(blob_handle: u32, segment: &[u8]) -> Bytes {
     let mut req = BytesMut::with_capacity(8 + segment.len());
@@ -452,6 +628,83 @@ pub fn close_blob(blob_handle: u32) -> Bytes {
     req.freeze()
 }
 
+/// Create batch request, the messages will use the format described in the `blr`
+pub fn batch_create(stmt_handle: u32, blr: &[u8], msg_len: u32, buffer_size: u32) -> Bytes {
+    let pb = {
+        let mut pb = BytesMut::with_capacity(32);
+
+        pb.put_u8(batch::VERSION1);
+
+        for &(tag, val) in &[
+            (batch::TAG_MULTIERROR, 1),
+            (batch::TAG_RECORD_COUNTS, 1),
+            (batch::TAG_BUFFER_BYTES_SIZE, buffer_size),
+            (batch::TAG_BLOB_POLICY, batch::BLOB_ID_USER),
+        ] {
+            pb.put_u8(tag);
+            pb.put_u32_le(4);
+            pb.put_u32_le(val);
+        }
+
+        pb.freeze()
+    };
+
+    let mut req = BytesMut::with_capacity(20 + blr.len() + pb.len());
+
+    req.put_u32(WireOp::BatchCreate as u32);
+    req.put_u32(stmt_handle);
+    req.put_wire_bytes(blr);
+    req.put_u32(msg_len);
+    req.put_wire_bytes(&pb);
+
+    req.freeze()
+}
+
+/// Register blob in batch request, allowing the messages to reference an existing blob
+pub fn batch_regblob(stmt_handle: u32, blob_id: BlobId) -> Bytes {
+    let mut req = BytesMut::with_capacity(24);
+
+    req.put_u32(WireOp::BatchRegblob as u32);
+    req.put_u32(stmt_handle);
+    req.put_u64(blob_id.0); // Existing blob id
+    req.put_u64(blob_id.0); // Id used in the messages
+
+    req.freeze()
+}
+
+/// Add messages to the batch request
+pub fn batch_msg(stmt_handle: u32, count: u32, data: &[u8]) -> Bytes {
+    let mut req = BytesMut::with_capacity(12 + data.len());
+
+    req.put_u32(WireOp::BatchMsg as u32);
+    req.put_u32(stmt_handle);
+    req.put_u32(count);
+    req.put_slice(data);
+
+    req.freeze()
+}
+
+/// Execute batch request
+pub fn batch_exec(stmt_handle: u32, tr_handle: u32) -> Bytes {
+    let mut req = BytesMut::with_capacity(12);
+
+    req.put_u32(WireOp::BatchExec as u32);
+    req.put_u32(stmt_handle);
+    req.put_u32(tr_handle);
+
+    req.freeze()
+}
+
+/// Release batch request
+pub fn batch_rls(stmt_handle: u32) -> Bytes {
+    let mut req = BytesMut::with_capacity(8);
+
+    req.put_u32(WireOp::BatchRls as u32);
+    req.put_u32(stmt_handle);
+
+    req.freeze()
+}
+
 #[derive(Debug)]
 /// `WireOp::Response` response
 pub struct Response {
@@ -476,22 +729,210 @@ pub fn parse_response(resp: &mut Bytes) -> Result<Response, FbError> {
     })
 }
 
-/// Parse a server sql response (`WireOp::FetchResponse`)
+/// Parse the operation code of the next packet, skipping the dummy packets
+pub fn parse_op_code(resp: &mut Bytes) -> Result<u32, FbError> {
+    loop {
+        let op_code = resp.get_u32()?;
+
+        if op_code != WireOp::Dummy as u32 {
+            return Ok(op_code);
+        }
+    }
+}
+
+/// Parse the next packet with the `parse` function if it has the `expected` operation code,
+/// returning the error sent by the server if it is a `WireOp::Response`
+fn parse_packet<T>(
+    resp: &mut Bytes,
+    expected: WireOp,
+    parse: impl FnOnce(&mut Bytes) -> Result<T, FbError>,
+) -> Result<T, FbError> {
+    let expected = expected as u32;
+    let op_code = parse_op_code(resp)?;
+
+    if op_code == WireOp::Response as u32 && expected != op_code {
+        // An error ocurred
+        parse_response(resp)?;
+    }
+
+    if op_code != expected {
+        return err_conn_rejected(op_code);
+    }
+
+    parse(resp)
+}
+
+/// Parse the next packet, that must be a server response (`WireOp::Response`)
+pub fn parse_response_packet(resp: &mut Bytes) -> Result<Response, FbError> {
+    parse_packet(resp, WireOp::Response, parse_response)
+}
+
+/// Parse the next packet, that must be the server response of a deferred request.
+/// Errors returned by the server are ignored, as they are not related to the current request
+pub fn parse_deferred_packet(resp: &mut Bytes) -> Result<(), FbError> {
+    parse_packet(resp, WireOp::Response, |resp| match parse_response(resp) {
+        Err(e) if is_missing_bytes(&e) => Err(e),
+        _ => Ok(()),
+    })
+}
+
+/// Parses the `data` received, returning `None` if it is incomplete,
+/// so it can be parsed again after appending more data with `append_received`.
+///
+/// The parsed bytes are consumed from `data` even on errors, as the server errors
+/// are only returned after the whole packet was parsed
+pub fn try_parse<T>(
+    data: &mut Bytes,
+    parse: impl FnOnce(&mut Bytes) -> Result<T, FbError>,
+) -> Result<Option<T>, FbError> {
+    let mut resp = data.clone();
+
+    match parse(&mut resp) {
+        Err(e) if is_missing_bytes(&e) => Ok(None),
+        res => {
+            *data = resp;
+            res.map(Some)
+        }
+    }
+}
+
+/// Appends the bytes `received` to the `data` not yet parsed
+pub fn append_received(data: &mut Bytes, received: &[u8]) {
+    let mut more = BytesMut::with_capacity(data.len() + received.len());
+    more.put_slice(&data[..]);
+    more.put_slice(received);
+    *data = more.freeze();
+}
+
+/// Statement information request for the number of affected rows
+pub fn info_sql_records(stmt_handle: u32) -> Bytes {
+    let mut req = BytesMut::with_capacity(24);
+
+    req.put_u32(WireOp::InfoSql as u32);
+    req.put_u32(stmt_handle);
+    req.put_u32(0); // Incarnation of object
+    req.put_wire_bytes(&[ibase::isc_info_sql_records as u8]);
+    req.put_u32(BUFFER_LENGTH);
+
+    req.freeze()
+}
+
+/// Parse the response of the `info_sql_records` request,
+/// returning the number of rows inserted, updated or deleted
+pub fn parse_info_sql_records(resp: &mut Bytes) -> Result<u64, FbError> {
+    if resp.get_u8()? != ibase::isc_info_sql_records as u8 {
+        return err_invalid_response();
+    }
+    resp.get_u16_le()?; // Total length
+
+    let mut affected = 0;
+
+    loop {
+        let item = resp.get_u8()? as u32;
+        if item == ibase::isc_info_end {
+            break;
+        }
+
+        let len = resp.get_u16_le()? as usize;
+        match item {
+            ibase::isc_info_req_insert_count
+            | ibase::isc_info_req_update_count
+            | ibase::isc_info_req_delete_count => affected += resp.get_u32_le()? as u64,
+            _ => resp.advance(len)?,
+        }
+    }
+
+    Ok(affected)
+}
+
+/// Parse a batch completion state (`WireOp::BatchCs`), returning the state of each message
+pub fn parse_batch_cs(resp: &mut Bytes) -> Result<Vec<BatchRowState>, FbError> {
+    resp.get_u32()?; // Statement handle
+    let rec_count = resp.get_u32()? as usize;
+    let updates = resp.get_u32()?;
+    let vectors = resp.get_u32()?;
+    let errors = resp.get_u32()?;
+
+    let mut states: Vec<_> = (0..rec_count)
+        .map(|_| BatchRowState::Success(None))
+        .collect();
+
+    let invalid_rec = |rec| FbError::from(format!("Invalid batch message number: {}", rec));
+
+    // Affected rows counts
+    for rec in 0..updates as usize {
+        *states.get_mut(rec).ok_or_else(|| invalid_rec(rec))? = match resp.get_i32()? {
+            batch::EXECUTE_FAILED => BatchRowState::Failed(None),
+            batch::SUCCESS_NO_INFO => BatchRowState::Success(None),
+            count => BatchRowState::Success(Some(count as u32)),
+        };
+    }
+
+    // Errors with the status vector
+    for _ in 0..vectors {
+        let rec = resp.get_u32()? as usize;
+
+        let err = match parse_status_vector(resp) {
+            Err(e) if is_missing_bytes(&e) => return Err(e),
+            res => res.err(),
+        };
+
+        *states.get_mut(rec).ok_or_else(|| invalid_rec(rec))? = BatchRowState::Failed(err);
+    }
+
+    // Errors without details
+    for _ in 0..errors {
+        let rec = resp.get_u32()? as usize;
+
+        *states.get_mut(rec).ok_or_else(|| invalid_rec(rec))? = BatchRowState::Failed(None);
+    }
+
+    Ok(states)
+}
+
+/// Parse the next packet, that must be a batch completion state (`WireOp::BatchCs`)
+pub fn parse_batch_cs_packet(resp: &mut Bytes) -> Result<Vec<BatchRowState>, FbError> {
+    parse_packet(resp, WireOp::BatchCs, parse_batch_cs)
+}
+
+/// Data of a `WireOp::FetchResponse`
+pub enum FetchResponse {
+    /// A row of the cursor
+    Row(Vec<ParsedColumn>),
+    /// All rows requested were sent, but the cursor may have more
+    EndOfBatch,
+    /// No more rows in the cursor
+    EndOfCursor,
+}
+
+/// Parse a server fetch response (`WireOp::FetchResponse`).
+///
+/// A fetch request receives one response for each row and one for the end of the batch
 pub fn parse_fetch_response(
     resp: &mut Bytes,
     xsqlda: &[XSqlVar],
     version: ProtocolVersion,
     charset: &Charset,
-) -> Result<Option<Vec<ParsedColumn>>, FbError> {
+) -> Result<FetchResponse, FbError> {
     const END_OF_STREAM: u32 = 100;
 
     let status = resp.get_u32()?;
 
     if status == END_OF_STREAM {
-        return Ok(None);
+        // Discard the count, so the next response starts at the right position
+        resp.get_u32()?;
+        return Ok(FetchResponse::EndOfCursor);
+    }
+
+    let mut count_peek = resp.clone();
+    if count_peek.get_u32()? == 0 {
+        resp.advance(4)?;
+        return Ok(FetchResponse::EndOfBatch);
     }
 
-    Ok(Some(parse_sql_response(resp, xsqlda, version, charset)?))
+    Ok(FetchResponse::Row(parse_sql_response(
+        resp, xsqlda, version, charset,
+    )?))
 }
 
 /// Parse a server sql response (`WireOp::SqlResponse`)
@@ -676,6 +1117,30 @@ pub fn parse_sql_response(
     Ok(data)
 }
 
+/// Parse the next packet, that must be a fetch response (`WireOp::FetchResponse`)
+pub fn parse_fetch_response_packet(
+    resp: &mut Bytes,
+    xsqlda: &[XSqlVar],
+    version: ProtocolVersion,
+    charset: &Charset,
+) -> Result<FetchResponse, FbError> {
+    parse_packet(resp, WireOp::FetchResponse, |resp| {
+        parse_fetch_response(resp, xsqlda, version, charset)
+    })
+}
+
+/// Parse the next packet, that must be a sql response (`WireOp::SqlResponse`)
+pub fn parse_sql_response_packet(
+    resp: &mut Bytes,
+    xsqlda: &[XSqlVar],
+    version: ProtocolVersion,
+    charset: &Charset,
+) -> Result<Vec<ParsedColumn>, FbError> {
+    parse_packet(resp, WireOp::SqlResponse, |resp| {
+        parse_sql_response(resp, xsqlda, version, charset)
+    })
+}
+
 /// Column data parsed from a fetch response
 pub enum ParsedColumn {
     /// All data received
@@ -721,19 +1186,29 @@ impl ParsedColumn {
 
                 conn.close_blob(blob_handle)?;
 
-                Column::new(
-                    col_name,
-                    if binary {
-                        SqlType::Binary(data)
-                    } else {
-                        SqlType::Text(conn.charset.decode(data)?)
-                    },
-                )
+                blob_column(col_name, binary, data, &conn.charset)?
             }
         })
     }
 }
 
+/// Column of a blob, with all the data read from the server
+pub fn blob_column(
+    col_name: String,
+    binary: bool,
+    data: Vec<u8>,
+    charset: &Charset,
+) -> Result<Column, FbError> {
+    Ok(Column::new(
+        col_name,
+        if binary {
+            SqlType::Binary(data)
+        } else {
+            SqlType::Text(charset.decode(data)?)
+        },
+    ))
+}
+
 /// Parses the error messages from the response
 pub fn parse_status_vector(resp: &mut Bytes) -> Result<(), FbError> {
     // Sql error code (default to -1)
@@ -741,6 +1216,8 @@ pub fn parse_status_vector(resp: &mut Bytes) -> Result<(), FbError> {
     // Error messages
     let mut message = String::new();
 
+    // Codes of all error messages
+    let mut gds_codes = Vec::new();
     // Code of the last error message
     let mut gds_code = 0;
     // Error message argument index
@@ -753,6 +1230,7 @@ pub fn parse_status_vector(resp: &mut Bytes) -> Result<(), FbError> {
                 gds_code = resp.get_u32()?;
 
                 if gds_code != 0 {
+                    gds_codes.push(gds_code);
                     message += gds_to_msg(gds_code);
                     num_arg = 0;
                 }
@@ -804,13 +1282,10 @@ pub fn parse_status_vector(resp: &mut Bytes) -> Result<(), FbError> {
         message.pop();
     }
 
-    if !message.is_empty() {
-        Err(FbError::Sql {
-            code: sql_code,
-            msg: message,
-        })
-    } else {
+    if message.is_empty() {
         Ok(())
+    } else {
+        Err(err_status_vector(&gds_codes, sql_code, message))
     }
 }
 
@@ -818,13 +1293,24 @@ pub fn parse_status_vector(resp: &mut Bytes) -> Result<(), FbError> {
 /// Data from the response of a connection request
 pub struct ConnectionResponse {
     pub version: ProtocolVersion,
-    pub auth_plugin: Option<AuthPlugin>,
+    /// Server accepted the lazy send protocol type, so some requests can be deferred
+    pub lazy_send: bool,
+    /// Server accepted the wire compression, so all the next packets will be compressed
+    pub compress: bool,
+    /// Authentication requested by the server, if not yet authenticated
+    pub auth_data: Option<ServerAuthData>,
+    /// Wire encryption keys known by the server
+    pub keys: Bytes,
 }
 
 #[derive(Debug)]
-pub struct AuthPlugin {
-    pub kind: AuthPluginType,
-    pub data: Option<SrpAuthData>,
+/// Authentication data sent by the server
+pub struct ServerAuthData {
+    /// Plugin used by the server
+    pub plugin: String,
+    /// Plugin data, empty if the server requests the client to start the authentication
+    pub data: Bytes,
+    /// Wire encryption keys known by the server
     pub keys: Bytes,
 }
 
@@ -847,60 +1333,118 @@ pub fn parse_accept(resp: &mut Bytes) -> Result<ConnectionResponse, FbError> {
     let version =
         ProtocolVersion::try_from(resp.get_u32()?).map_err(|e| FbError::Other(e.to_string()))?;
     resp.get_u32()?; // Arch
-    resp.get_u32()?; // Type
+    let ptype = resp.get_u32()?;
+    let lazy_send = ptype & PROTOCOL_TYPE_MASK == ProtocolType::LazySend as u32;
+    let compress = ptype & PFLAG_COMPRESS != 0;
 
-    let auth_plugin =
+    let (auth_data, keys) =
         if op_code == WireOp::AcceptData as u32 || op_code == WireOp::CondAccept as u32 {
-            let auth_data = parse_srp_auth_data(&mut resp.get_wire_bytes()?)?;
+            let data = resp.get_wire_bytes()?;
 
-            let plugin = AuthPluginType::parse(&resp.get_wire_bytes()?)?;
+            let plugin = String::from_utf8_lossy(&resp.get_wire_bytes()?).into_owned();
 
             let authenticated = resp.get_u32()? != 0;
 
             let keys = resp.get_wire_bytes()?;
 
             if authenticated {
-                None
+                (None, keys)
             } else {
-                Some(AuthPlugin {
-                    kind: plugin,
-                    data: auth_data,
-                    keys,
-                })
+                let auth_data = ServerAuthData {
+                    plugin,
+                    data,
+                    keys: keys.clone(),
+                };
+
+                (Some(auth_data), keys)
             }
         } else {
-            None
+            (None, Bytes::new())
         };
 
     Ok(ConnectionResponse {
         version,
-        auth_plugin,
+        lazy_send,
+        compress,
+        auth_data,
+        keys,
     })
 }
 
-/// Parse an authentication continuation response (`WireOp::ContAuth`)
-pub fn parse_cont_auth(resp: &mut Bytes) -> Result<AuthPlugin, FbError> {
-    let op_code = resp.get_u32()?;
+/// Parse an authentication continuation response (`WireOp::ContAuth`), after the op code
+pub fn parse_cont_auth(resp: &mut Bytes) -> Result<ServerAuthData, FbError> {
+    let data = resp.get_wire_bytes()?;
+    let plugin = String::from_utf8_lossy(&resp.get_wire_bytes()?).into_owned();
+    let _plugin_list = resp.get_wire_bytes()?;
+    let keys = resp.get_wire_bytes()?;
 
-    if op_code == WireOp::Response as u32 {
-        // Returned an error
-        parse_response(resp)?;
+    Ok(ServerAuthData { plugin, data, keys })
+}
+
+#[derive(Debug, Default)]
+/// Wire encryption keys known by the server
+pub struct ServerKeys {
+    /// Plugins that can use the `Symmetric` key generated by the authentication
+    pub plugins: Vec<String>,
+    /// Plugin specific data, like the ChaCha nonce, by plugin name
+    pub specific_data: Vec<(String, Bytes)>,
+}
+
+impl ServerKeys {
+    /// Returns `true` if the server can use the plugin for the wire encryption
+    pub fn supports(&self, plugin: WireCryptPlugin) -> bool {
+        self.plugins.iter().any(|p| p == plugin.name())
     }
 
-    if op_code != WireOp::ContAuth as u32 {
-        return err_conn_rejected(op_code);
+    /// Data sent by the server for the plugin
+    pub fn specific_data(&self, plugin: WireCryptPlugin) -> Option<&Bytes> {
+        self.specific_data
+            .iter()
+            .find(|(p, _)| p == plugin.name())
+            .map(|(_, data)| data)
     }
+}
 
-    let auth_data = parse_srp_auth_data(&mut resp.get_wire_bytes()?)?;
-    let plugin = AuthPluginType::parse(&resp.get_wire_bytes()?)?;
-    let _plugin_list = resp.get_wire_bytes()?;
-    let keys = resp.get_wire_bytes()?;
+/// Parse the wire encryption keys sent by the server, adding them to `keys`
+pub fn parse_server_keys(resp: &mut Bytes, keys: &mut ServerKeys) -> Result<(), FbError> {
+    let mut key_type = None;
 
-    Ok(AuthPlugin {
-        kind: plugin,
-        data: auth_data,
-        keys,
-    })
+    while resp.remaining() > 0 {
+        let tag = resp.get_u8()?;
+        let len = resp.get_u8()? as usize;
+        if resp.remaining() < len {
+            return err_invalid_response();
+        }
+        let data = resp.split_to(len);
+
+        match tag {
+            crypt_keys::TAG_KEY_TYPE => key_type = Some(data),
+            crypt_keys::TAG_KEY_PLUGINS => {
+                // Only the key generated by the authentication is used
+                let symmetric =
+                    key_type.take().as_deref() == Some(crypt_keys::KEY_TYPE_SYMMETRIC.as_bytes());
+
+                if symmetric {
+                    keys.plugins.extend(
+                        String::from_utf8_lossy(&data)
+                            .split(&[' ', ',', '\t'][..])
+                            .filter(|p| !p.is_empty())
+                            .map(str::to_string),
+                    );
+                }
+            }
+            crypt_keys::TAG_PLUGIN_SPECIFIC => {
+                if let Some(sep) = data.iter().position(|&b| b == 0) {
+                    let plugin = String::from_utf8_lossy(&data[..sep]).into_owned();
+                    keys.specific_data.push((plugin, data.slice(sep + 1..)));
+                }
+            }
+            // Known plugins and unknown tags are not used
+            _ => {}
+        }
+    }
+
+    Ok(())
 }
 
 #[derive(Debug)]
@@ -942,3 +1486,136 @@ pub fn parse_srp_auth_data(resp: &mut Bytes) -> Result<Option<SrpAuthData>, FbEr
         pub_key: pub_key.into_boxed_slice(),
     }))
 }
+
+#[test]
+fn legacy_auth_hash_test() {
+    assert_eq!(legacy_auth_hash("masterkey").unwrap(), "QP3LMZ/MJh.");
+}
+
+#[test]
+fn parse_server_keys_test() {
+    let mut resp = Bytes::from_static(
+        b"\x00\x09Symmetric\x01\x14ChaCha64 ChaCha Arc4\
+          \x02\x06Plugin\
+          \x03\x0fChaCha\x00\x01\x02\x03\x04\x05\x06\x07\x08",
+    );
+    let mut keys = ServerKeys::default();
+
+    parse_server_keys(&mut resp, &mut keys).unwrap();
+
+    assert_eq!(keys.plugins, vec!["ChaCha64", "ChaCha", "Arc4"]);
+    assert!(keys.supports(WireCryptPlugin::Arc4));
+    assert_eq!(
+        keys.specific_data(WireCryptPlugin::ChaCha).map(|d| &d[..]),
+        Some(&b"\x01\x02\x03\x04\x05\x06\x07\x08"[..])
+    );
+    assert!(keys.specific_data(WireCryptPlugin::ChaCha64).is_none());
+
+    // Other key types are ignored
+    let mut resp = Bytes::from_static(b"\x00\x05Other\x01\x04Arc4");
+    let mut keys = ServerKeys::default();
+
+    parse_server_keys(&mut resp, &mut keys).unwrap();
+    assert!(keys.plugins.is_empty());
+}
+
+#[test]
+fn parse_info_sql_records_test() {
+    let mut resp = Bytes::from_static(&[
+        23, 29, 0, // isc_info_sql_records
+        13, 4, 0, 9, 0, 0, 0, // Select count, ignored
+        14, 4, 0, 2, 0, 0, 0, // Insert count
+        15, 4, 0, 3, 0, 0, 0, // Update count
+        16, 4, 0, 0, 1, 0, 0, // Delete count
+        1, 1, // isc_info_end
+    ]);
+
+    assert_eq!(parse_info_sql_records(&mut resp).unwrap(), 2 + 3 + 256);
+}
+
+#[test]
+fn parse_fetch_response_end_test() {
+    let mut resp = BytesMut::new();
+
+    resp.put_u32(100); // End of stream
+    resp.put_u32(0); // Count
+    resp.put_u32(0); // Status of the next fetch
+    resp.put_u32(0); // End of batch
+    let mut resp = resp.freeze();
+
+    let end = parse_fetch_response(
+        &mut resp,
+        &[],
+        ProtocolVersion::V13,
+        &rsfbclient_core::charset::UTF_8,
+    )
+    .unwrap();
+    assert!(matches!(end, FetchResponse::EndOfCursor));
+    assert_eq!(resp.remaining(), 8);
+
+    let end = parse_fetch_response(
+        &mut resp,
+        &[],
+        ProtocolVersion::V13,
+        &rsfbclient_core::charset::UTF_8,
+    )
+    .unwrap();
+    assert!(matches!(end, FetchResponse::EndOfBatch));
+    assert_eq!(resp.remaining(), 0);
+}
+
+#[test]
+fn parse_batch_cs_test() {
+    let mut resp = BytesMut::new();
+
+    resp.put_u32(1); // Statement handle
+    resp.put_u32(3); // Messages
+    resp.put_u32(3); // Affected rows counts
+    resp.put_u32(1); // Status vectors
+    resp.put_u32(0); // Errors without details
+
+    resp.put_i32(1);
+    resp.put_i32(batch::EXECUTE_FAILED);
+    resp.put_i32(batch::SUCCESS_NO_INFO);
+
+    resp.put_u32(1); // Message number
+    resp.put_u32(ibase::isc_arg_interpreted);
+    resp.put_wire_bytes(b"violation of PRIMARY or UNIQUE KEY constraint");
+    resp.put_u32(ibase::isc_arg_end);
+
+    let states = parse_batch_cs(&mut resp.freeze()).unwrap();
+
+    assert_eq!(states.len(), 3);
+    assert!(matches!(states[0], BatchRowState::Success(Some(1))));
+    assert!(
+        matches!(&states[1], BatchRowState::Failed(Some(FbError::Sql { msg, .. })) if msg.starts_with("violation"))
+    );
+    assert!(matches!(states[2], BatchRowState::Success(None)));
+}
+
+#[test]
+fn parse_status_vector_cancelled_test() {
+    // isc_arg_gds isc_cancelled, isc_arg_end
+    let mut resp = Bytes::from_static(b"\x00\x00\x00\x01\x14\x00\x01\xda\x00\x00\x00\x00");
+
+    let err = parse_status_vector(&mut resp).unwrap_err();
+    assert!(matches!(err, FbError::Cancelled(msg) if msg == "operation was cancelled"));
+
+    // isc_arg_gds isc_cancelled, isc_arg_gds isc_req_stmt_timeout, isc_arg_end
+    let mut resp = Bytes::from_static(
+        b"\x00\x00\x00\x01\x14\x00\x01\xda\x00\x00\x00\x01\x14\x00\x03\x29\x00\x00\x00\x00",
+    );
+
+    let err = parse_status_vector(&mut resp).unwrap_err();
+    assert!(matches!(err, FbError::StatementTimeout(_)));
+}
+
+#[test]
+fn execute_timeout_test() {
+    let req = execute(1, 2, &[], &[], 1500, ProtocolVersion::V16);
+    assert_eq!(&req[req.len() - 4..], &1500u32.to_be_bytes());
+
+    // Not sent in the older protocols
+    let req = execute(1, 2, &[], &[], 1500, ProtocolVersion::V13);
+    assert_eq!(req.len(), 6 * 4);
+}
@@ -0,0 +1,160 @@
+//! Zlib stream compression for the firebird wire compression (Wire Protocol 13)
+
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
+use std::io::{self, Read, Write};
+
+/// Wraps a stream, compressing / decompressing the data
+///
+/// The written data is buffered and only compressed when flushed,
+/// so each flush sends the complete packets to the server
+pub struct ZlibStream<S> {
+    compress: Box<Compress>,
+    decompress: Box<Decompress>,
+    /// Uncompressed data waiting for the flush
+    write_buf: Vec<u8>,
+    /// Compressed data to write to the stream
+    enc_buf: Box<[u8]>,
+    /// Compressed data read from the stream
+    dec_buf: Box<[u8]>,
+    /// Range of `dec_buf` not yet decompressed
+    dec_pos: usize,
+    dec_len: usize,
+    stream: S,
+}
+
+impl<S> ZlibStream<S> {
+    pub fn new(stream: S, buf_len: usize) -> Self {
+        Self {
+            compress: Box::new(Compress::new(Compression::default(), true)),
+            decompress: Box::new(Decompress::new(true)),
+            write_buf: Vec::with_capacity(buf_len),
+            enc_buf: vec![0; buf_len].into_boxed_slice(),
+            dec_buf: vec![0; buf_len].into_boxed_slice(),
+            dec_pos: 0,
+            dec_len: 0,
+            stream,
+        }
+    }
+
+    /// Replaces the underlying stream, keeping the compression state
+    pub fn map_stream<T>(self, f: impl FnOnce(S) -> T) -> ZlibStream<T> {
+        ZlibStream {
+            compress: self.compress,
+            decompress: self.decompress,
+            write_buf: self.write_buf,
+            enc_buf: self.enc_buf,
+            dec_buf: self.dec_buf,
+            dec_pos: self.dec_pos,
+            dec_len: self.dec_len,
+            stream: f(self.stream),
+        }
+    }
+}
+
+impl<S: Read> Read for ZlibStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            // Decompress the data already read, as the decompressor may also
+            // have data pending from the last call
+            let in_before = self.decompress.total_in();
+            let out_before = self.decompress.total_out();
+
+            self.decompress
+                .decompress(
+                    &self.dec_buf[self.dec_pos..self.dec_len],
+                    buf,
+                    FlushDecompress::Sync,
+                )
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+            let consumed = (self.decompress.total_in() - in_before) as usize;
+            let len = (self.decompress.total_out() - out_before) as usize;
+            self.dec_pos += consumed;
+
+            if len > 0 {
+                return Ok(len);
+            }
+
+            if self.dec_pos < self.dec_len {
+                if consumed == 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Unexpected end of the compressed stream",
+                    ));
+                }
+            } else {
+                // Read more compressed data
+                self.dec_len = self.stream.read(&mut self.dec_buf)?;
+                self.dec_pos = 0;
+
+                if self.dec_len == 0 {
+                    return Ok(0);
+                }
+            }
+        }
+    }
+}
+
+impl<S: Write> Write for ZlibStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_buf.extend_from_slice(buf);
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut input = &self.write_buf[..];
+
+        loop {
+            let in_before = self.compress.total_in();
+            let out_before = self.compress.total_out();
+
+            self.compress
+                .compress(input, &mut self.enc_buf, FlushCompress::Sync)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+            input = &input[(self.compress.total_in() - in_before) as usize..];
+            let len = (self.compress.total_out() - out_before) as usize;
+
+            self.stream.write_all(&self.enc_buf[..len])?;
+
+            // The sync flush is complete when the output buffer was not filled
+            if input.is_empty() && len < self.enc_buf.len() {
+                break;
+            }
+        }
+
+        self.write_buf.clear();
+
+        self.stream.flush()
+    }
+}
+
+#[test]
+fn zlib_test() {
+    // Small buffer, to force multiple reads and writes
+    let mut z1 = ZlibStream::new(Vec::new(), 16);
+
+    let plain: Vec<u8> = (0..1000).map(|i| (i % 7) as u8).collect();
+
+    z1.write_all(&plain[..600]).unwrap();
+    z1.flush().unwrap();
+    let first_len = z1.stream.len();
+
+    z1.write_all(&plain[600..]).unwrap();
+    z1.flush().unwrap();
+
+    // Sync flush ends each chunk with an empty stored block
+    assert!(z1.stream[..first_len].ends_with(&[0, 0, 0xff, 0xff]));
+    assert!(z1.stream.len() < plain.len());
+
+    let mut z2 = ZlibStream::new(&z1.stream[..], 16);
+
+    let mut dec = Vec::new();
+    z2.read_to_end(&mut dec).unwrap();
+    assert_eq!(dec, plain);
+}
@@ -0,0 +1,317 @@
+//!
+//! Rust Firebird Client
+//!
+//! Async connection functions
+//!
+
+use futures_util::stream;
+use rsfbclient_core::{
+    Dialect, FbError, FirebirdClientAsync, FirebirdClientDbOpsAsync, FreeStmtOp, FromRow,
+    IntoParams, TrOp,
+};
+use std::{marker, time::Duration};
+
+use super::{statement_timeout_sql, ConnectionConfiguration};
+use crate::{
+    query::{AsyncExecute, AsyncQueryable, RowStream},
+    transaction::AsyncTransaction,
+};
+
+/// An async connection to a firebird database, only available for the pure rust client.
+///
+/// The statements are prepared in each call, as there is no statement cache.
+/// Dropping the connection closes the socket, use `close` to detach gracefully
+pub struct AsyncConnection<C: FirebirdClientAsync> {
+    /// Database handler
+    pub(crate) handle: <C as FirebirdClientDbOpsAsync>::DbHandle,
+
+    /// Firebird dialect for the statements
+    pub(crate) dialect: Dialect,
+
+    /// Firebird client
+    pub(crate) cli: C,
+
+    /// Cleanup of the statements and transactions dropped before finishing. As the
+    /// `Drop` can't run async code, it is done before the next operation
+    pub(crate) pending: Vec<PendingOp<C>>,
+}
+
+/// Cleanup operation waiting to be sent to the server
+pub(crate) enum PendingOp<C: FirebirdClientAsync> {
+    /// Drop a statement
+    FreeStatement(C::StmtHandle),
+    /// Commit a transaction
+    Commit(C::TrHandle),
+    /// Rollback a transaction
+    Rollback(C::TrHandle),
+}
+
+impl<C: FirebirdClientAsync> AsyncConnection<C> {
+    /// Open the client connection.
+    pub async fn open(
+        mut cli: C,
+        conf: &ConnectionConfiguration<<C as FirebirdClientDbOpsAsync>::AttachmentConfig>,
+    ) -> Result<AsyncConnection<C>, FbError> {
+        let handle = cli.attach_database(&conf.attachment_conf).await?;
+
+        let mut conn = AsyncConnection {
+            handle,
+            dialect: conf.dialect,
+            cli,
+            pending: Vec::new(),
+        };
+
+        conn.init_session(conf.statement_timeout, conf.idle_timeout)
+            .await?;
+
+        Ok(conn)
+    }
+
+    /// Apply the session settings of a new attachment
+    async fn init_session(
+        &mut self,
+        statement_timeout: Option<Duration>,
+        idle_timeout: Option<Duration>,
+    ) -> Result<(), FbError> {
+        let mut sqls = Vec::new();
+
+        if let Some(timeout) = statement_timeout {
+            sqls.push(statement_timeout_sql(Some(timeout)));
+        }
+
+        if let Some(timeout) = idle_timeout {
+            sqls.push(format!(
+                "SET SESSION IDLE TIMEOUT {} SECOND",
+                timeout.as_secs().max(1).min(u32::MAX as u64)
+            ));
+        }
+
+        if sqls.is_empty() {
+            return Ok(());
+        }
+
+        let mut tr = self.begin_transaction().await?;
+        for sql in &sqls {
+            tr.execute_immediate(sql).await?;
+        }
+        tr.commit().await
+    }
+
+    /// Start a new transaction
+    pub async fn begin_transaction(&mut self) -> Result<AsyncTransaction<'_, C>, FbError> {
+        AsyncTransaction::new(self).await
+    }
+
+    /// Check if the server is still reachable, with a cheap roundtrip
+    /// that doesn't need a transaction
+    pub async fn ping(&mut self) -> Result<(), FbError> {
+        self.run_pending().await;
+
+        self.cli.ping(&mut self.handle).await
+    }
+
+    /// `true` if the connection found an I/O or network error, so it can't
+    /// be used anymore. Doesn't contact the server, use `ping` for that
+    pub fn is_broken(&self) -> bool {
+        self.cli.is_broken()
+    }
+
+    /// Drop the current database
+    pub async fn drop_database(mut self) -> Result<(), FbError> {
+        self.run_pending().await;
+
+        self.cli.drop_database(&mut self.handle).await
+    }
+
+    /// Close the current connection.
+    pub async fn close(mut self) -> Result<(), FbError> {
+        self.run_pending().await;
+
+        self.cli.detach_database(&mut self.handle).await
+    }
+
+    /// Release the statements and transactions dropped before finishing.
+    /// The errors are ignored, as the resources are not used anymore
+    pub(crate) async fn run_pending(&mut self) {
+        for op in std::mem::take(&mut self.pending) {
+            match op {
+                PendingOp::FreeStatement(mut stmt) => {
+                    self.cli.free_statement(&mut stmt, FreeStmtOp::Drop).await
+                }
+                PendingOp::Commit(mut tr) => {
+                    self.cli.transaction_operation(&mut tr, TrOp::Commit).await
+                }
+                PendingOp::Rollback(mut tr) => {
+                    self.cli
+                        .transaction_operation(&mut tr, TrOp::Rollback)
+                        .await
+                }
+            }
+            .ok();
+        }
+    }
+}
+
+/// Transaction used by a `RowCursor`
+pub(crate) enum CursorTr<'a, 'c, C: FirebirdClientAsync> {
+    /// Started for the query, committed at the end of the rows
+    Owned(AsyncTransaction<'c, C>),
+    /// Managed by the caller
+    Borrowed(&'a mut AsyncTransaction<'c, C>),
+}
+
+/// Open cursor of a query, converted to a `Stream` of rows
+pub(crate) struct RowCursor<'a, 'c, R, C: FirebirdClientAsync> {
+    tr: CursorTr<'a, 'c, C>,
+
+    /// Statement of the cursor, `None` after it was dropped
+    stmt: Option<C::StmtHandle>,
+
+    _marker: marker::PhantomData<R>,
+}
+
+impl<'a, 'c, R, C> RowCursor<'a, 'c, R, C>
+where
+    R: FromRow + Send + 'static,
+    C: FirebirdClientAsync + 'c,
+    'c: 'a,
+{
+    /// Prepare and execute the query, returning the stream of the rows
+    pub(crate) async fn open<P: IntoParams>(
+        mut tr: CursorTr<'a, 'c, C>,
+        sql: &str,
+        params: P,
+    ) -> Result<RowStream<'a, R>, FbError> {
+        let stmt = match &mut tr {
+            CursorTr::Owned(tr) => tr.query(sql, params).await?,
+            CursorTr::Borrowed(tr) => tr.query(sql, params).await?,
+        };
+
+        let cursor = RowCursor {
+            tr,
+            stmt: Some(stmt),
+            _marker: marker::PhantomData,
+        };
+
+        Ok(Box::pin(stream::unfold(cursor, |mut cursor| async move {
+            match cursor.fetch().await {
+                Ok(Some(row)) => Some((Ok(row), cursor)),
+                Ok(None) => None,
+                Err(e) => {
+                    // Stop at the first error
+                    cursor.finish().await.ok();
+                    Some((Err(e), cursor))
+                }
+            }
+        })))
+    }
+
+    fn tr(&mut self) -> &mut AsyncTransaction<'c, C> {
+        match &mut self.tr {
+            CursorTr::Owned(tr) => tr,
+            CursorTr::Borrowed(tr) => tr,
+        }
+    }
+
+    /// Fetch the next row, releasing the cursor after the last one
+    async fn fetch(&mut self) -> Result<Option<R>, FbError> {
+        let stmt = match &mut self.stmt {
+            Some(stmt) => stmt,
+            None => return Ok(None),
+        };
+
+        let tr = match &mut self.tr {
+            CursorTr::Owned(tr) => tr,
+            CursorTr::Borrowed(tr) => &mut **tr,
+        };
+
+        match tr.fetch(stmt).await? {
+            Some(row) => FromRow::try_from(row).map(Some),
+            None => {
+                self.finish().await?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Drop the statement and commit the transaction started for the query
+    async fn finish(&mut self) -> Result<(), FbError> {
+        if let Some(mut stmt) = self.stmt.take() {
+            self.tr().free_statement(&mut stmt).await?;
+        }
+
+        if let CursorTr::Owned(tr) = &mut self.tr {
+            tr.commit_in_place().await?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<R, C: FirebirdClientAsync> Drop for RowCursor<'_, '_, R, C> {
+    fn drop(&mut self) {
+        // Stream dropped before the last row
+        if let Some(stmt) = self.stmt.take() {
+            let tr = match &mut self.tr {
+                CursorTr::Owned(tr) => tr,
+                CursorTr::Borrowed(tr) => &mut **tr,
+            };
+            tr.conn.pending.push(PendingOp::FreeStatement(stmt));
+
+            if let CursorTr::Owned(tr) = &mut self.tr {
+                tr.defer(PendingOp::Commit);
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<C> AsyncQueryable for AsyncConnection<C>
+where
+    C: FirebirdClientAsync,
+{
+    async fn query_stream<'a, P, R>(
+        &'a mut self,
+        sql: &str,
+        params: P,
+    ) -> Result<RowStream<'a, R>, FbError>
+    where
+        P: IntoParams + Send,
+        R: FromRow + Send + 'static,
+    {
+        let tr = AsyncTransaction::new(self).await?;
+
+        RowCursor::open(CursorTr::Owned(tr), sql, params).await
+    }
+}
+
+#[async_trait::async_trait]
+impl<C> AsyncExecute for AsyncConnection<C>
+where
+    C: FirebirdClientAsync,
+{
+    async fn execute<P>(&mut self, sql: &str, params: P) -> Result<(), FbError>
+    where
+        P: IntoParams + Send,
+    {
+        let mut tr = AsyncTransaction::new(self).await?;
+
+        tr.execute(sql, params).await?;
+
+        tr.commit().await
+    }
+
+    async fn execute_returnable<P, R>(&mut self, sql: &str, params: P) -> Result<R, FbError>
+    where
+        P: IntoParams + Send,
+        R: FromRow + Send + 'static,
+    {
+        let mut tr = AsyncTransaction::new(self).await?;
+
+        let row = tr.execute_returnable(sql, params).await?;
+
+        tr.commit().await?;
+
+        Ok(row)
+    }
+}
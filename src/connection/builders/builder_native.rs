@@ -1,6 +1,8 @@
 use super::*;
 use crate::connection::conn_string;
+use crate::{Dpb, SetBind, WireCrypt};
 use std::marker::PhantomData;
+use std::time::Duration;
 
 #[doc(hidden)]
 pub use rsfbclient_native::{DynLink, DynLoad};
@@ -113,6 +115,10 @@ where
     fn get_conn_conf(&self) -> &ConnectionConfiguration<NativeFbAttachmentConfig> {
         &self.conn_conf
     }
+
+    fn is_embedded(&self) -> bool {
+        self.conn_conf.attachment_conf.remote.is_none()
+    }
 }
 
 #[cfg(feature = "linking")]
@@ -130,6 +136,10 @@ where
     fn get_conn_conf(&self) -> &ConnectionConfiguration<NativeFbAttachmentConfig> {
         &self.conn_conf
     }
+
+    fn is_embedded(&self) -> bool {
+        self.conn_conf.attachment_conf.remote.is_none()
+    }
 }
 
 impl<A, B> NativeConnectionBuilder<A, B>
@@ -144,6 +154,12 @@ where
     pub fn connect(&self) -> Result<Connection<NativeFbClient<A>>, FbError> {
         Connection::open(self.new_instance()?, &self.conn_conf)
     }
+
+    /// Create the database, with the configured `page_size`,
+    /// returning a new connection to it
+    pub fn create_database(&self) -> Result<Connection<NativeFbClient<A>>, FbError> {
+        Connection::create(self.new_instance()?, &self.conn_conf)
+    }
 }
 
 impl<A, B> NativeConnectionBuilder<A, B>
@@ -180,6 +196,72 @@ where
         self.conn_conf.stmt_cache_size = stmt_cache_size;
         self
     }
+
+    /// Default timeout of the statements executed by the connection. A statement
+    /// exceeding it fails with `FbError::StatementTimeout`. Requires Firebird 4+. Default: no timeout
+    pub fn statement_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.conn_conf.statement_timeout = Some(timeout);
+        self
+    }
+
+    /// Time without activity before the server closes the session, in whole seconds
+    /// rounded up, zero disables it. The next operation fails with `FbError::IdleTimeout`. Requires Firebird 4+. Default: server config
+    pub fn idle_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.conn_conf.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Attach to the database again when the connection is lost, as configured
+    /// in the policy. Default: disabled
+    pub fn reconnect(&mut self, policy: ReconnectPolicy) -> &mut Self {
+        self.conn_conf.reconnect = Some(policy);
+        self
+    }
+
+    /// SQL role of the connection. Default: none
+    pub fn role<S: Into<String>>(&mut self, role: S) -> &mut Self {
+        self.conn_conf.attachment_conf.dpb.role(role);
+        self
+    }
+
+    /// Time zone of the session, like `UTC` or `America/Sao_Paulo`.
+    /// Requires Firebird 4+. Default: server time zone
+    pub fn time_zone<S: Into<String>>(&mut self, time_zone: S) -> &mut Self {
+        self.conn_conf
+            .attachment_conf
+            .dpb
+            .session_time_zone(time_zone);
+        self
+    }
+
+    /// Coercion of the Firebird 4+ types by the server, like `SetBind::Legacy`
+    /// to read the `INT128`, `DECFLOAT` and time zone types as the legacy
+    /// types supported by the `SqlType`. Default: server config
+    pub fn set_bind(&mut self, bind: SetBind) -> &mut Self {
+        self.conn_conf.attachment_conf.dpb.set_bind(bind);
+        self
+    }
+
+    /// Items of the database parameter buffer sent on the attachment, merged
+    /// with the ones set before, like by the `role`, `time_zone` and `set_bind`.
+    /// The items set in both are taken from `dpb`
+    pub fn dpb(&mut self, dpb: Dpb) -> &mut Self {
+        self.conn_conf.attachment_conf.dpb.merge(dpb);
+        self
+    }
+
+    /// Authentication plugins, in order of preference, overriding the `AuthClient`
+    /// of the firebird.conf. Default: firebird.conf
+    pub fn auth_plugins(&mut self, plugins: &[&str]) -> &mut Self {
+        self.conn_conf.attachment_conf.auth_plugin_list = Some(plugins.join(","));
+        self
+    }
+
+    /// Page size of the databases created by `create_database`. Default: server default
+    pub fn page_size(&mut self, page_size: u32) -> &mut Self {
+        self.conn_conf.page_size = Some(page_size);
+        self
+    }
 }
 
 impl<A, B> NativeConnectionBuilder<A, B> {
@@ -243,6 +325,36 @@ impl<A> NativeConnectionBuilder<A, ConnRemote> {
         self.get_initialized_remote().pass = pass.into();
         self
     }
+
+    /// Maximum time to establish the connection, in whole seconds.
+    /// Default: the fbclient default. A zero timeout is ignored
+    pub fn connect_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.get_initialized_remote().connect_timeout = Some(timeout).filter(|t| !t.is_zero());
+        self
+    }
+
+    /// Interval of the keepalive packets, used to detect a dead
+    /// connection. In whole seconds. Default: disabled. A zero interval is ignored
+    ///
+    /// The native client doesn't support a per operation timeout
+    pub fn keepalive(&mut self, interval: Duration) -> &mut Self {
+        self.get_initialized_remote().keepalive = Some(interval).filter(|t| !t.is_zero());
+        self
+    }
+
+    /// Wire encryption level, overriding the `WireCrypt` of the firebird.conf
+    /// for this connection. Default: firebird.conf
+    pub fn wire_crypt(&mut self, wire_crypt: WireCrypt) -> &mut Self {
+        self.get_initialized_remote().wire_crypt = Some(wire_crypt);
+        self
+    }
+
+    /// Request the zlib compression of the network traffic, overriding the
+    /// `WireCompression` of the firebird.conf for this connection. Default: firebird.conf
+    pub fn wire_compression(&mut self, wire_compression: bool) -> &mut Self {
+        self.get_initialized_remote().wire_compression = Some(wire_compression);
+        self
+    }
 }
 
 impl<A> NativeConnectionBuilder<A, ConnTypeNotConfigured> {
@@ -328,6 +440,9 @@ impl<A> NativeConnectionBuilder<LinkageNotConfigured, A> {
     /// - If you not provide the host, we will consider this a embedded connection.
     /// - The port, user and pass parameters only will be accepted if you provide the host.
     /// - If you not provide the `lib={fbclient}`, we will consider this a dynamic linked connection. The default, by the way.
+    /// - The `wire_crypt` and `wire_compression` options override the firebird.conf for the connection.
+    /// - The `io_timeout`, `wire_crypt_plugins` and `fetch_size` options are not supported
+    ///   by the native client, and are errors.
     #[allow(clippy::wrong_self_convention)]
     pub fn from_string(
         self,
@@ -335,7 +450,30 @@ impl<A> NativeConnectionBuilder<LinkageNotConfigured, A> {
     ) -> Result<NativeConnectionBuilder<DynByString, ConnByString>, FbError> {
         let settings = conn_string::parse(s_conn)?;
 
-        let mut cb: NativeConnectionBuilder<DynByString, ConnRemote> = {
+        self.from_settings(settings)
+    }
+
+    /// Setup the connection with the settings of a parsed connection string,
+    /// using the `L` linkage. Fails if an option is not supported by the native client
+    #[allow(clippy::wrong_self_convention)]
+    pub(crate) fn from_settings<L: ConfiguredLinkage>(
+        self,
+        settings: conn_string::ConnStringSettings,
+    ) -> Result<NativeConnectionBuilder<L, ConnByString>, FbError> {
+        let unsupported = [
+            ("io_timeout", settings.io_timeout.is_some()),
+            ("wire_crypt_plugins", settings.wire_crypt_plugins.is_some()),
+            ("fetch_size", settings.fetch_size.is_some()),
+        ];
+
+        if let Some((option, _)) = unsupported.iter().find(|(_, set)| *set) {
+            return Err(FbError::from(format!(
+                "The option '{}' of the connection string is not supported by the native client",
+                option
+            )));
+        }
+
+        let mut cb: NativeConnectionBuilder<L, ConnRemote> = {
             // When we have a host, we will consider
             // this a remote connection
             if let Some(host) = settings.host {
@@ -355,6 +493,22 @@ impl<A> NativeConnectionBuilder<LinkageNotConfigured, A> {
                     cb.pass(pass);
                 }
 
+                if let Some(timeout) = settings.connect_timeout {
+                    cb.connect_timeout(timeout);
+                }
+
+                if let Some(interval) = settings.keepalive {
+                    cb.keepalive(interval);
+                }
+
+                if let Some(wire_crypt) = settings.wire_crypt {
+                    cb.wire_crypt(wire_crypt);
+                }
+
+                if let Some(wire_compression) = settings.wire_compression {
+                    cb.wire_compression(wire_compression);
+                }
+
                 cb
             } else {
                 self.safe_transmute()
@@ -379,6 +533,35 @@ impl<A> NativeConnectionBuilder<LinkageNotConfigured, A> {
             cb.stmt_cache_size(stmt_cache_size);
         }
 
+        if let Some(timeout) = settings.statement_timeout {
+            cb.statement_timeout(timeout);
+        }
+
+        if let Some(timeout) = settings.idle_timeout {
+            cb.idle_timeout(timeout);
+        }
+
+        if let Some(role) = settings.role {
+            cb.role(role);
+        }
+
+        if let Some(time_zone) = settings.time_zone {
+            cb.time_zone(time_zone);
+        }
+
+        if let Some(bind) = settings.set_bind {
+            cb.set_bind(bind);
+        }
+
+        if let Some(plugins) = settings.auth_plugins {
+            let plugins: Vec<_> = plugins.iter().map(String::as_str).collect();
+            cb.auth_plugins(&plugins);
+        }
+
+        if let Some(page_size) = settings.page_size {
+            cb.page_size(page_size);
+        }
+
         Ok(cb.safe_transmute())
     }
 }
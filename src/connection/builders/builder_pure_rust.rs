@@ -1,7 +1,14 @@
 use super::*;
 use crate::connection::conn_string;
-use crate::{charset, Charset};
-use rsfbclient_rust::{RustFbClient, RustFbClientAttachmentConfig};
+use crate::{charset, Charset, Dpb, SetBind, WireCrypt, WireCryptPlugin};
+use rsfbclient_rust::{
+    AuthPlugin, AuthPluginFactory, LegacyAuth, RustFbClient, RustFbClientAttachmentConfig, Srp,
+    Transport,
+};
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(feature = "async-tokio")]
+use {crate::AsyncConnection, rsfbclient_rust::AsyncRustFbClient};
 
 impl FirebirdClientFactory for PureRustConnectionBuilder {
     type C = RustFbClient;
@@ -39,6 +46,20 @@ impl PureRustConnectionBuilder {
         Connection::open(self.new_instance()?, &self.0)
     }
 
+    /// Create the database, with the configured `page_size`,
+    /// returning a new connection to it
+    pub fn create_database(&self) -> Result<Connection<RustFbClient>, FbError> {
+        Connection::create(self.new_instance()?, &self.0)
+    }
+
+    /// Open an async connection, using the tokio runtime.
+    ///
+    /// The `transport` option is not supported
+    #[cfg(feature = "async-tokio")]
+    pub async fn connect_async(&self) -> Result<AsyncConnection<AsyncRustFbClient>, FbError> {
+        AsyncConnection::open(AsyncRustFbClient::new(self.1.clone()), &self.0).await
+    }
+
     /// Username. Default: SYSDBA
     pub fn user<S: Into<String>>(&mut self, user: S) -> &mut Self {
         self.0.attachment_conf.user = user.into();
@@ -81,6 +102,144 @@ impl PureRustConnectionBuilder {
         self
     }
 
+    /// Default timeout of the statements executed by the connection. A statement
+    /// exceeding it fails with `FbError::StatementTimeout`. Requires Firebird 4+. Default: no timeout
+    pub fn statement_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.0.statement_timeout = Some(timeout);
+        self
+    }
+
+    /// Time without activity before the server closes the session, in whole seconds
+    /// rounded up, zero disables it. The next operation fails with `FbError::IdleTimeout`. Requires Firebird 4+. Default: server config
+    pub fn idle_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.0.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Attach to the database again when the connection is lost, as configured
+    /// in the policy. Default: disabled
+    pub fn reconnect(&mut self, policy: ReconnectPolicy) -> &mut Self {
+        self.0.reconnect = Some(policy);
+        self
+    }
+
+    /// SQL role of the connection. Default: none
+    pub fn role<S: Into<String>>(&mut self, role: S) -> &mut Self {
+        self.0.attachment_conf.dpb.role(role);
+        self
+    }
+
+    /// Time zone of the session, like `UTC` or `America/Sao_Paulo`.
+    /// Requires Firebird 4+. Default: server time zone
+    pub fn time_zone<S: Into<String>>(&mut self, time_zone: S) -> &mut Self {
+        self.0.attachment_conf.dpb.session_time_zone(time_zone);
+        self
+    }
+
+    /// Coercion of the Firebird 4+ types by the server, like `SetBind::Legacy`
+    /// to read the `INT128`, `DECFLOAT` and time zone types as the legacy
+    /// types supported by the `SqlType`. Default: server config
+    pub fn set_bind(&mut self, bind: SetBind) -> &mut Self {
+        self.0.attachment_conf.dpb.set_bind(bind);
+        self
+    }
+
+    /// Items of the database parameter buffer sent on the attachment, merged
+    /// with the ones set before, like by the `role`, `time_zone` and `set_bind`.
+    /// The items set in both are taken from `dpb`
+    pub fn dpb(&mut self, dpb: Dpb) -> &mut Self {
+        self.0.attachment_conf.dpb.merge(dpb);
+        self
+    }
+
+    /// Page size of the databases created by `create_database`. Default: server default
+    pub fn page_size(&mut self, page_size: u32) -> &mut Self {
+        self.0.page_size = Some(page_size);
+        self
+    }
+
+    /// Number of rows requested from the server in each fetch round trip. Default: 400
+    pub fn fetch_size(&mut self, fetch_size: u32) -> &mut Self {
+        self.0.attachment_conf.fetch_size = fetch_size.max(1);
+        self
+    }
+
+    /// Request the zlib compression of the network traffic. Only
+    /// used if the server supports the protocol 13 (Firebird 3+) and
+    /// enabled the `WireCompression` option. Default: false
+    pub fn wire_compression(&mut self, wire_compression: bool) -> &mut Self {
+        self.0.attachment_conf.wire_compression = wire_compression;
+        self
+    }
+
+    /// Wire encryption level. Only used if the server supports the
+    /// protocol 13 (Firebird 3+). Default: Enabled
+    pub fn wire_crypt(&mut self, wire_crypt: WireCrypt) -> &mut Self {
+        self.0.attachment_conf.wire_crypt = wire_crypt;
+        self
+    }
+
+    /// Wire encryption plugins, in order of preference. The `ChaCha`
+    /// and `ChaCha64` plugins need Firebird 4+. Default: ChaCha64, ChaCha, Arc4
+    pub fn wire_crypt_plugins(&mut self, plugins: &[WireCryptPlugin]) -> &mut Self {
+        self.0.attachment_conf.wire_crypt_plugins = plugins.to_vec();
+        self
+    }
+
+    /// Register an authentication plugin, preferred over the ones already registered.
+    ///
+    /// The function is called for each new connection. Default plugins: Srp, Srp256, Legacy_Auth
+    pub fn auth_plugin<F>(&mut self, plugin: F) -> &mut Self
+    where
+        F: Fn() -> Box<dyn AuthPlugin> + Send + Sync + 'static,
+    {
+        self.0
+            .attachment_conf
+            .auth_plugins
+            .insert(0, Arc::new(plugin));
+        self
+    }
+
+    /// Replace the authentication plugins, in order of preference
+    pub fn auth_plugins(&mut self, plugins: Vec<AuthPluginFactory>) -> &mut Self {
+        self.0.attachment_conf.auth_plugins = plugins;
+        self
+    }
+
+    /// Use a custom stream to communicate with the server, instead of a tcp connection.
+    ///
+    /// The function receives the configured host and port, and is called for each
+    /// new connection. Useful for ssh tunnels, proxies or in memory streams
+    pub fn with_transport<F>(&mut self, connector: F) -> &mut Self
+    where
+        F: Fn(&str, u16) -> Result<Box<dyn Transport>, FbError> + Send + Sync + 'static,
+    {
+        self.0.attachment_conf.transport = Some(Arc::new(connector));
+        self
+    }
+
+    /// Maximum time to establish the tcp connection. Default: no timeout.
+    /// A zero timeout is ignored
+    pub fn connect_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.0.attachment_conf.connect_timeout = Some(timeout).filter(|t| !t.is_zero());
+        self
+    }
+
+    /// Maximum time for each read or write in the tcp connection, so a dead server
+    /// or a half-open connection returns a `FbError::Timeout`. The connection must
+    /// be discarded after a timeout. Default: no timeout. A zero timeout is ignored
+    pub fn io_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.0.attachment_conf.io_timeout = Some(timeout).filter(|t| !t.is_zero());
+        self
+    }
+
+    /// Enable the tcp keepalive, with the idle time before sending the probes. Default: disabled.
+    /// A zero time is ignored
+    pub fn keepalive(&mut self, time: Duration) -> &mut Self {
+        self.0.attachment_conf.keepalive = Some(time).filter(|t| !t.is_zero());
+        self
+    }
+
     /// Connection charset. Default: UTF-8
     pub fn charset(&mut self, charset: Charset) -> &mut Self {
         self.1 = charset;
@@ -94,10 +253,19 @@ impl PureRustConnectionBuilder {
     /// some default values.
     ///
     /// Basic string syntax: `firebird://{user}:{pass}@{host}:{port}/{db_name}?charset={charset}&dialect={dialect}`
+    ///
+    /// The `inet://{host}:{port}/{db_name}` and `{host}/{port}:{db_name}` forms are also accepted.
+    /// The `lib` option is ignored
     #[allow(clippy::wrong_self_convention)]
     pub fn from_string(&mut self, s_conn: &str) -> Result<&mut Self, FbError> {
         let settings = conn_string::parse(s_conn)?;
 
+        Ok(self.from_settings(settings))
+    }
+
+    /// Setup the connection with the settings of a parsed connection string
+    #[allow(clippy::wrong_self_convention)]
+    pub(crate) fn from_settings(&mut self, settings: conn_string::ConnStringSettings) -> &mut Self {
         if let Some(host) = settings.host {
             self.host(host);
         }
@@ -128,7 +296,75 @@ impl PureRustConnectionBuilder {
             self.stmt_cache_size(stmt_cache_size);
         }
 
-        Ok(self)
+        if let Some(wire_compression) = settings.wire_compression {
+            self.wire_compression(wire_compression);
+        }
+
+        if let Some(wire_crypt) = settings.wire_crypt {
+            self.wire_crypt(wire_crypt);
+        }
+
+        if let Some(plugins) = settings.wire_crypt_plugins {
+            self.wire_crypt_plugins(&plugins);
+        }
+
+        if let Some(timeout) = settings.connect_timeout {
+            self.connect_timeout(timeout);
+        }
+
+        if let Some(timeout) = settings.io_timeout {
+            self.io_timeout(timeout);
+        }
+
+        if let Some(time) = settings.keepalive {
+            self.keepalive(time);
+        }
+
+        if let Some(timeout) = settings.statement_timeout {
+            self.statement_timeout(timeout);
+        }
+
+        if let Some(timeout) = settings.idle_timeout {
+            self.idle_timeout(timeout);
+        }
+
+        if let Some(role) = settings.role {
+            self.role(role);
+        }
+
+        if let Some(time_zone) = settings.time_zone {
+            self.time_zone(time_zone);
+        }
+
+        if let Some(bind) = settings.set_bind {
+            self.set_bind(bind);
+        }
+
+        if let Some(plugins) = settings.auth_plugins {
+            let plugins = plugins
+                .iter()
+                .filter_map(|name| -> Option<AuthPluginFactory> {
+                    // The names were validated by the parser
+                    match name.as_str() {
+                        "Srp" => Some(Arc::new(|| Box::new(Srp::sha1()))),
+                        "Srp256" => Some(Arc::new(|| Box::new(Srp::sha256()))),
+                        "Legacy_Auth" => Some(Arc::new(|| Box::new(LegacyAuth))),
+                        _ => None,
+                    }
+                })
+                .collect();
+            self.auth_plugins(plugins);
+        }
+
+        if let Some(fetch_size) = settings.fetch_size {
+            self.fetch_size(fetch_size);
+        }
+
+        if let Some(page_size) = settings.page_size {
+            self.page_size(page_size);
+        }
+
+        self
     }
 }
 
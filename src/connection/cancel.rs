@@ -0,0 +1,50 @@
+//!
+//! Rust Firebird Client
+//!
+//! Cancellation of the connection operations
+//!
+
+use rsfbclient_core::{CancelKind, FbError, FirebirdClientCancel};
+use std::sync::Arc;
+
+/// Cancels the operations of a connection from other threads.
+///
+/// The operation in progress fails with `FbError::Cancelled`.
+///
+/// ```no_run
+/// # use rsfbclient::prelude::*;
+/// # fn run(mut conn: rsfbclient::SimpleConnection) -> Result<(), rsfbclient::FbError> {
+/// let cancel = conn.cancel_handle()?;
+///
+/// std::thread::spawn(move || {
+///     std::thread::sleep(std::time::Duration::from_secs(30));
+///     cancel.cancel().ok();
+/// });
+///
+/// let res = conn.execute("execute procedure long_report", ());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct CancelHandle(Arc<dyn FirebirdClientCancel>);
+
+impl CancelHandle {
+    pub(crate) fn new(inner: Box<dyn FirebirdClientCancel>) -> Self {
+        Self(inner.into())
+    }
+
+    /// Cancel the operation in progress
+    pub fn cancel(&self) -> Result<(), FbError> {
+        self.0.cancel_operation(CancelKind::Raise)
+    }
+
+    /// Cancel the operation in progress and close the connection
+    pub fn abort(&self) -> Result<(), FbError> {
+        self.0.cancel_operation(CancelKind::Abort)
+    }
+
+    /// Send a cancel request of the specified kind
+    pub fn cancel_operation(&self, kind: CancelKind) -> Result<(), FbError> {
+        self.0.cancel_operation(kind)
+    }
+}
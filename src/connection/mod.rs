@@ -3,10 +3,16 @@
 //!
 //! Connection functions
 //!
-use rsfbclient_core::{Dialect, FbError, FirebirdClient, FirebirdClientDbOps, FromRow, IntoParams};
-use std::{marker, mem};
-
-use crate::{query::Queryable, statement::StatementData, Execute, Transaction};
+use rsfbclient_core::{
+    BatchRowState, Dialect, FbError, FirebirdClient, FirebirdClientDbOps, FromRow, IntoParams,
+    ParamsType, TrOp,
+};
+use std::{marker, mem, thread, time::Duration};
+
+use crate::{
+    query::Queryable, statement::StatementData, Execute, ExecuteManyOptions, ExecuteManyResult,
+    Transaction, TransactionRetryPolicy,
+};
 use stmt_cache::{StmtCache, StmtCacheData};
 
 pub mod builders {
@@ -15,7 +21,7 @@ pub mod builders {
     use super::{
         super::{charset, Charset},
         Connection, ConnectionConfiguration, Dialect, FbError, FirebirdClient,
-        FirebirdClientFactory,
+        FirebirdClientFactory, ReconnectPolicy,
     };
 
     #[cfg(feature = "native_client")]
@@ -27,14 +33,29 @@ pub mod builders {
     mod builder_pure_rust;
     #[cfg(feature = "pure_rust")]
     pub use builder_pure_rust::*;
+
+    mod builder_simple;
+    pub use builder_simple::*;
 }
 
 pub(crate) mod conn_string;
 pub(crate) mod stmt_cache;
+pub use stmt_cache::StmtCacheStats;
 
 pub(crate) mod simple;
 pub use simple::SimpleConnection;
 
+pub(crate) mod cancel;
+pub use cancel::CancelHandle;
+
+pub(crate) mod reconnect;
+pub use reconnect::ReconnectPolicy;
+
+#[cfg(feature = "async-tokio")]
+pub(crate) mod asynchronous;
+#[cfg(feature = "async-tokio")]
+pub use asynchronous::AsyncConnection;
+
 /// A generic factory for creating multiple preconfigured instances of a particular client implementation
 /// Intended mainly for use by connection pool
 pub trait FirebirdClientFactory {
@@ -47,6 +68,12 @@ pub trait FirebirdClientFactory {
     fn get_conn_conf(
         &self,
     ) -> &ConnectionConfiguration<<Self::C as FirebirdClientDbOps>::AttachmentConfig>;
+
+    /// `true` if the connections are made to an embedded database,
+    /// opened directly by the client process. Default: false
+    fn is_embedded(&self) -> bool {
+        false
+    }
 }
 
 /// Generic aggregate of configuration data for firebird db Connections
@@ -56,6 +83,10 @@ pub struct ConnectionConfiguration<A> {
     attachment_conf: A,
     dialect: Dialect,
     stmt_cache_size: usize,
+    statement_timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    reconnect: Option<ReconnectPolicy>,
+    page_size: Option<u32>,
 }
 
 impl<A: Default> Default for ConnectionConfiguration<A> {
@@ -64,6 +95,10 @@ impl<A: Default> Default for ConnectionConfiguration<A> {
             attachment_conf: Default::default(),
             dialect: Dialect::D3,
             stmt_cache_size: 20,
+            statement_timeout: None,
+            idle_timeout: None,
+            reconnect: None,
+            page_size: None,
         }
     }
 }
@@ -81,6 +116,40 @@ pub struct Connection<C: FirebirdClient> {
 
     /// Firebird client
     pub(crate) cli: C,
+
+    /// Default statement timeout of the session
+    pub(crate) statement_timeout: Option<Duration>,
+
+    /// Statement timeout of the `with_statement_timeout`, replacing the default
+    pub(crate) scoped_statement_timeout: Option<Duration>,
+
+    /// Fetch size of the `with_fetch_size`, replacing the one of the configuration
+    pub(crate) scoped_fetch_size: Option<u32>,
+
+    /// Statement timeout currently set in the session, `None` for the server config
+    pub(crate) session_statement_timeout: Option<Duration>,
+
+    /// Idle timeout of the session
+    pub(crate) idle_timeout: Option<Duration>,
+
+    /// Configuration used to attach again, if the reconnection is enabled
+    pub(crate) reconnect_conf: Option<Box<ConnectionConfiguration<C::AttachmentConfig>>>,
+
+    /// Transactions started and not yet committed or rolled back
+    pub(crate) open_transactions: Vec<C::TrHandle>,
+
+    /// A statement was sent to be executed, since the start of the `retry_reconnect`
+    pub(crate) executed: bool,
+}
+
+/// Sql to set the statement timeout of the session (Firebird 4+). `None` disables it
+pub(crate) fn statement_timeout_sql(timeout: Option<Duration>) -> String {
+    let millis = timeout.map(|t| t.as_millis()).unwrap_or(0);
+
+    format!(
+        "SET STATEMENT TIMEOUT {} MILLISECOND",
+        millis.min(u32::MAX as u128)
+    )
 }
 
 impl<C: FirebirdClient> Connection<C> {
@@ -90,16 +159,312 @@ impl<C: FirebirdClient> Connection<C> {
         conf: &ConnectionConfiguration<C::AttachmentConfig>,
     ) -> Result<Connection<C>, FbError> {
         let handle = cli.attach_database(&conf.attachment_conf)?;
+
+        Self::init(cli, handle, conf)
+    }
+
+    /// Create the database, with the page size of the configuration,
+    /// returning a connection to it
+    pub fn create(
+        mut cli: C,
+        conf: &ConnectionConfiguration<C::AttachmentConfig>,
+    ) -> Result<Connection<C>, FbError> {
+        let handle = cli.create_database(&conf.attachment_conf, conf.page_size, conf.dialect)?;
+
+        Self::init(cli, handle, conf)
+    }
+
+    /// Setup the connection of a new attachment
+    fn init(
+        cli: C,
+        handle: <C as FirebirdClientDbOps>::DbHandle,
+        conf: &ConnectionConfiguration<C::AttachmentConfig>,
+    ) -> Result<Connection<C>, FbError> {
         let stmt_cache = StmtCache::new(conf.stmt_cache_size);
 
-        Ok(Connection {
+        let mut conn = Connection {
             handle,
             dialect: conf.dialect,
             stmt_cache,
             cli,
+            statement_timeout: conf.statement_timeout,
+            scoped_statement_timeout: None,
+            scoped_fetch_size: None,
+            session_statement_timeout: None,
+            idle_timeout: conf.idle_timeout,
+            reconnect_conf: conf.reconnect.map(|_| Box::new(conf.clone())),
+            open_transactions: Vec::new(),
+            executed: false,
+        };
+
+        conn.init_session()?;
+
+        Ok(conn)
+    }
+
+    /// Apply the session settings of a new attachment
+    fn init_session(&mut self) -> Result<(), FbError> {
+        // A new or reset session has the timeout of the server config
+        self.session_statement_timeout = None;
+        self.apply_statement_timeout()?;
+
+        if let Some(timeout) = self.idle_timeout {
+            // Zero disables the timeout, less than a second is rounded up
+            let secs = if timeout.is_zero() {
+                0
+            } else {
+                timeout.as_secs().clamp(1, u32::MAX as u64)
+            };
+            let sql = format!("SET SESSION IDLE TIMEOUT {} SECOND", secs);
+            self.with_transaction(|tr| tr.execute_immediate(&sql))?;
+        }
+
+        Ok(())
+    }
+
+    /// Reset the session state with `ALTER SESSION RESET`, clearing the context
+    /// variables, the global temporary tables and the session settings (role,
+    /// time zone, binds...). The timeouts of the connection are applied again.
+    ///
+    /// Fails if a transaction other than the one used for the reset is open.
+    /// Requires Firebird 4+
+    pub fn reset_session(&mut self) -> Result<(), FbError> {
+        self.with_transaction(|tr| tr.execute_immediate("ALTER SESSION RESET"))?;
+
+        self.init_session()
+    }
+
+    /// Rollback the transactions left open, like a `Transaction` leaked with
+    /// `mem::forget` or one which failed to rollback. Returns the first error,
+    /// after trying to rollback all of them
+    pub fn rollback_open_transactions(&mut self) -> Result<(), FbError> {
+        let mut result = Ok(());
+
+        for mut handle in mem::take(&mut self.open_transactions) {
+            let res = self.cli.transaction_operation(&mut handle, TrOp::Rollback);
+            result = result.and(res);
+        }
+
+        result
+    }
+
+    /// If the connection is broken and the reconnection is enabled, attach to the database
+    /// again, as configured in the `ReconnectPolicy`. The cached statements are discarded.
+    ///
+    /// Must only be called when there is no transaction open
+    pub(crate) fn reconnect_if_broken(&mut self) -> Result<(), FbError> {
+        let conf = match &self.reconnect_conf {
+            Some(conf) if self.cli.is_broken() => conf.clone(),
+            _ => return Ok(()),
+        };
+        let policy = conf.reconnect.unwrap_or_default();
+
+        // The statements and transactions belong to the lost attachment,
+        // the statements will be prepared again when used
+        self.stmt_cache.discard_all();
+        self.open_transactions.clear();
+
+        // Release the client resources of the lost attachment
+        self.cli.detach_database(&mut self.handle).ok();
+
+        let mut attempt = 1;
+        loop {
+            let mut attached = false;
+            let res = self
+                .cli
+                .attach_database(&conf.attachment_conf)
+                .and_then(|handle| {
+                    self.handle = handle;
+                    attached = true;
+                    self.init_session()
+                });
+
+            match res {
+                Err(_) if attempt < policy.max_attempts => {
+                    if attached {
+                        // Don't leak the attachment which failed the session setup
+                        self.cli.detach_database(&mut self.handle).ok();
+                    }
+
+                    thread::sleep(policy.backoff(attempt));
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// Run an operation, running it again if it fails because the connection was
+    /// lost before executing any statement and the reconnection is enabled.
+    ///
+    /// A statement sent to be executed may have changed the database even when its
+    /// response was lost, like a selectable procedure or an `UPDATE ... RETURNING`,
+    /// so the operation is not run again
+    fn retry_reconnect<T, F>(&mut self, mut f: F) -> Result<T, FbError>
+    where
+        F: FnMut(&mut Self) -> Result<T, FbError>,
+    {
+        self.executed = false;
+
+        match f(self) {
+            // The next attempt reconnects when starting the transaction
+            Err(_) if self.reconnect_conf.is_some() && self.cli.is_broken() && !self.executed => {
+                f(self)
+            }
+            res => res,
+        }
+    }
+
+    /// Run a closure with a different statement timeout for the statements
+    /// executed by it, restoring the connection default at the end.
+    ///
+    /// A statement exceeding the timeout fails with `FbError::StatementTimeout`.
+    /// Requires Firebird 4+
+    pub fn with_statement_timeout<T, F>(
+        &mut self,
+        timeout: Duration,
+        closure: F,
+    ) -> Result<T, FbError>
+    where
+        F: FnOnce(&mut Self) -> Result<T, FbError>,
+    {
+        let previous = self.set_scoped_statement_timeout(Some(timeout))?;
+
+        let res = closure(self);
+
+        let restored = self.set_scoped_statement_timeout(previous);
+
+        let value = res?;
+        restored?;
+
+        Ok(value)
+    }
+
+    /// Run a closure with a different number of rows requested from the server in
+    /// each fetch round trip, for the queries executed by it, restoring the
+    /// connection default at the end.
+    ///
+    /// Only used by the pure rust client, as the native client manages the prefetch
+    /// by itself. A statement with its own `Statement::set_fetch_size` keeps it
+    pub fn with_fetch_size<T, F>(&mut self, fetch_size: u32, closure: F) -> Result<T, FbError>
+    where
+        F: FnOnce(&mut Self) -> Result<T, FbError>,
+    {
+        let previous = self.set_scoped_fetch_size(Some(fetch_size));
+
+        let res = closure(self);
+
+        self.set_scoped_fetch_size(previous);
+
+        res
+    }
+
+    /// Statistics and contents of the statement cache
+    pub fn stmt_cache_stats(&self) -> StmtCacheStats {
+        self.stmt_cache.stats()
+    }
+
+    /// Remove the statement of the sql from the cache, closing it, so it will be
+    /// prepared again in the next use. Returns `false` if it was not in the cache
+    pub fn stmt_cache_invalidate(&mut self, sql: &str) -> Result<bool, FbError> {
+        StmtCache::invalidate(self, sql)
+    }
+
+    /// Remove all the statements from the cache, closing them.
+    /// The statistics and the pins are kept
+    pub fn stmt_cache_clear(&mut self) -> Result<(), FbError> {
+        StmtCache::clear(self)
+    }
+
+    /// Keep the statement of the sql in the cache from the next time it is used,
+    /// without being evicted by the other statements or counted in the `stmt_cache_size`
+    pub fn stmt_cache_pin(&mut self, sql: &str) {
+        self.stmt_cache.pin(sql)
+    }
+
+    /// Allow the statement of the sql to be evicted again
+    pub fn stmt_cache_unpin(&mut self, sql: &str) -> Result<(), FbError> {
+        StmtCache::unpin_and_close(self, sql)
+    }
+
+    /// Run a closure without the statement cache: the statements
+    /// executed by it are prepared and closed in each use
+    pub fn without_stmt_cache<T, F>(&mut self, closure: F) -> Result<T, FbError>
+    where
+        F: FnOnce(&mut Self) -> Result<T, FbError>,
+    {
+        let previous = StmtCache::set_bypass(self, true);
+
+        let res = closure(self);
+
+        StmtCache::set_bypass(self, previous);
+
+        res
+    }
+
+    /// Statement timeout of the session for the statements without their own timeout
+    pub(crate) fn default_statement_timeout(&self) -> Option<Duration> {
+        self.scoped_statement_timeout.or(self.statement_timeout)
+    }
+
+    /// Set the statement timeout of the `with_statement_timeout` in the
+    /// session, `None` for the connection default. Returns the previous one
+    pub(crate) fn set_scoped_statement_timeout(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<Option<Duration>, FbError> {
+        let previous = mem::replace(&mut self.scoped_statement_timeout, timeout);
+
+        if let Err(e) = self.apply_statement_timeout() {
+            self.scoped_statement_timeout = previous;
+            return Err(e);
+        }
+
+        Ok(previous)
+    }
+
+    /// Set the fetch size of the `with_fetch_size`, `None` for
+    /// the connection default. Returns the previous one
+    pub(crate) fn set_scoped_fetch_size(&mut self, fetch_size: Option<u32>) -> Option<u32> {
+        mem::replace(&mut self.scoped_fetch_size, fetch_size)
+    }
+
+    /// Set the `default_statement_timeout` in the session, if not already set
+    pub(crate) fn apply_statement_timeout(&mut self) -> Result<(), FbError> {
+        let timeout = self.default_statement_timeout();
+
+        if self.session_statement_timeout == timeout {
+            return Ok(());
+        }
+
+        self.with_transaction(|tr| {
+            let Transaction { conn, data } = tr;
+            conn.set_session_statement_timeout(&mut data.handle, timeout)
         })
     }
 
+    /// Set the statement timeout of the session in the transaction, if different
+    /// from the current one, so it is only sent to the server when changed
+    pub(crate) fn set_session_statement_timeout(
+        &mut self,
+        tr_handle: &mut C::TrHandle,
+        timeout: Option<Duration>,
+    ) -> Result<(), FbError> {
+        if self.session_statement_timeout == timeout {
+            return Ok(());
+        }
+
+        self.cli.exec_immediate(
+            &mut self.handle,
+            tr_handle,
+            self.dialect,
+            &statement_timeout_sql(timeout),
+        )?;
+        self.session_statement_timeout = timeout;
+
+        Ok(())
+    }
+
     /// Drop the current database
     pub fn drop_database(mut self) -> Result<(), FbError> {
         self.cli.drop_database(&mut self.handle)?;
@@ -107,6 +472,24 @@ impl<C: FirebirdClient> Connection<C> {
         Ok(())
     }
 
+    /// Create a handle to cancel the operations of this connection
+    /// from other threads
+    pub fn cancel_handle(&mut self) -> Result<CancelHandle, FbError> {
+        Ok(CancelHandle::new(self.cli.cancel_handle(&mut self.handle)?))
+    }
+
+    /// Check if the server is still reachable, with a cheap roundtrip
+    /// that doesn't need a transaction
+    pub fn ping(&mut self) -> Result<(), FbError> {
+        self.cli.ping(&mut self.handle)
+    }
+
+    /// `true` if the connection found an I/O or network error, so it can't
+    /// be used anymore. Doesn't contact the server, use `ping` for that
+    pub fn is_broken(&self) -> bool {
+        self.cli.is_broken()
+    }
+
     /// Close the current connection.
     pub fn close(mut self) -> Result<(), FbError> {
         let res = self.cleanup_and_detach();
@@ -141,6 +524,107 @@ impl<C: FirebirdClient> Connection<C> {
 
         res
     }
+
+    /// Run a closure with a transaction, like `with_transaction`, but running
+    /// it again in a new transaction if it fails due to a lock conflict, deadlock or
+    /// update conflict, as configured in the `policy`
+    pub fn with_transaction_retry<T, F>(
+        &mut self,
+        policy: TransactionRetryPolicy,
+        mut closure: F,
+    ) -> Result<T, FbError>
+    where
+        F: FnMut(&mut Transaction<C>) -> Result<T, FbError>,
+    {
+        policy.run(|| self.with_transaction(&mut closure))
+    }
+
+    /// `true` if the client and the server support the batch api, used
+    /// by the `execute_batch` and `Statement::batch` to send the parameter
+    /// sets in bulk. Else they are executed one by one
+    pub fn supports_batch(&self) -> bool {
+        self.cli.supports_batch()
+    }
+
+    /// Execute the statement once for each of the parameter sets, in a new transaction,
+    /// returning the completion state of each one, in the same order.
+    ///
+    /// The parameter sets are sent in bulk using the batch api, supported by the
+    /// pure rust client with Firebird 4+, see `supports_batch`. Else they are
+    /// executed one by one, with the same completion states.
+    /// A failed parameter set doesn't stop the execution of the others
+    pub fn execute_batch<P, I>(
+        &mut self,
+        sql: &str,
+        params: I,
+    ) -> Result<Vec<BatchRowState>, FbError>
+    where
+        I: IntoIterator<Item = P>,
+        P: IntoParams,
+    {
+        let params: Vec<ParamsType> = params.into_iter().map(IntoParams::to_params).collect();
+        let named = params.first().map(ParamsType::named).unwrap_or(false);
+
+        let mut tr = Transaction::new(self)?;
+
+        // Get a statement from the cache
+        let mut stmt_cache_data = StmtCache::get_or_prepare(&mut tr, sql, named)?;
+
+        // Do not return now in case of error, because we need to return the statement to the cache
+        let res = stmt_cache_data
+            .stmt
+            .execute_batch(tr.conn, &mut tr.data, params);
+
+        // Return the statement to the cache
+        StmtCache::insert_and_close(tr.conn, stmt_cache_data)?;
+
+        let states = res?;
+
+        tr.commit()?;
+
+        Ok(states)
+    }
+
+    /// Execute the statement once for each of the parameter sets, in a new transaction.
+    ///
+    /// The statement is prepared only once. The execution stops at the first
+    /// parameter set that fails, with any error, which is reported in the result
+    /// with its index. The changes of the parameter sets executed before it are committed,
+    /// unless the connection was lost, so the caller can resume from the `committed` count
+    pub fn execute_many<P, I>(&mut self, sql: &str, params: I) -> Result<ExecuteManyResult, FbError>
+    where
+        I: IntoIterator<Item = P>,
+        P: IntoParams,
+    {
+        self.execute_many_with(sql, params, ExecuteManyOptions::default())
+    }
+
+    /// Like `execute_many`, with the options
+    pub fn execute_many_with<P, I>(
+        &mut self,
+        sql: &str,
+        params: I,
+        options: ExecuteManyOptions,
+    ) -> Result<ExecuteManyResult, FbError>
+    where
+        I: IntoIterator<Item = P>,
+        P: IntoParams,
+    {
+        let mut tr = Transaction::new(self)?;
+
+        let mut result = tr.execute_many_with(sql, params, options)?;
+
+        match tr.commit() {
+            Ok(()) => result.committed = result.executed,
+            // Reported as the failure of the next parameter set, as the
+            // ones after the last `commit_every` must be executed again
+            Err(e) if result.failed.is_none() => result.failed = Some((result.executed, e)),
+            // The connection may be broken by the error already reported
+            Err(_) => {}
+        }
+
+        Ok(result)
+    }
 }
 
 impl<C: FirebirdClient> Drop for Connection<C> {
@@ -237,6 +721,26 @@ where
             }
         }
     }
+
+    fn query<P, R>(&mut self, sql: &str, params: P) -> Result<Vec<R>, FbError>
+    where
+        P: IntoParams,
+        R: FromRow + 'static,
+    {
+        let params = params.to_params();
+
+        self.retry_reconnect(|conn| conn.query_iter(sql, params.clone())?.collect())
+    }
+
+    fn query_first<P, R>(&mut self, sql: &str, params: P) -> Result<Option<R>, FbError>
+    where
+        P: IntoParams,
+        R: FromRow + 'static,
+    {
+        let params = params.to_params();
+
+        self.retry_reconnect(|conn| conn.query_iter(sql, params.clone())?.next().transpose())
+    }
 }
 
 impl<C> Execute for Connection<C>
@@ -324,4 +828,33 @@ mk_tests_default! {
 
         Ok(())
     }
+
+    #[test]
+    fn with_transaction_retry() -> Result<(), FbError> {
+        let mut conn = cbuilder().connect()?;
+
+        let policy = TransactionRetryPolicy::default()
+            .initial_backoff(std::time::Duration::from_millis(1));
+
+        let mut attempts = 0;
+        let (v,): (i32,) = conn.with_transaction_retry(policy, |tr| {
+            attempts += 1;
+
+            let row = tr.query_first("SELECT -3 FROM RDB$DATABASE", ())?;
+
+            if attempts < 3 {
+                return Err(FbError::Sql {
+                    code: -913,
+                    msg: "deadlock\nupdate conflicts with concurrent update".to_string(),
+                });
+            }
+
+            Ok(row.unwrap())
+        })?;
+
+        assert_eq!(v, -3);
+        assert_eq!(attempts, 3);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,113 @@
+//!
+//! Rust Firebird Client
+//!
+//! Automatic reconnection of connections that lost the link with the server
+//!
+
+use std::time::Duration;
+
+use crate::transaction::retry::{exponential_backoff, jitter};
+
+/// Configuration of the automatic reconnection, enabled with the `reconnect` method of the builders
+///
+/// When the connection finds an I/O or network error, the next operation started outside
+/// of a transaction attaches to the database again, sleeping for an exponentially increasing
+/// amount of time between the failed attempts. The prepared statements are discarded and
+/// prepared again when used.
+///
+/// Queries made directly on the connection are run again after reconnecting, if the connection
+/// was lost before the statement was sent to be executed, as it may have changed the database.
+/// Other operations return the error, as their changes may have been committed before the
+/// connection was lost. Errors inside a transaction are always returned.
+#[derive(Debug, Clone, Copy)]
+pub struct ReconnectPolicy {
+    /// Maximum number of attach attempts for each reconnection. Default: 3
+    pub max_attempts: u32,
+    /// Backoff used after the first failed attempt. Default: 100ms
+    pub initial_backoff: Duration,
+    /// Upper limit for the backoff. Default: 5s
+    pub max_backoff: Duration,
+    /// Factor applied to the backoff after each failed attempt. Default: 2
+    pub multiplier: u32,
+    /// Randomize the backoff between 50% and 100% of its value,
+    /// to avoid all the clients reconnecting at the same time. Default: true
+    pub jitter: bool,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            multiplier: 2,
+            jitter: true,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Maximum number of attach attempts for each reconnection
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Backoff used after the first failed attempt
+    pub fn initial_backoff(mut self, initial_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    /// Upper limit for the backoff
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Factor applied to the backoff after each failed attempt
+    pub fn multiplier(mut self, multiplier: u32) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Enable or disable the random jitter on the backoff
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Time to sleep after the failed attempt number `attempt` (starting from 1)
+    pub(crate) fn backoff(&self, attempt: u32) -> Duration {
+        let backoff = exponential_backoff(
+            self.initial_backoff,
+            self.max_backoff,
+            self.multiplier,
+            attempt,
+        );
+
+        if self.jitter {
+            jitter(backoff)
+        } else {
+            backoff
+        }
+    }
+}
+
+#[test]
+fn reconnect_policy_backoff_test() {
+    let policy = ReconnectPolicy::default()
+        .initial_backoff(Duration::from_millis(100))
+        .max_backoff(Duration::from_millis(300))
+        .jitter(false);
+
+    assert_eq!(policy.backoff(1), Duration::from_millis(100));
+    assert_eq!(policy.backoff(2), Duration::from_millis(200));
+    assert_eq!(policy.backoff(3), Duration::from_millis(300));
+
+    let policy = policy.jitter(true);
+    for _ in 0..100 {
+        let b = policy.backoff(2);
+        assert!(b >= Duration::from_millis(100) && b <= Duration::from_millis(200));
+    }
+}
@@ -3,7 +3,12 @@
 //! multiple connection types/variations.
 //!
 
-use crate::{Connection, Execute, FbError, FromRow, IntoParams, Queryable, SimpleTransaction};
+use crate::connection::stmt_cache::StmtCache;
+use crate::{
+    builder_simple, CancelHandle, Connection, Execute, ExecuteManyOptions, ExecuteManyResult,
+    FbError, FromRow, IntoParams, Queryable, SimpleTransaction, StmtCacheStats,
+    TransactionRetryPolicy,
+};
 #[cfg(feature = "linking")]
 use rsfbclient_native::DynLink;
 #[cfg(feature = "dynamic_loading")]
@@ -13,6 +18,7 @@ use rsfbclient_native::NativeFbClient;
 #[cfg(feature = "pure_rust")]
 use rsfbclient_rust::RustFbClient;
 use std::convert::{From, TryFrom};
+use std::time::Duration;
 
 /// A connection API without client types
 pub struct SimpleConnection {
@@ -95,6 +101,13 @@ impl TryFrom<SimpleConnection> for Connection<RustFbClient> {
 }
 
 impl SimpleConnection {
+    /// Open a connection with the client selected by the connection string.
+    ///
+    /// See `builder_simple` for the `client` option
+    pub fn connect(s_conn: &str) -> Result<SimpleConnection, FbError> {
+        builder_simple().from_string(s_conn)?.connect()
+    }
+
     /// Drop the current database
     pub fn drop_database(self) -> Result<(), FbError> {
         match self.inner {
@@ -137,6 +150,283 @@ impl SimpleConnection {
 
         res
     }
+
+    /// Run a closure with a transaction, like `with_transaction`, but running
+    /// it again in a new transaction if it fails due to a lock conflict, deadlock or
+    /// update conflict, as configured in the `policy`
+    pub fn with_transaction_retry<T, F>(
+        &mut self,
+        policy: TransactionRetryPolicy,
+        mut closure: F,
+    ) -> Result<T, FbError>
+    where
+        F: FnMut(&mut SimpleTransaction) -> Result<T, FbError>,
+    {
+        policy.run(|| self.with_transaction(&mut closure))
+    }
+
+    /// Run a closure with a different statement timeout for the statements
+    /// executed by it, restoring the connection default at the end.
+    ///
+    /// A statement exceeding the timeout fails with `FbError::StatementTimeout`.
+    /// Requires Firebird 4+
+    pub fn with_statement_timeout<T, F>(
+        &mut self,
+        timeout: Duration,
+        closure: F,
+    ) -> Result<T, FbError>
+    where
+        F: FnOnce(&mut SimpleConnection) -> Result<T, FbError>,
+    {
+        let previous = self.set_scoped_statement_timeout(Some(timeout))?;
+
+        let res = closure(self);
+
+        let restored = self.set_scoped_statement_timeout(previous);
+
+        let value = res?;
+        restored?;
+
+        Ok(value)
+    }
+
+    /// Set the statement timeout of the `with_statement_timeout` in the
+    /// session, `None` for the connection default. Returns the previous one
+    fn set_scoped_statement_timeout(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<Option<Duration>, FbError> {
+        match &mut self.inner {
+            #[cfg(feature = "linking")]
+            TypeConnectionContainer::NativeDynLink(c) => c.set_scoped_statement_timeout(timeout),
+            #[cfg(feature = "dynamic_loading")]
+            TypeConnectionContainer::NativeDynLoad(c) => c.set_scoped_statement_timeout(timeout),
+            #[cfg(feature = "pure_rust")]
+            TypeConnectionContainer::PureRust(c) => c.set_scoped_statement_timeout(timeout),
+        }
+    }
+
+    /// Run a closure with a different number of rows requested from the server in
+    /// each fetch round trip, for the queries executed by it, restoring the
+    /// connection default at the end. See `Connection::with_fetch_size`
+    pub fn with_fetch_size<T, F>(&mut self, fetch_size: u32, closure: F) -> Result<T, FbError>
+    where
+        F: FnOnce(&mut SimpleConnection) -> Result<T, FbError>,
+    {
+        let previous = self.set_scoped_fetch_size(Some(fetch_size));
+
+        let res = closure(self);
+
+        self.set_scoped_fetch_size(previous);
+
+        res
+    }
+
+    /// Set the fetch size of the `with_fetch_size`, `None` for
+    /// the connection default. Returns the previous one
+    fn set_scoped_fetch_size(&mut self, fetch_size: Option<u32>) -> Option<u32> {
+        match &mut self.inner {
+            #[cfg(feature = "linking")]
+            TypeConnectionContainer::NativeDynLink(c) => c.set_scoped_fetch_size(fetch_size),
+            #[cfg(feature = "dynamic_loading")]
+            TypeConnectionContainer::NativeDynLoad(c) => c.set_scoped_fetch_size(fetch_size),
+            #[cfg(feature = "pure_rust")]
+            TypeConnectionContainer::PureRust(c) => c.set_scoped_fetch_size(fetch_size),
+        }
+    }
+
+    /// Reset the session state with `ALTER SESSION RESET`, clearing the context
+    /// variables, the global temporary tables and the session settings. The timeouts
+    /// of the connection are applied again.
+    ///
+    /// Requires Firebird 4+
+    pub fn reset_session(&mut self) -> Result<(), FbError> {
+        match &mut self.inner {
+            #[cfg(feature = "linking")]
+            TypeConnectionContainer::NativeDynLink(c) => c.reset_session(),
+            #[cfg(feature = "dynamic_loading")]
+            TypeConnectionContainer::NativeDynLoad(c) => c.reset_session(),
+            #[cfg(feature = "pure_rust")]
+            TypeConnectionContainer::PureRust(c) => c.reset_session(),
+        }
+    }
+
+    /// Rollback the transactions left open, like a `Transaction` leaked with
+    /// `mem::forget` or one which failed to rollback
+    pub fn rollback_open_transactions(&mut self) -> Result<(), FbError> {
+        match &mut self.inner {
+            #[cfg(feature = "linking")]
+            TypeConnectionContainer::NativeDynLink(c) => c.rollback_open_transactions(),
+            #[cfg(feature = "dynamic_loading")]
+            TypeConnectionContainer::NativeDynLoad(c) => c.rollback_open_transactions(),
+            #[cfg(feature = "pure_rust")]
+            TypeConnectionContainer::PureRust(c) => c.rollback_open_transactions(),
+        }
+    }
+
+    /// Create a handle to cancel the operations of this connection
+    /// from other threads
+    pub fn cancel_handle(&mut self) -> Result<CancelHandle, FbError> {
+        match &mut self.inner {
+            #[cfg(feature = "linking")]
+            TypeConnectionContainer::NativeDynLink(c) => c.cancel_handle(),
+            #[cfg(feature = "dynamic_loading")]
+            TypeConnectionContainer::NativeDynLoad(c) => c.cancel_handle(),
+            #[cfg(feature = "pure_rust")]
+            TypeConnectionContainer::PureRust(c) => c.cancel_handle(),
+        }
+    }
+
+    /// Statistics and contents of the statement cache
+    pub fn stmt_cache_stats(&self) -> StmtCacheStats {
+        match &self.inner {
+            #[cfg(feature = "linking")]
+            TypeConnectionContainer::NativeDynLink(c) => c.stmt_cache_stats(),
+            #[cfg(feature = "dynamic_loading")]
+            TypeConnectionContainer::NativeDynLoad(c) => c.stmt_cache_stats(),
+            #[cfg(feature = "pure_rust")]
+            TypeConnectionContainer::PureRust(c) => c.stmt_cache_stats(),
+        }
+    }
+
+    /// Remove the statement of the sql from the cache, closing it, so it will be
+    /// prepared again in the next use. Returns `false` if it was not in the cache
+    pub fn stmt_cache_invalidate(&mut self, sql: &str) -> Result<bool, FbError> {
+        match &mut self.inner {
+            #[cfg(feature = "linking")]
+            TypeConnectionContainer::NativeDynLink(c) => c.stmt_cache_invalidate(sql),
+            #[cfg(feature = "dynamic_loading")]
+            TypeConnectionContainer::NativeDynLoad(c) => c.stmt_cache_invalidate(sql),
+            #[cfg(feature = "pure_rust")]
+            TypeConnectionContainer::PureRust(c) => c.stmt_cache_invalidate(sql),
+        }
+    }
+
+    /// Remove all the statements from the cache, closing them.
+    /// The statistics and the pins are kept
+    pub fn stmt_cache_clear(&mut self) -> Result<(), FbError> {
+        match &mut self.inner {
+            #[cfg(feature = "linking")]
+            TypeConnectionContainer::NativeDynLink(c) => c.stmt_cache_clear(),
+            #[cfg(feature = "dynamic_loading")]
+            TypeConnectionContainer::NativeDynLoad(c) => c.stmt_cache_clear(),
+            #[cfg(feature = "pure_rust")]
+            TypeConnectionContainer::PureRust(c) => c.stmt_cache_clear(),
+        }
+    }
+
+    /// Keep the statement of the sql in the cache from the next time it is used,
+    /// without being evicted by the other statements or counted in the `stmt_cache_size`
+    pub fn stmt_cache_pin(&mut self, sql: &str) {
+        match &mut self.inner {
+            #[cfg(feature = "linking")]
+            TypeConnectionContainer::NativeDynLink(c) => c.stmt_cache_pin(sql),
+            #[cfg(feature = "dynamic_loading")]
+            TypeConnectionContainer::NativeDynLoad(c) => c.stmt_cache_pin(sql),
+            #[cfg(feature = "pure_rust")]
+            TypeConnectionContainer::PureRust(c) => c.stmt_cache_pin(sql),
+        }
+    }
+
+    /// Allow the statement of the sql to be evicted again
+    pub fn stmt_cache_unpin(&mut self, sql: &str) -> Result<(), FbError> {
+        match &mut self.inner {
+            #[cfg(feature = "linking")]
+            TypeConnectionContainer::NativeDynLink(c) => c.stmt_cache_unpin(sql),
+            #[cfg(feature = "dynamic_loading")]
+            TypeConnectionContainer::NativeDynLoad(c) => c.stmt_cache_unpin(sql),
+            #[cfg(feature = "pure_rust")]
+            TypeConnectionContainer::PureRust(c) => c.stmt_cache_unpin(sql),
+        }
+    }
+
+    /// Run a closure without the statement cache: the statements
+    /// executed by it are prepared and closed in each use
+    pub fn without_stmt_cache<T, F>(&mut self, closure: F) -> Result<T, FbError>
+    where
+        F: FnOnce(&mut SimpleConnection) -> Result<T, FbError>,
+    {
+        let previous = self.set_stmt_cache_bypass(true);
+
+        let res = closure(self);
+
+        self.set_stmt_cache_bypass(previous);
+
+        res
+    }
+
+    /// Enable or disable the bypass of the statement cache, returning the previous state
+    fn set_stmt_cache_bypass(&mut self, bypass: bool) -> bool {
+        match &mut self.inner {
+            #[cfg(feature = "linking")]
+            TypeConnectionContainer::NativeDynLink(c) => StmtCache::set_bypass(c, bypass),
+            #[cfg(feature = "dynamic_loading")]
+            TypeConnectionContainer::NativeDynLoad(c) => StmtCache::set_bypass(c, bypass),
+            #[cfg(feature = "pure_rust")]
+            TypeConnectionContainer::PureRust(c) => StmtCache::set_bypass(c, bypass),
+        }
+    }
+
+    /// Check if the server is still reachable, with a cheap roundtrip
+    /// that doesn't need a transaction
+    pub fn ping(&mut self) -> Result<(), FbError> {
+        match &mut self.inner {
+            #[cfg(feature = "linking")]
+            TypeConnectionContainer::NativeDynLink(c) => c.ping(),
+            #[cfg(feature = "dynamic_loading")]
+            TypeConnectionContainer::NativeDynLoad(c) => c.ping(),
+            #[cfg(feature = "pure_rust")]
+            TypeConnectionContainer::PureRust(c) => c.ping(),
+        }
+    }
+
+    /// `true` if the connection found an I/O or network error, so it can't
+    /// be used anymore
+    pub fn is_broken(&self) -> bool {
+        match &self.inner {
+            #[cfg(feature = "linking")]
+            TypeConnectionContainer::NativeDynLink(c) => c.is_broken(),
+            #[cfg(feature = "dynamic_loading")]
+            TypeConnectionContainer::NativeDynLoad(c) => c.is_broken(),
+            #[cfg(feature = "pure_rust")]
+            TypeConnectionContainer::PureRust(c) => c.is_broken(),
+        }
+    }
+
+    /// Execute the statement once for each of the parameter sets, in a new transaction.
+    ///
+    /// The statement is prepared only once. The execution stops at the first
+    /// parameter set that fails, with any error, which is reported in the result
+    /// with its index. The changes of the parameter sets executed before it are committed,
+    /// unless the connection was lost, so the caller can resume from the `committed` count
+    pub fn execute_many<P, I>(&mut self, sql: &str, params: I) -> Result<ExecuteManyResult, FbError>
+    where
+        I: IntoIterator<Item = P>,
+        P: IntoParams,
+    {
+        self.execute_many_with(sql, params, ExecuteManyOptions::default())
+    }
+
+    /// Like `execute_many`, with the options
+    pub fn execute_many_with<P, I>(
+        &mut self,
+        sql: &str,
+        params: I,
+        options: ExecuteManyOptions,
+    ) -> Result<ExecuteManyResult, FbError>
+    where
+        I: IntoIterator<Item = P>,
+        P: IntoParams,
+    {
+        match &mut self.inner {
+            #[cfg(feature = "linking")]
+            TypeConnectionContainer::NativeDynLink(c) => c.execute_many_with(sql, params, options),
+            #[cfg(feature = "dynamic_loading")]
+            TypeConnectionContainer::NativeDynLoad(c) => c.execute_many_with(sql, params, options),
+            #[cfg(feature = "pure_rust")]
+            TypeConnectionContainer::PureRust(c) => c.execute_many_with(sql, params, options),
+        }
+    }
 }
 
 impl Execute for SimpleConnection {
@@ -189,6 +479,36 @@ impl Queryable for SimpleConnection {
             TypeConnectionContainer::PureRust(c) => c.query_iter(sql, params),
         }
     }
+
+    fn query<P, R>(&mut self, sql: &str, params: P) -> Result<Vec<R>, FbError>
+    where
+        P: IntoParams,
+        R: FromRow + 'static,
+    {
+        match &mut self.inner {
+            #[cfg(feature = "linking")]
+            TypeConnectionContainer::NativeDynLink(c) => c.query(sql, params),
+            #[cfg(feature = "dynamic_loading")]
+            TypeConnectionContainer::NativeDynLoad(c) => c.query(sql, params),
+            #[cfg(feature = "pure_rust")]
+            TypeConnectionContainer::PureRust(c) => c.query(sql, params),
+        }
+    }
+
+    fn query_first<P, R>(&mut self, sql: &str, params: P) -> Result<Option<R>, FbError>
+    where
+        P: IntoParams,
+        R: FromRow + 'static,
+    {
+        match &mut self.inner {
+            #[cfg(feature = "linking")]
+            TypeConnectionContainer::NativeDynLink(c) => c.query_first(sql, params),
+            #[cfg(feature = "dynamic_loading")]
+            TypeConnectionContainer::NativeDynLoad(c) => c.query_first(sql, params),
+            #[cfg(feature = "pure_rust")]
+            TypeConnectionContainer::PureRust(c) => c.query_first(sql, params),
+        }
+    }
 }
 
 #[cfg(test)]
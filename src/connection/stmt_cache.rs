@@ -5,7 +5,10 @@
 //!
 
 use lru_cache::LruCache;
-use std::{collections::HashSet, mem};
+use std::{
+    collections::{HashMap, HashSet},
+    mem,
+};
 
 use crate::{statement::StatementData, Connection, FbError, Transaction};
 use rsfbclient_core::FirebirdClient;
@@ -16,6 +19,18 @@ use rsfbclient_core::FirebirdClient;
 pub struct StmtCache<T> {
     cache: LruCache<String, T>,
     sqls: HashSet<String>,
+
+    /// Statements never evicted, not counted in the capacity
+    pinned: HashMap<String, T>,
+    /// Sqls marked to be pinned when added to the cache
+    pinned_sqls: HashSet<String>,
+
+    /// The statements are not cached, closing them after use
+    bypass: bool,
+
+    hits: u64,
+    misses: u64,
+    evictions: u64,
 }
 
 pub struct StmtCacheData<T> {
@@ -23,55 +38,180 @@ pub struct StmtCacheData<T> {
     pub(crate) stmt: T,
 }
 
+/// Statistics of the statement cache of a connection,
+/// to help to choose the `stmt_cache_size`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StmtCacheStats {
+    /// Statements found in the cache
+    pub hits: u64,
+    /// Statements not found in the cache, so prepared
+    pub misses: u64,
+    /// Statements closed to make room for others
+    pub evictions: u64,
+    /// Max number of statements, not counting the pinned ones
+    pub capacity: usize,
+    /// Sqls of the cached statements, the pinned ones first, sorted,
+    /// then from the most to the least recently used
+    pub sqls: Vec<String>,
+    /// Sqls marked as pinned, sorted
+    pub pinned: Vec<String>,
+}
+
+impl StmtCacheStats {
+    /// Ratio of the statements found in the cache, from 0 to 1
+    pub fn hit_ratio(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            total => self.hits as f64 / total as f64,
+        }
+    }
+}
+
 /// General functions
 impl<T> StmtCache<T> {
     pub fn new(capacity: usize) -> Self {
         Self {
             cache: LruCache::new(capacity),
             sqls: HashSet::with_capacity(capacity),
+            pinned: HashMap::new(),
+            pinned_sqls: HashSet::new(),
+            bypass: false,
+            hits: 0,
+            misses: 0,
+            evictions: 0,
+        }
+    }
+
+    /// Remove all the statements without closing them, as they
+    /// belong to an attachment that was lost. The pins and
+    /// statistics are kept
+    pub fn discard_all(&mut self) {
+        self.cache.clear();
+        self.sqls.clear();
+        self.pinned.clear();
+    }
+
+    /// Statistics and contents of the cache
+    pub fn stats(&self) -> StmtCacheStats {
+        // Sorted, as the order of the hash collections is not deterministic
+        let mut sqls: Vec<String> = self.pinned.keys().cloned().collect();
+        sqls.sort();
+        sqls.extend(self.cache.iter().rev().map(|(sql, _)| sql.clone()));
+
+        let mut pinned: Vec<String> = self.pinned_sqls.iter().cloned().collect();
+        pinned.sort();
+
+        StmtCacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            evictions: self.evictions,
+            capacity: self.cache.capacity(),
+            sqls,
+            pinned,
         }
     }
 
     /// Get a prepared statement from the cache
     fn get(&mut self, sql: &str) -> Option<StmtCacheData<T>> {
-        if let Some(stmt) = self.cache.remove(sql) {
+        let found = if let Some((sql, stmt)) = self.pinned.remove_entry(sql) {
+            Some(StmtCacheData { stmt, sql })
+        } else if let Some(stmt) = self.cache.remove(sql) {
             let sql = self.sqls.take(sql).unwrap();
 
             Some(StmtCacheData { stmt, sql })
         } else {
             None
+        };
+
+        if found.is_some() {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
         }
+
+        found
     }
 
     /// Adds a prepared statement to the cache, returning the previous one for this sql
     /// or another if the cache is full
     fn insert(&mut self, data: StmtCacheData<T>) -> Option<T> {
+        if self.pinned_sqls.contains(&data.sql) {
+            return self.pinned.insert(data.sql, data.stmt);
+        }
+
         if self.sqls.contains(&data.sql) {
             // Insert the new one and return the old
-            self.cache.insert(data.sql, data.stmt)
-        } else {
-            // Insert the sql
-            self.sqls.insert(data.sql.clone());
-
-            // If full, remove the last recently used
-            let old = if self.cache.len() == self.cache.capacity() {
-                if let Some((sql, stmt)) = self.cache.remove_lru() {
-                    // Remove the sql
-                    self.sqls.remove(&sql);
-
-                    Some(stmt)
-                } else {
-                    None
-                }
+            return self.cache.insert(data.sql, data.stmt);
+        }
+
+        if self.cache.capacity() == 0 {
+            return Some(data.stmt);
+        }
+
+        // Insert the sql
+        self.sqls.insert(data.sql.clone());
+
+        // If full, remove the last recently used
+        let old = if self.cache.len() == self.cache.capacity() {
+            if let Some((sql, stmt)) = self.cache.remove_lru() {
+                // Remove the sql
+                self.sqls.remove(&sql);
+                self.evictions += 1;
+
+                Some(stmt)
             } else {
                 None
-            };
+            }
+        } else {
+            None
+        };
 
-            // Insert the new one
-            self.cache.insert(data.sql, data.stmt);
+        // Insert the new one
+        self.cache.insert(data.sql, data.stmt);
 
-            old
+        old
+    }
+
+    /// Remove the statement of the sql from the cache
+    fn remove(&mut self, sql: &str) -> Option<T> {
+        if let Some(stmt) = self.pinned.remove(sql) {
+            return Some(stmt);
         }
+
+        self.sqls.remove(sql);
+        self.cache.remove(sql)
+    }
+
+    /// Remove all the statements
+    fn take_all(&mut self) -> Vec<T> {
+        self.sqls.clear();
+
+        let mut stmts: Vec<T> = self.pinned.drain().map(|(_, stmt)| stmt).collect();
+        while let Some((_, stmt)) = self.cache.remove_lru() {
+            stmts.push(stmt);
+        }
+
+        stmts
+    }
+
+    /// Keep the statement of the sql in the cache, out of the capacity,
+    /// from the next time it is used
+    pub fn pin(&mut self, sql: &str) {
+        self.pinned_sqls.insert(sql.to_string());
+
+        if let Some(stmt) = self.cache.remove(sql) {
+            let sql = self.sqls.take(sql).unwrap();
+            self.pinned.insert(sql, stmt);
+        }
+    }
+
+    /// Return the statement of the sql to the lru cache, returning
+    /// another if the cache is full
+    fn unpin(&mut self, sql: &str) -> Option<T> {
+        self.pinned_sqls.remove(sql);
+
+        let (sql, stmt) = self.pinned.remove_entry(sql)?;
+        self.insert(StmtCacheData { sql, stmt })
     }
 }
 
@@ -86,7 +226,13 @@ where
         sql: &str,
         named_params: bool,
     ) -> Result<StmtCacheData<StatementData<C>>, FbError> {
-        if let Some(data) = tr.conn.stmt_cache.get(sql) {
+        let cached = if tr.conn.stmt_cache.bypass {
+            None
+        } else {
+            tr.conn.stmt_cache.get(sql)
+        };
+
+        if let Some(data) = cached {
             Ok(data)
         } else {
             Ok(StmtCacheData {
@@ -100,9 +246,11 @@ where
     /// or another if the cache is full
     pub fn insert_and_close(
         conn: &mut Connection<C>,
-        data: StmtCacheData<StatementData<C>>,
+        mut data: StmtCacheData<StatementData<C>>,
     ) -> Result<(), FbError> {
-        conn.stmt_cache.sqls.insert(data.sql.clone());
+        if conn.stmt_cache.bypass {
+            return data.stmt.close(conn);
+        }
 
         // Insert the new one and close the old if exists
         if let Some(mut stmt) = conn.stmt_cache.insert(data) {
@@ -112,12 +260,54 @@ where
         Ok(())
     }
 
+    /// Remove the statement of the sql from the cache, closing it.
+    /// Returns `false` if it was not in the cache
+    pub fn invalidate(conn: &mut Connection<C>, sql: &str) -> Result<bool, FbError> {
+        match conn.stmt_cache.remove(sql) {
+            Some(mut stmt) => {
+                stmt.close(conn)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Remove all the statements from the cache, closing them
+    pub fn clear(conn: &mut Connection<C>) -> Result<(), FbError> {
+        let mut res = Ok(());
+
+        for mut stmt in conn.stmt_cache.take_all() {
+            // Close all of them, returning the first error
+            let closed = stmt.close(conn);
+            if res.is_ok() {
+                res = closed;
+            }
+        }
+
+        res
+    }
+
+    /// Allow the statement of the sql to be evicted again, closing
+    /// another if the cache is full
+    pub fn unpin_and_close(conn: &mut Connection<C>, sql: &str) -> Result<(), FbError> {
+        if let Some(mut stmt) = conn.stmt_cache.unpin(sql) {
+            stmt.close(conn)?;
+        }
+
+        Ok(())
+    }
+
+    /// Enable or disable the bypass of the cache, returning the previous state
+    pub fn set_bypass(conn: &mut Connection<C>, bypass: bool) -> bool {
+        mem::replace(&mut conn.stmt_cache.bypass, bypass)
+    }
+
     /// Closes all statements in the cache.
     /// Needs to be called before dropping the cache.
     pub fn close_all(conn: &mut Connection<C>) {
         let mut stmt_cache = mem::replace(&mut conn.stmt_cache, StmtCache::new(0));
 
-        for (_, stmt) in stmt_cache.cache.iter_mut() {
+        for stmt in stmt_cache.take_all().iter_mut() {
             stmt.close(conn).ok();
         }
     }
@@ -169,3 +359,82 @@ fn stmt_cache_test() {
     assert!(cache.cache.is_empty());
     assert!(cache.sqls.is_empty());
 }
+
+#[test]
+fn stmt_cache_stats_test() {
+    let mut cache = StmtCache::new(2);
+
+    let mk_test_data = |n: usize| StmtCacheData {
+        sql: format!("sql {}", n),
+        stmt: n,
+    };
+
+    assert!(cache.get("sql 1").is_none());
+    assert!(cache.insert(mk_test_data(1)).is_none());
+
+    let sql1 = cache.get("sql 1").expect("sql1 not in the cache");
+    assert!(cache.insert(sql1).is_none());
+
+    assert!(cache.insert(mk_test_data(2)).is_none());
+    assert_eq!(cache.insert(mk_test_data(3)), Some(1));
+
+    let stats = cache.stats();
+    assert_eq!(stats.hits, 1);
+    assert_eq!(stats.misses, 1);
+    assert_eq!(stats.evictions, 1);
+    assert_eq!(stats.capacity, 2);
+    assert_eq!(stats.sqls, vec!["sql 3", "sql 2"]);
+    assert_eq!(stats.hit_ratio(), 0.5);
+
+    assert_eq!(cache.remove("sql 2"), Some(2));
+    assert_eq!(cache.remove("sql 2"), None);
+    assert_eq!(cache.stats().sqls, vec!["sql 3"]);
+
+    assert_eq!(cache.take_all(), vec![3]);
+    assert!(cache.stats().sqls.is_empty());
+    assert!(cache.sqls.is_empty());
+
+    // Nothing is cached without capacity
+    let mut cache = StmtCache::new(0);
+    assert_eq!(cache.insert(mk_test_data(1)), Some(1));
+}
+
+#[test]
+fn stmt_cache_pin_test() {
+    let mut cache = StmtCache::new(1);
+
+    let mk_test_data = |n: usize| StmtCacheData {
+        sql: format!("sql {}", n),
+        stmt: n,
+    };
+
+    // Pin a statement already in the cache
+    assert!(cache.insert(mk_test_data(1)).is_none());
+    cache.pin("sql 1");
+
+    // And one not in the cache yet
+    cache.pin("sql 2");
+    assert!(cache.insert(mk_test_data(2)).is_none());
+
+    // The pinned ones are not evicted
+    assert!(cache.insert(mk_test_data(3)).is_none());
+    assert_eq!(cache.insert(mk_test_data(4)), Some(3));
+
+    let stats = cache.stats();
+    assert_eq!(stats.evictions, 1);
+    assert_eq!(stats.sqls, vec!["sql 1", "sql 2", "sql 4"]);
+    assert_eq!(stats.pinned, vec!["sql 1", "sql 2"]);
+
+    let sql1 = cache.get("sql 1").expect("sql1 not in the cache");
+    assert!(cache.insert(sql1).is_none());
+
+    // Unpinned, returns to the lru and evicts another
+    assert_eq!(cache.unpin("sql 1"), Some(4));
+    assert_eq!(cache.stats().pinned, vec!["sql 2"]);
+    assert_eq!(cache.stats().sqls, vec!["sql 2", "sql 1"]);
+
+    // The pins are kept when the statements are discarded
+    cache.discard_all();
+    assert!(cache.stats().sqls.is_empty());
+    assert_eq!(cache.stats().pinned, vec!["sql 2"]);
+}
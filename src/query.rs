@@ -5,6 +5,12 @@
 //!
 
 use rsfbclient_core::{FbError, FromRow, IntoParams};
+#[cfg(feature = "async-tokio")]
+use {
+    async_trait::async_trait,
+    futures_util::{Stream, StreamExt},
+    std::pin::Pin,
+};
 
 /// Implemented for types that can be used to execute sql queries
 pub trait Queryable {
@@ -97,3 +103,83 @@ pub trait Execute {
         P: IntoParams,
         R: FromRow + 'static;
 }
+
+/// Stream of the rows returned by the async queries
+#[cfg(feature = "async-tokio")]
+pub type RowStream<'a, R> = Pin<Box<dyn Stream<Item = Result<R, FbError>> + Send + 'a>>;
+
+/// Implemented for types that can be used to execute sql queries without blocking
+#[cfg(feature = "async-tokio")]
+#[async_trait]
+pub trait AsyncQueryable: Send {
+    /// Returns the results of the query as a stream.
+    ///
+    /// The query must be return an open cursor, so for cases like 'insert .. returning'
+    /// you will need to use the [execute_returnable](prelude/trait.AsyncExecute.html#tymethod.execute_returnable) method instead.
+    ///
+    /// If the stream is dropped before the last row, the cursor is released in the
+    /// next operation of the connection.
+    ///
+    /// The possible values for the argument `params` are the same of [Queryable::query_iter](prelude/trait.Queryable.html#tymethod.query_iter)
+    async fn query_stream<'a, P, R>(
+        &'a mut self,
+        sql: &str,
+        params: P,
+    ) -> Result<RowStream<'a, R>, FbError>
+    where
+        P: IntoParams + Send,
+        R: FromRow + Send + 'static;
+
+    /// Returns the results of the query as a `Vec`
+    ///
+    /// The possible values for the argument `params` are the same of [Queryable::query_iter](prelude/trait.Queryable.html#tymethod.query_iter)
+    async fn query<P, R>(&mut self, sql: &str, params: P) -> Result<Vec<R>, FbError>
+    where
+        P: IntoParams + Send,
+        R: FromRow + Send + 'static,
+    {
+        let mut rows = self.query_stream(sql, params).await?;
+
+        let mut res = Vec::new();
+        while let Some(row) = rows.next().await {
+            res.push(row?);
+        }
+
+        Ok(res)
+    }
+
+    /// Returns the first result of the query, or None.
+    ///
+    /// The possible values for the argument `params` are the same of [Queryable::query_iter](prelude/trait.Queryable.html#tymethod.query_iter)
+    async fn query_first<P, R>(&mut self, sql: &str, params: P) -> Result<Option<R>, FbError>
+    where
+        P: IntoParams + Send,
+        R: FromRow + Send + 'static,
+    {
+        self.query_stream(sql, params)
+            .await?
+            .next()
+            .await
+            .transpose()
+    }
+}
+
+/// Implemented for types that can be used to execute sql statements without blocking
+#[cfg(feature = "async-tokio")]
+#[async_trait]
+pub trait AsyncExecute: Send {
+    /// Execute a query, may or may not commit the changes
+    ///
+    /// The possible values for the argument `params` are the same of [Execute::execute](prelude/trait.Execute.html#tymethod.execute)
+    async fn execute<P>(&mut self, sql: &str, params: P) -> Result<(), FbError>
+    where
+        P: IntoParams + Send;
+
+    /// Execute a query that will return data, like the 'insert ... returning ..' or 'execute procedure'.
+    ///
+    /// Use `()` for no parameters or a tuple of parameters
+    async fn execute_returnable<P, R>(&mut self, sql: &str, params: P) -> Result<R, FbError>
+    where
+        P: IntoParams + Send,
+        R: FromRow + Send + 'static;
+}
@@ -9,8 +9,10 @@ use crate::{
     Connection,
 };
 use rsfbclient_core::{
-    Column, FbError, FirebirdClient, FreeStmtOp, FromRow, IntoParams, NamedParams, StmtType,
+    BatchRowState, Column, FbError, FirebirdClient, FreeStmtOp, FromRow, IntoParams, NamedParams,
+    SqlType, StmtType,
 };
+use std::{convert::TryFrom, time::Duration};
 
 pub struct Statement<'c, 't, C: FirebirdClient> {
     pub(crate) data: StatementData<C>,
@@ -59,6 +61,46 @@ where
             _marker: Default::default(),
         })
     }
+
+    /// Start a batch, to collect many parameter sets and execute them in bulk.
+    ///
+    /// Uses the batch api when supported, see `Connection::supports_batch`.
+    /// Else the parameter sets are executed one by one, with the same results
+    pub fn batch<'s>(&'s mut self) -> StatementBatch<'c, 's, C> {
+        StatementBatch {
+            stmt: &mut self.data,
+            tr: self.tr,
+            params: Vec::new(),
+        }
+    }
+
+    /// Set the number of rows requested from the server in each fetch round trip.
+    /// Only used by the pure rust client, as the native client manages the prefetch by itself.
+    /// Named cursors always fetch one row at a time. `0` uses the connection default
+    pub fn set_fetch_size(&mut self, fetch_size: u32) {
+        self.data.set_fetch_size(self.tr.conn, fetch_size)
+    }
+
+    /// Set the name of the cursor opened by `query`, allowing the current row
+    /// to be referenced by `UPDATE / DELETE ... WHERE CURRENT OF <cursor_name>` statements.
+    ///
+    /// The pure rust client fetches the rows of a named cursor one at a time,
+    /// ignoring the `set_fetch_size`
+    pub fn set_cursor_name(&mut self, cursor_name: &str) -> Result<(), FbError> {
+        self.data.set_cursor_name(self.tr.conn, cursor_name)
+    }
+
+    /// Set the timeout of the next executions of the statement, overriding the
+    /// connection default. `None` uses the connection default.
+    ///
+    /// The pure rust client sends the timeout with the executions, the native one sets
+    /// it in the session, only when different from the one of the last execution.
+    ///
+    /// An execution exceeding the timeout fails with `FbError::StatementTimeout`.
+    /// Requires Firebird 4+
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.data.timeout = timeout;
+    }
 }
 
 impl<C> Drop for Statement<'_, '_, C>
@@ -89,6 +131,22 @@ where
             .fetch(self.tr.conn, &mut self.tr.data)
             .and_then(|row| row.map(FromRow::try_from).transpose())
     }
+
+    /// Set the name of the cursor, allowing its current row in the server
+    /// to be referenced by `UPDATE / DELETE ... WHERE CURRENT OF <cursor_name>` statements.
+    ///
+    /// Must be called before the first `fetch`. The pure rust client then fetches the rows
+    /// one at a time, so the current row is the last fetched one, and returns an error if
+    /// rows were already fetched. The native client leaves the prefetch to the fbclient
+    pub fn set_cursor_name(&mut self, cursor_name: &str) -> Result<(), FbError> {
+        self.stmt.set_cursor_name(self.tr.conn, cursor_name)
+    }
+
+    /// The transaction of the cursor, can be used to execute
+    /// positioned updates / deletes while fetching the rows
+    pub fn transaction(&mut self) -> &mut Transaction<'c, C> {
+        self.tr
+    }
 }
 
 impl<T, C> Iterator for StatementFetch<'_, '_, T, C>
@@ -112,6 +170,53 @@ where
     }
 }
 
+/// Parameter sets collected to execute a statement in bulk
+pub struct StatementBatch<'c, 's, C: FirebirdClient> {
+    pub(crate) stmt: &'s mut StatementData<C>,
+    pub(crate) tr: &'s mut Transaction<'c, C>,
+    /// Parameter sets not yet executed
+    params: Vec<Vec<SqlType>>,
+}
+
+impl<'c, 's, C> StatementBatch<'c, 's, C>
+where
+    C: FirebirdClient,
+{
+    /// Add a parameter set to the batch
+    ///
+    /// Use `()` for no parameters or a tuple of parameters
+    pub fn add<T>(&mut self, params: T) -> Result<(), FbError>
+    where
+        T: IntoParams,
+    {
+        self.params.push(self.stmt.named_params.convert(params)?);
+
+        Ok(())
+    }
+
+    /// Number of parameter sets not yet executed
+    pub fn len(&self) -> usize {
+        self.params.len()
+    }
+
+    /// Returns `true` if there are no parameter sets to execute
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty()
+    }
+
+    /// Execute the statement with the parameter sets added, returning the completion
+    /// state of each one, in the same order they were added.
+    ///
+    /// A failed parameter set doesn't stop the execution of the others.
+    /// The batch is empty after the execution, and can be reused
+    pub fn execute(&mut self) -> Result<Vec<BatchRowState>, FbError> {
+        let params = std::mem::take(&mut self.params);
+
+        self.stmt
+            .execute_batch(self.tr.conn, &mut self.tr.data, params)
+    }
+}
+
 /// Low level statement handler.
 ///
 /// Needs to be closed calling `close` before dropping.
@@ -119,6 +224,10 @@ pub struct StatementData<C: FirebirdClient> {
     pub(crate) handle: C::StmtHandle,
     pub(crate) stmt_type: StmtType,
     named_params: NamedParams,
+    /// Statement timeout, overriding the connection default
+    pub(crate) timeout: Option<Duration>,
+    /// Rows requested in each fetch, overriding the connection default
+    fetch_size: Option<u32>,
 }
 
 impl<C: FirebirdClient> StatementData<C>
@@ -147,9 +256,45 @@ where
             stmt_type,
             handle,
             named_params,
+            timeout: None,
+            fetch_size: None,
         })
     }
 
+    /// Run the closure with the statement timeout applied.
+    ///
+    /// If the client supports it and `with_execute` (the closure executes the statement
+    /// with the `execute`/`execute2` of the client), the timeout is sent with the execution.
+    /// Else it is set in the session, which is only changed when the timeout differs
+    /// from the one of the last execution
+    fn with_timeout<T>(
+        &mut self,
+        conn: &mut Connection<C>,
+        tr: &mut TransactionData<C>,
+        with_execute: bool,
+        f: impl FnOnce(&mut Self, &mut Connection<C>, &mut TransactionData<C>) -> Result<T, FbError>,
+    ) -> Result<T, FbError> {
+        let send = with_execute && conn.cli.supports_statement_timeout();
+
+        // A zero timeout sent with the execution uses the one of the session,
+        // so the session timeout must be changed to disable it
+        let sent = self.timeout.filter(|t| send && !t.is_zero());
+        if send {
+            conn.cli.set_statement_timeout(&mut self.handle, sent);
+        }
+
+        let session_timeout = match sent {
+            Some(_) => conn.default_statement_timeout(),
+            None => self.timeout.or_else(|| conn.default_statement_timeout()),
+        };
+        conn.set_session_statement_timeout(&mut tr.handle, session_timeout)?;
+
+        // From now on the statement may be executed by the server
+        conn.executed = true;
+
+        f(self, conn, tr)
+    }
+
     /// Execute the current statement without returnig any row
     ///
     /// Use `()` for no parameters or a tuple of parameters
@@ -162,12 +307,12 @@ where
     where
         T: IntoParams,
     {
-        conn.cli.execute(
-            &mut conn.handle,
-            &mut tr.handle,
-            &mut self.handle,
-            self.named_params.convert(params)?,
-        )?;
+        let params = self.named_params.convert(params)?;
+
+        self.with_timeout(conn, tr, true, |stmt, conn, tr| {
+            conn.cli
+                .execute(&mut conn.handle, &mut tr.handle, &mut stmt.handle, params)
+        })?;
 
         if self.stmt_type == StmtType::Select {
             // Close the cursor, as it will not be used
@@ -177,6 +322,71 @@ where
         Ok(())
     }
 
+    /// Number of rows inserted, updated or deleted by the last execution of the statement
+    pub fn affected_rows(&mut self, conn: &mut Connection<C>) -> Result<u64, FbError> {
+        conn.cli.affected_rows(&mut self.handle)
+    }
+
+    /// Execute the current statement once for each of the parameter sets,
+    /// returning their completion states
+    ///
+    /// Without the batch api the parameter sets are executed one by one
+    pub fn execute_batch<I>(
+        &mut self,
+        conn: &mut Connection<C>,
+        tr: &mut TransactionData<C>,
+        params: I,
+    ) -> Result<Vec<BatchRowState>, FbError>
+    where
+        I: IntoIterator,
+        I::Item: IntoParams,
+    {
+        let params: Vec<Vec<SqlType>> = params
+            .into_iter()
+            .map(|p| self.named_params.convert(p))
+            .collect::<Result<_, _>>()?;
+
+        if !conn.cli.supports_batch() {
+            return self.execute_each(conn, tr, params);
+        }
+
+        self.with_timeout(conn, tr, false, |stmt, conn, tr| {
+            conn.cli
+                .execute_batch(&mut conn.handle, &mut tr.handle, &mut stmt.handle, params)
+        })
+    }
+
+    /// Fallback of the `execute_batch` for the clients without the batch api,
+    /// returning the same completion states.
+    ///
+    /// A failed parameter set doesn't stop the execution of the others,
+    /// unless the connection is broken
+    fn execute_each(
+        &mut self,
+        conn: &mut Connection<C>,
+        tr: &mut TransactionData<C>,
+        params: Vec<Vec<SqlType>>,
+    ) -> Result<Vec<BatchRowState>, FbError> {
+        let mut states = Vec::with_capacity(params.len());
+
+        for params in params {
+            let res = self
+                .with_timeout(conn, tr, true, |stmt, conn, tr| {
+                    conn.cli
+                        .execute(&mut conn.handle, &mut tr.handle, &mut stmt.handle, params)
+                })
+                .and_then(|_| conn.cli.affected_rows(&mut self.handle));
+
+            match res {
+                Ok(rows) => states.push(BatchRowState::Success(u32::try_from(rows).ok())),
+                Err(e) if conn.cli.is_broken() => return Err(e),
+                Err(e) => states.push(BatchRowState::Failed(Some(e))),
+            }
+        }
+
+        Ok(states)
+    }
+
     /// Execute the current statement with input and returns a single row
     ///
     /// Use `()` for no parameters or a tuple of parameters
@@ -189,12 +399,12 @@ where
     where
         T: IntoParams,
     {
-        conn.cli.execute2(
-            &mut conn.handle,
-            &mut tr.handle,
-            &mut self.handle,
-            self.named_params.convert(params)?,
-        )
+        let params = self.named_params.convert(params)?;
+
+        self.with_timeout(conn, tr, true, |stmt, conn, tr| {
+            conn.cli
+                .execute2(&mut conn.handle, &mut tr.handle, &mut stmt.handle, params)
+        })
     }
 
     /// Execute the current statement
@@ -210,12 +420,16 @@ where
     where
         T: IntoParams,
     {
-        conn.cli.execute(
-            &mut conn.handle,
-            &mut tr.handle,
-            &mut self.handle,
-            self.named_params.convert(params)?,
-        )
+        let params = self.named_params.convert(params)?;
+
+        // `0` is the size of the connection configuration
+        let fetch_size = self.fetch_size.or(conn.scoped_fetch_size).unwrap_or(0);
+        conn.cli.set_fetch_size(&mut self.handle, fetch_size);
+
+        self.with_timeout(conn, tr, true, |stmt, conn, tr| {
+            conn.cli
+                .execute(&mut conn.handle, &mut tr.handle, &mut stmt.handle, params)
+        })
     }
 
     /// Fetch for the next row, needs to be called after `query`
@@ -228,6 +442,21 @@ where
             .fetch(&mut conn.handle, &mut tr.handle, &mut self.handle)
     }
 
+    /// Set the name of the statement cursor
+    pub fn set_cursor_name(
+        &mut self,
+        conn: &mut Connection<C>,
+        cursor_name: &str,
+    ) -> Result<(), FbError> {
+        conn.cli.set_cursor_name(&mut self.handle, cursor_name)
+    }
+
+    /// Set the number of rows requested from the server in each fetch
+    pub fn set_fetch_size(&mut self, conn: &mut Connection<C>, fetch_size: u32) {
+        self.fetch_size = Some(fetch_size);
+        conn.cli.set_fetch_size(&mut self.handle, fetch_size)
+    }
+
     /// Closes the statement cursor, if it was open
     pub fn close_cursor(&mut self, conn: &mut Connection<C>) -> Result<(), FbError> {
         conn.cli.free_statement(&mut self.handle, FreeStmtOp::Close)
@@ -245,7 +474,7 @@ static TABLE_COUNTER: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU3
 
 #[cfg(test)]
 mk_tests_default! {
-    use crate::{prelude::*, Connection, Row};
+    use crate::{prelude::*, BatchRowState, Connection, ExecuteManyOptions, FbError, Row};
     use rsfbclient_core::FirebirdClient;
 
     #[test]
@@ -401,6 +630,207 @@ mk_tests_default! {
         conn.close().expect("error on close the connection");
     }
 
+    #[test]
+    fn fetch_size() {
+        let (mut conn, table) = setup();
+
+        let vals: Vec<(i32, String)> = (0..7).map(|i| (i, format!("product {}", i))).collect();
+
+        conn.with_transaction(|tr| {
+            for val in &vals {
+                tr.execute(&format!("insert into {} (id, name) values (?, ?)", table), val.clone())
+                    .expect("Error on insert");
+            }
+
+            Ok(())
+        })
+        .expect("Error commiting the transaction");
+
+        conn.with_transaction(|tr| {
+            let mut stmt = tr
+                .prepare(&format!("select id, name from {} order by id", table), false)
+                .expect("Error on prepare the select");
+
+            // Multiple batches, the last one incomplete
+            stmt.set_fetch_size(3);
+
+            let rows: Vec<(i32, String)> = stmt
+                .query(())
+                .expect("Error on query")
+                .collect::<Result<_, _>>()
+                .expect("Error on fetch");
+
+            assert_eq!(vals, rows);
+
+            // The rows of the previous execution must not leak into the next one
+            let mut rows = stmt.query(()).expect("Error on query");
+            let row: Option<(i32, String)> = rows.fetch().expect("Error on fetch");
+            assert_eq!(Some(vals[0].clone()), row);
+
+            Ok(())
+        })
+        .expect("Error commiting the transaction");
+
+        let sql = format!("select id, name from {} order by id", table);
+
+        let rows: Vec<(i32, String)> = conn
+            .with_fetch_size(2, |conn| conn.query(&sql, ()))
+            .expect("Error on query");
+        assert_eq!(vals, rows);
+
+        // The cached statement is back to the connection default
+        let rows: Vec<(i32, String)> = conn.query(&sql, ()).expect("Error on query");
+        assert_eq!(vals, rows);
+    }
+
+    #[test]
+    fn positioned_update() {
+        let (mut conn, table) = setup();
+
+        conn.with_transaction(|tr| {
+            for val in &[(1, "apple"), (2, "coffee")] {
+                tr.execute(&format!("insert into {} (id, name) values (?, ?)", table), *val)
+                    .expect("Error on insert");
+            }
+
+            Ok(())
+        })
+        .expect("Error commiting the transaction");
+
+        let cursor = format!("cur_{}", table);
+
+        conn.with_transaction(|tr| {
+            let mut stmt = tr
+                .prepare(&format!("select id from {} for update", table), false)
+                .expect("Error on prepare the select");
+
+            let mut rows = stmt.query(()).expect("Error on query");
+            rows.set_cursor_name(&cursor).expect("Error setting the cursor name");
+
+            while let Some((id,)) = rows.fetch().expect("Error on fetch the next row") {
+                let id: i32 = id;
+
+                rows.transaction()
+                    .execute(&format!("update {} set quantity = ? where current of {}", table, cursor), (id * 10,))
+                    .expect("Error on positioned update");
+            }
+
+            Ok(())
+        })
+        .expect("Error commiting the transaction");
+
+        let rows: Vec<(i32, i32)> = conn
+            .query(&format!("select id, quantity from {} order by id", table), ())
+            .expect("Error executing query");
+
+        assert_eq!(vec![(1, 10), (2, 20)], rows);
+    }
+
+    #[test]
+    fn batch_insert() {
+        let (mut conn, table) = setup();
+
+        let too_long = "x".repeat(100);
+
+        let states = conn.with_transaction(|tr| {
+            let mut stmt = tr
+                .prepare(&format!("insert into {} (id, name) values (?, ?)", table), false)
+                .expect("Error preparing the insert statement");
+
+            let mut batch = stmt.batch();
+            batch.add((1, "apple")).expect("Error adding to the batch");
+            // Larger than the column, must fail without affecting the others
+            batch.add((2, too_long.as_str())).expect("Error adding to the batch");
+            batch.add((3, None::<String>)).expect("Error adding to the batch");
+            assert_eq!(3, batch.len());
+
+            let states = batch.execute().expect("Error executing the batch");
+            assert!(batch.is_empty());
+
+            Ok(states)
+        })
+        .expect("Error commiting the transaction");
+
+        assert_eq!(3, states.len());
+        assert!(states[0].is_success());
+        assert!(matches!(states[1], BatchRowState::Failed(_)));
+        assert!(states[2].is_success());
+
+        let states = conn
+            .execute_batch(
+                &format!("insert into {} (id, name) values (?, ?)", table),
+                (4..6).map(|i| (i, format!("product {}", i))),
+            )
+            .expect("Error executing the batch");
+
+        assert!(states.iter().all(BatchRowState::is_success));
+
+        let rows: Vec<(i32, Option<String>)> = conn
+            .query(&format!("select id, name from {} order by id", table), ())
+            .expect("Error executing query");
+
+        assert_eq!(
+            vec![
+                (1, Some("apple".to_string())),
+                (3, None),
+                (4, Some("product 4".to_string())),
+                (5, Some("product 5".to_string())),
+            ],
+            rows
+        );
+    }
+
+    #[test]
+    fn execute_many() {
+        let (mut conn, table) = setup();
+
+        let too_long = "x".repeat(100);
+        let insert = format!("insert into {} (id, name) values (?, ?)", table);
+
+        let res = conn
+            .execute_many_with(
+                &insert,
+                vec![(1, "apple"), (2, "coffee"), (3, too_long.as_str()), (4, "tea")],
+                ExecuteManyOptions::default().commit_every(2),
+            )
+            .expect("Error executing the statements");
+
+        assert_eq!(2, res.executed);
+        assert_eq!(2, res.committed);
+        assert_eq!(Some(2), res.affected_rows);
+        assert!(matches!(res.failed, Some((2, FbError::Sql { .. }))));
+
+        let res = conn.with_transaction(|tr| {
+            tr.execute_many(
+                &format!("update {} set quantity = ? where id <= ?", table),
+                vec![(10, 1), (20, 2)],
+            )
+        })
+        .expect("Error executing the statements");
+
+        assert_eq!(2, res.executed);
+        assert_eq!(Some(3), res.affected_rows);
+        assert!(res.failed.is_none());
+
+        let res = conn
+            .execute_many_with(
+                &format!("update {} set quantity = quantity where id = ?", table),
+                vec![(1,)],
+                ExecuteManyOptions::default().count_affected_rows(false),
+            )
+            .expect("Error executing the statements");
+
+        assert_eq!(1, res.executed);
+        assert_eq!(None, res.affected_rows);
+        assert!(res.failed.is_none());
+
+        let rows: Vec<(i32, Option<i32>)> = conn
+            .query(&format!("select id, quantity from {} order by id", table), ())
+            .expect("Error executing query");
+
+        assert_eq!(vec![(1, Some(20)), (2, Some(20))], rows);
+    }
+
     // #[test]
     // fn immediate_insert() {
     //     let (mut conn, table) = setup();
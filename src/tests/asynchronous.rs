@@ -0,0 +1,115 @@
+//!
+//! Rust Firebird Client
+//!
+//! Async connection tests
+//!
+
+#[cfg(feature = "async-tokio")]
+mod pure_rust {
+    use crate::{prelude::*, FbError};
+    use futures_util::StreamExt;
+
+    #[tokio::test]
+    async fn query_stream() -> Result<(), FbError> {
+        let mut conn = crate::builder_pure_rust().connect_async().await?;
+
+        let mut rows = conn
+            .query_stream("SELECT -3 FROM RDB$DATABASE WHERE 1 = ?", (1,))
+            .await?;
+
+        let mut count = 0;
+        while let Some(row) = rows.next().await {
+            let (v,): (i32,) = row?;
+            assert_eq!(v, -3);
+
+            count += 1;
+        }
+        assert_eq!(count, 1);
+
+        drop(rows);
+
+        conn.close().await
+    }
+
+    #[tokio::test]
+    async fn stream_dropped_early() -> Result<(), FbError> {
+        let mut conn = crate::builder_pure_rust().connect_async().await?;
+
+        {
+            let mut rows = conn
+                .query_stream::<_, (i32,)>("SELECT 1 FROM RDB$RELATIONS", ())
+                .await?;
+            rows.next().await.transpose()?;
+        }
+
+        // The cursor is released before this query
+        let row: Option<(i32,)> = conn.query_first("SELECT 2 FROM RDB$DATABASE", ()).await?;
+        assert_eq!(row, Some((2,)));
+
+        conn.close().await
+    }
+
+    #[tokio::test]
+    async fn transaction() -> Result<(), FbError> {
+        let mut conn = crate::builder_pure_rust().connect_async().await?;
+
+        conn.execute(
+            "recreate table rsfbclient_test_async (id int, description varchar(20))",
+            (),
+        )
+        .await?;
+
+        let mut tr = conn.begin_transaction().await?;
+        tr.execute(
+            "insert into rsfbclient_test_async (id, description) values (?, ?)",
+            (1, "one"),
+        )
+        .await?;
+        tr.commit().await?;
+
+        let mut tr = conn.begin_transaction().await?;
+        tr.execute(
+            "insert into rsfbclient_test_async (id, description) values (?, ?)",
+            (2, "two"),
+        )
+        .await?;
+        // Rolled back
+        drop(tr);
+
+        let rows: Vec<(i32, String)> = conn
+            .query("select id, description from rsfbclient_test_async", ())
+            .await?;
+        assert_eq!(rows, vec![(1, "one".to_string())]);
+
+        let (id,): (i32,) = conn
+            .execute_returnable(
+                "insert into rsfbclient_test_async (id) values (3) returning id",
+                (),
+            )
+            .await?;
+        assert_eq!(id, 3);
+
+        conn.execute("drop table rsfbclient_test_async", ()).await?;
+
+        conn.close().await
+    }
+
+    #[tokio::test]
+    async fn spawned() -> Result<(), FbError> {
+        let mut conn = crate::builder_pure_rust().connect_async().await?;
+
+        // The connection can be moved between tasks
+        let (v,): (i32,) = tokio::spawn(async move {
+            let row = conn.query_first("SELECT 7 FROM RDB$DATABASE", ()).await;
+            conn.close().await?;
+            row
+        })
+        .await
+        .unwrap()?
+        .unwrap();
+
+        assert_eq!(v, 7);
+
+        Ok(())
+    }
+}
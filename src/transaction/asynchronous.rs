@@ -0,0 +1,242 @@
+//!
+//! Rust Firebird Client
+//!
+//! Async transaction functions
+//!
+
+use rsfbclient_core::{
+    Column, FbError, FirebirdClientAsync, FreeStmtOp, FromRow, IntoParams, NamedParams,
+    TrIsolationLevel, TrOp,
+};
+
+use crate::{
+    connection::asynchronous::{CursorTr, PendingOp, RowCursor},
+    query::{AsyncExecute, AsyncQueryable, RowStream},
+    AsyncConnection,
+};
+
+/// A transaction of an `AsyncConnection`. Dropping it without
+/// calling `commit` rolls back the changes
+pub struct AsyncTransaction<'c, C: FirebirdClientAsync> {
+    pub(crate) conn: &'c mut AsyncConnection<C>,
+
+    /// Transaction handle, `None` after it was finished
+    handle: Option<C::TrHandle>,
+}
+
+impl<'c, C: FirebirdClientAsync> AsyncTransaction<'c, C> {
+    /// Start a new transaction
+    pub async fn new(conn: &'c mut AsyncConnection<C>) -> Result<AsyncTransaction<'c, C>, FbError> {
+        conn.run_pending().await;
+
+        let handle = conn
+            .cli
+            .begin_transaction(&mut conn.handle, TrIsolationLevel::ReadCommited)
+            .await?;
+
+        Ok(AsyncTransaction {
+            conn,
+            handle: Some(handle),
+        })
+    }
+
+    /// Connection and transaction handle, for the client calls
+    fn parts(&mut self) -> Result<(&mut AsyncConnection<C>, &mut C::TrHandle), FbError> {
+        match &mut self.handle {
+            Some(handle) => Ok((&mut *self.conn, handle)),
+            None => Err("Transaction already finished".into()),
+        }
+    }
+
+    /// Commit the current transaction changes. If the commit
+    /// fails, the transaction is rolled back
+    pub async fn commit(mut self) -> Result<(), FbError> {
+        self.commit_in_place().await
+    }
+
+    /// Commit the transaction, leaving it finished
+    pub(crate) async fn commit_in_place(&mut self) -> Result<(), FbError> {
+        let (conn, handle) = self.parts()?;
+
+        conn.cli.transaction_operation(handle, TrOp::Commit).await?;
+        self.handle = None;
+
+        Ok(())
+    }
+
+    /// Commit the current transaction changes, but allowing to reuse the transaction
+    pub async fn commit_retaining(&mut self) -> Result<(), FbError> {
+        let (conn, handle) = self.parts()?;
+
+        conn.cli
+            .transaction_operation(handle, TrOp::CommitRetaining)
+            .await
+    }
+
+    /// Rollback the current transaction changes, but allowing to reuse the transaction
+    pub async fn rollback_retaining(&mut self) -> Result<(), FbError> {
+        let (conn, handle) = self.parts()?;
+
+        conn.cli
+            .transaction_operation(handle, TrOp::RollbackRetaining)
+            .await
+    }
+
+    /// Rollback the current transaction changes
+    pub async fn rollback(mut self) -> Result<(), FbError> {
+        let (conn, handle) = self.parts()?;
+
+        let result = conn.cli.transaction_operation(handle, TrOp::Rollback).await;
+        self.handle = None;
+
+        result
+    }
+
+    /// Execute the statement without returning any row
+    pub async fn execute_immediate(&mut self, sql: &str) -> Result<(), FbError> {
+        let (conn, handle) = self.parts()?;
+
+        conn.run_pending().await;
+
+        conn.cli
+            .exec_immediate(&mut conn.handle, handle, conn.dialect, sql)
+            .await
+    }
+
+    /// Finish the transaction in the next operation of the connection
+    pub(crate) fn defer(&mut self, op: fn(C::TrHandle) -> PendingOp<C>) {
+        if let Some(handle) = self.handle.take() {
+            self.conn.pending.push(op(handle));
+        }
+    }
+
+    /// Prepare the statement, converting the named parameters
+    async fn prepare(
+        &mut self,
+        sql: &str,
+        named_params: bool,
+    ) -> Result<(NamedParams, C::StmtHandle), FbError> {
+        let named_params = if named_params {
+            NamedParams::parse(sql)?
+        } else {
+            NamedParams::empty(sql)
+        };
+
+        let (conn, handle) = self.parts()?;
+
+        conn.run_pending().await;
+
+        let (_, stmt) = conn
+            .cli
+            .prepare_statement(&mut conn.handle, handle, conn.dialect, &named_params.sql)
+            .await?;
+
+        Ok((named_params, stmt))
+    }
+
+    /// Prepare and execute the statement, returning it with the cursor open
+    pub(crate) async fn query<P: IntoParams>(
+        &mut self,
+        sql: &str,
+        params: P,
+    ) -> Result<C::StmtHandle, FbError> {
+        let params = params.to_params();
+        let (named_params, mut stmt) = self.prepare(sql, params.named()).await?;
+
+        let res = match named_params.convert(params) {
+            Ok(params) => {
+                let (conn, handle) = self.parts()?;
+
+                conn.cli
+                    .execute(&mut conn.handle, handle, &mut stmt, params)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+
+        match res {
+            Ok(()) => Ok(stmt),
+            Err(e) => {
+                self.free_statement(&mut stmt).await.ok();
+                Err(e)
+            }
+        }
+    }
+
+    /// Fetch the next row of a statement executed by `query`
+    pub(crate) async fn fetch(
+        &mut self,
+        stmt: &mut C::StmtHandle,
+    ) -> Result<Option<Vec<Column>>, FbError> {
+        let (conn, handle) = self.parts()?;
+
+        conn.cli.fetch(&mut conn.handle, handle, stmt).await
+    }
+
+    /// Drop the statement
+    pub(crate) async fn free_statement(&mut self, stmt: &mut C::StmtHandle) -> Result<(), FbError> {
+        self.conn.cli.free_statement(stmt, FreeStmtOp::Drop).await
+    }
+}
+
+impl<C: FirebirdClientAsync> Drop for AsyncTransaction<'_, C> {
+    fn drop(&mut self) {
+        self.defer(PendingOp::Rollback);
+    }
+}
+
+#[async_trait::async_trait]
+impl<'c, C: FirebirdClientAsync> AsyncQueryable for AsyncTransaction<'c, C> {
+    async fn query_stream<'a, P, R>(
+        &'a mut self,
+        sql: &str,
+        params: P,
+    ) -> Result<RowStream<'a, R>, FbError>
+    where
+        P: IntoParams + Send,
+        R: FromRow + Send + 'static,
+    {
+        RowCursor::open(CursorTr::Borrowed(self), sql, params).await
+    }
+}
+
+#[async_trait::async_trait]
+impl<'c, C: FirebirdClientAsync> AsyncExecute for AsyncTransaction<'c, C> {
+    async fn execute<P>(&mut self, sql: &str, params: P) -> Result<(), FbError>
+    where
+        P: IntoParams + Send,
+    {
+        let mut stmt = self.query(sql, params).await?;
+
+        // The cursor is not used, so just drop the statement
+        self.free_statement(&mut stmt).await
+    }
+
+    async fn execute_returnable<P, R>(&mut self, sql: &str, params: P) -> Result<R, FbError>
+    where
+        P: IntoParams + Send,
+        R: FromRow + Send + 'static,
+    {
+        let params = params.to_params();
+        let (named_params, mut stmt) = self.prepare(sql, params.named()).await?;
+
+        // Do not return now in case of error, because we need to drop the statement
+        let res = match named_params.convert(params) {
+            Ok(params) => {
+                let (conn, handle) = self.parts()?;
+
+                conn.cli
+                    .execute2(&mut conn.handle, handle, &mut stmt, params)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+
+        let closed = self.free_statement(&mut stmt).await;
+
+        let row = FromRow::try_from(res?)?;
+        closed?;
+
+        Ok(row)
+    }
+}
@@ -4,9 +4,12 @@
 //! Transaction functions
 //!
 
-use rsfbclient_core::{FbError, FirebirdClient, FromRow, IntoParams, TrIsolationLevel, TrOp};
+use rsfbclient_core::{
+    FbError, FirebirdClient, FromRow, IntoParams, ParamsType, TrIsolationLevel, TrOp,
+};
 use std::marker;
 use std::mem;
+use std::time::Duration;
 
 use super::{connection::Connection, statement::Statement};
 use crate::{
@@ -14,9 +17,16 @@ use crate::{
     statement::StatementData, Execute, Queryable,
 };
 
+pub(crate) mod retry;
 mod simple;
+pub use retry::TransactionRetryPolicy;
 pub use simple::SimpleTransaction;
 
+#[cfg(feature = "async-tokio")]
+mod asynchronous;
+#[cfg(feature = "async-tokio")]
+pub use asynchronous::AsyncTransaction;
+
 pub struct Transaction<'c, C>
 where
     C: FirebirdClient,
@@ -28,6 +38,8 @@ where
 impl<'c, C: FirebirdClient> Transaction<'c, C> {
     /// Start a new transaction
     pub fn new(conn: &'c mut Connection<C>) -> Result<Self, FbError> {
+        conn.reconnect_if_broken()?;
+
         let data = TransactionData::new(conn)?;
 
         Ok(Transaction { data, conn })
@@ -68,6 +80,117 @@ impl<'c, C: FirebirdClient> Transaction<'c, C> {
         self.data.execute_immediate(self.conn, sql)
     }
 
+    /// Execute the statement once for each of the parameter sets, in this transaction.
+    ///
+    /// The statement is prepared only once. The execution stops at the first
+    /// parameter set that fails, with any error, which is reported in the result
+    /// with its index
+    pub fn execute_many<P, I>(&mut self, sql: &str, params: I) -> Result<ExecuteManyResult, FbError>
+    where
+        I: IntoIterator<Item = P>,
+        P: IntoParams,
+    {
+        self.execute_many_with(sql, params, ExecuteManyOptions::default())
+    }
+
+    /// Like `execute_many`, with the options. The `commit_every`
+    /// commits (retaining) the changes in this transaction
+    pub fn execute_many_with<P, I>(
+        &mut self,
+        sql: &str,
+        params: I,
+        options: ExecuteManyOptions,
+    ) -> Result<ExecuteManyResult, FbError>
+    where
+        I: IntoIterator<Item = P>,
+        P: IntoParams,
+    {
+        let mut params = params.into_iter().map(IntoParams::to_params).peekable();
+        let named = params.peek().map(ParamsType::named).unwrap_or(false);
+
+        // Get a statement from the cache
+        let mut stmt_cache_data = StmtCache::get_or_prepare(self, sql, named)?;
+
+        let mut result = ExecuteManyResult {
+            affected_rows: options.count_affected_rows.then_some(0),
+            ..Default::default()
+        };
+
+        for (index, params) in params.enumerate() {
+            let stmt = &mut stmt_cache_data.stmt;
+
+            // Any error stops the execution, but is reported with the index, so
+            // the caller can resume after the parameter sets already committed
+            let res = (|| {
+                stmt.execute(self.conn, &mut self.data, params)?;
+                result.executed += 1;
+
+                if let Some(affected_rows) = &mut result.affected_rows {
+                    *affected_rows += stmt.affected_rows(self.conn)?;
+                }
+
+                // `None` if `commit_every` is 0
+                if result.executed.checked_rem(options.commit_every) == Some(0) {
+                    self.data.commit_retaining(self.conn)?;
+                    result.committed = result.executed;
+                }
+
+                Ok(())
+            })();
+
+            if let Err(e) = res {
+                result.failed = Some((index, e));
+                break;
+            }
+        }
+
+        // Return the statement to the cache. The error is ignored if one was already
+        // reported, as the connection may be broken and the result must not be lost
+        let closed = StmtCache::insert_and_close(self.conn, stmt_cache_data);
+        if result.failed.is_none() {
+            closed?;
+        }
+
+        Ok(result)
+    }
+
+    /// Run a closure with a different statement timeout for the statements
+    /// executed by it, restoring the connection default at the end.
+    ///
+    /// A statement exceeding the timeout fails with `FbError::StatementTimeout`.
+    /// Requires Firebird 4+
+    pub fn with_statement_timeout<T, F>(
+        &mut self,
+        timeout: Duration,
+        closure: F,
+    ) -> Result<T, FbError>
+    where
+        F: FnOnce(&mut Self) -> Result<T, FbError>,
+    {
+        let previous = self.conn.scoped_statement_timeout.replace(timeout);
+
+        let res = match self.apply_statement_timeout() {
+            Ok(()) => closure(self),
+            Err(e) => Err(e),
+        };
+
+        self.conn.scoped_statement_timeout = previous;
+        let restored = self.apply_statement_timeout();
+
+        let value = res?;
+        restored?;
+
+        Ok(value)
+    }
+
+    /// Set the default statement timeout of the connection in the session
+    fn apply_statement_timeout(&mut self) -> Result<(), FbError> {
+        let timeout = self.conn.default_statement_timeout();
+
+        self.conn
+            .set_session_statement_timeout(&mut self.data.handle, timeout)
+    }
+
     /// Prepare a new statement for execute
     pub fn prepare<'t>(
         &'t mut self,
@@ -217,6 +340,56 @@ impl<C: FirebirdClient> Execute for Transaction<'_, C> {
     }
 }
 
+#[derive(Debug, Clone, Copy)]
+/// Options of the `execute_many_with` methods
+pub struct ExecuteManyOptions {
+    /// Commit the changes every `commit_every` parameter sets. Default: 0, only at the end
+    pub commit_every: usize,
+    /// Count the rows inserted, updated or deleted, in the `affected_rows` of the result.
+    /// Requires a round trip to the server after each execution. Default: true
+    pub count_affected_rows: bool,
+}
+
+impl Default for ExecuteManyOptions {
+    fn default() -> Self {
+        Self {
+            commit_every: 0,
+            count_affected_rows: true,
+        }
+    }
+}
+
+impl ExecuteManyOptions {
+    /// Commit the changes every `commit_every` parameter sets. Use `0` to commit only at the end
+    pub fn commit_every(mut self, commit_every: usize) -> Self {
+        self.commit_every = commit_every;
+        self
+    }
+
+    /// Count the rows inserted, updated or deleted by the executions.
+    /// Disable to save a round trip to the server after each execution
+    pub fn count_affected_rows(mut self, count_affected_rows: bool) -> Self {
+        self.count_affected_rows = count_affected_rows;
+        self
+    }
+}
+
+#[derive(Debug, Default)]
+/// Result of the `execute_many` family of methods
+pub struct ExecuteManyResult {
+    /// Number of parameter sets executed successfully
+    pub executed: usize,
+    /// Number of parameter sets executed whose changes were committed,
+    /// the execution can be resumed from this index after a failure
+    pub committed: usize,
+    /// Total of rows inserted, updated or deleted by the successful executions,
+    /// `None` if disabled by the `ExecuteManyOptions::count_affected_rows`
+    pub affected_rows: Option<u64>,
+    /// Index and error of the parameter set that failed, if any.
+    /// The parameter sets after it were not executed
+    pub failed: Option<(usize, FbError)>,
+}
+
 #[derive(Debug)]
 /// Low level transaction handler.
 ///
@@ -235,6 +408,8 @@ where
             .cli
             .begin_transaction(&mut conn.handle, TrIsolationLevel::ReadCommited)?;
 
+        conn.open_transactions.push(handle.clone());
+
         Ok(Self { handle })
     }
 
@@ -246,8 +421,7 @@ where
 
     /// Commit the current transaction changes, not allowing to reuse the transaction
     pub fn commit(&mut self, conn: &mut Connection<C>) -> Result<(), FbError> {
-        conn.cli
-            .transaction_operation(&mut self.handle, TrOp::Commit)
+        self.close(conn, TrOp::Commit)
     }
 
     /// Commit the current transaction changes, but allowing to reuse the transaction
@@ -264,7 +438,18 @@ where
 
     /// Rollback the transaction, invalidating it
     pub fn rollback(&mut self, conn: &mut Connection<C>) -> Result<(), FbError> {
-        conn.cli
-            .transaction_operation(&mut self.handle, TrOp::Rollback)
+        self.close(conn, TrOp::Rollback)
+    }
+
+    /// Commit or rollback, removing the transaction from the open ones of the connection
+    fn close(&mut self, conn: &mut Connection<C>, op: TrOp) -> Result<(), FbError> {
+        // The native client zeroes the handle when the transaction ends
+        let handle = self.handle.clone();
+
+        conn.cli.transaction_operation(&mut self.handle, op)?;
+
+        conn.open_transactions.retain(|open| *open != handle);
+
+        Ok(())
     }
 }
@@ -0,0 +1,228 @@
+//!
+//! Rust Firebird Client
+//!
+//! Retry policy for transactions that failed due to concurrent updates
+//!
+
+use rsfbclient_core::FbError;
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    thread,
+    time::Duration,
+};
+
+/// Sql code returned by the server for deadlocks and update conflicts
+const SQLCODE_DEADLOCK: i32 = -913;
+
+/// Error messages that indicate a conflict with a concurrent transaction.
+/// Used because the `lock conflict on no wait transaction` error reports the generic -901 sql code
+const CONFLICT_MESSAGES: &[&str] = &[
+    "deadlock",
+    "lock conflict on no wait transaction",
+    "update conflicts with concurrent update",
+];
+
+/// Configuration of the `with_transaction_retry` methods
+///
+/// The transaction is retried when the closure fails with a lock conflict,
+/// a deadlock or an update conflict. Between the attempts, the thread sleeps
+/// for an exponentially increasing amount of time, with an optional random jitter.
+#[derive(Debug, Clone, Copy)]
+pub struct TransactionRetryPolicy {
+    /// Maximum number of times the closure will be run, including the first one. Default: 5
+    pub max_attempts: u32,
+    /// Backoff used after the first failed attempt. Default: 10ms
+    pub initial_backoff: Duration,
+    /// Upper limit for the backoff. Default: 1s
+    pub max_backoff: Duration,
+    /// Factor applied to the backoff after each failed attempt. Default: 2
+    pub multiplier: u32,
+    /// Randomize the backoff between 50% and 100% of its value,
+    /// to avoid the conflicting transactions running in lockstep. Default: true
+    pub jitter: bool,
+}
+
+impl Default for TransactionRetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+            multiplier: 2,
+            jitter: true,
+        }
+    }
+}
+
+impl TransactionRetryPolicy {
+    /// Maximum number of times the closure will be run, including the first one
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Backoff used after the first failed attempt
+    pub fn initial_backoff(mut self, initial_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    /// Upper limit for the backoff
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Factor applied to the backoff after each failed attempt
+    pub fn multiplier(mut self, multiplier: u32) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Enable or disable the random jitter on the backoff
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Returns `true` if the error was caused by a concurrent transaction,
+    /// so running the transaction again may succeed
+    pub fn is_retryable(err: &FbError) -> bool {
+        match err {
+            FbError::Sql { code, msg } => {
+                *code == SQLCODE_DEADLOCK || CONFLICT_MESSAGES.iter().any(|m| msg.contains(m))
+            }
+            _ => false,
+        }
+    }
+
+    /// Backoff to wait after the failed attempt number `attempt` (starting from 1), without jitter
+    pub(crate) fn backoff(&self, attempt: u32) -> Duration {
+        exponential_backoff(
+            self.initial_backoff,
+            self.max_backoff,
+            self.multiplier,
+            attempt,
+        )
+    }
+
+    /// Run the closure until it succeeds, returns a non retryable error
+    /// or the maximum number of attempts is reached
+    pub(crate) fn run<T, F>(&self, mut f: F) -> Result<T, FbError>
+    where
+        F: FnMut() -> Result<T, FbError>,
+    {
+        let mut attempt = 1;
+
+        loop {
+            match f() {
+                Err(e) if attempt < self.max_attempts && Self::is_retryable(&e) => {
+                    let backoff = self.backoff(attempt);
+
+                    thread::sleep(if self.jitter {
+                        jitter(backoff)
+                    } else {
+                        backoff
+                    });
+
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+/// Backoff after the failed attempt number `attempt` (starting from 1),
+/// multiplied after each attempt up to `max`
+pub(crate) fn exponential_backoff(
+    initial: Duration,
+    max: Duration,
+    multiplier: u32,
+    attempt: u32,
+) -> Duration {
+    let mut backoff = initial;
+
+    for _ in 1..attempt {
+        backoff = backoff.checked_mul(multiplier).unwrap_or(max);
+
+        if backoff >= max {
+            break;
+        }
+    }
+
+    backoff.min(max)
+}
+
+/// Randomize the duration between 50% and 100% of its value
+pub(crate) fn jitter(backoff: Duration) -> Duration {
+    // A new `RandomState` is randomly seeded, so no need for a rng crate
+    let rand = RandomState::new().build_hasher().finish();
+    let half = backoff / 2;
+
+    half + Duration::from_nanos(rand % (half.as_nanos() as u64 + 1))
+}
+
+#[test]
+fn retry_policy_backoff_test() {
+    let policy = TransactionRetryPolicy::default()
+        .initial_backoff(Duration::from_millis(10))
+        .max_backoff(Duration::from_millis(50))
+        .multiplier(2);
+
+    assert_eq!(policy.backoff(1), Duration::from_millis(10));
+    assert_eq!(policy.backoff(2), Duration::from_millis(20));
+    assert_eq!(policy.backoff(3), Duration::from_millis(40));
+    assert_eq!(policy.backoff(4), Duration::from_millis(50));
+    assert_eq!(policy.backoff(100), Duration::from_millis(50));
+
+    for _ in 0..100 {
+        let j = jitter(Duration::from_millis(40));
+        assert!(j >= Duration::from_millis(20) && j <= Duration::from_millis(40));
+    }
+}
+
+#[test]
+fn retry_policy_run_test() {
+    let policy = TransactionRetryPolicy::default()
+        .max_attempts(3)
+        .initial_backoff(Duration::from_millis(1))
+        .jitter(false);
+
+    let conflict = || FbError::Sql {
+        code: -901,
+        msg: "lock conflict on no wait transaction\nupdate conflicts with concurrent update"
+            .to_string(),
+    };
+
+    // Succeeds in the last attempt
+    let mut calls = 0;
+    let res = policy.run(|| {
+        calls += 1;
+        if calls < 3 {
+            Err(conflict())
+        } else {
+            Ok(calls)
+        }
+    });
+    assert_eq!(res.unwrap(), 3);
+
+    // Gives up after the max attempts
+    let mut calls = 0;
+    let res: Result<(), _> = policy.run(|| {
+        calls += 1;
+        Err(conflict())
+    });
+    assert!(res.is_err());
+    assert_eq!(calls, 3);
+
+    // Other errors are not retried
+    let mut calls = 0;
+    let res: Result<(), _> = policy.run(|| {
+        calls += 1;
+        Err(FbError::from("other error"))
+    });
+    assert!(res.is_err());
+    assert_eq!(calls, 1);
+}